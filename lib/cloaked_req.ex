@@ -4,27 +4,147 @@ defmodule CloakedReq do
 
   - `attach/2` — set adapter and merge options
   - `impersonate/2` — set browser profile
+  - `configure_client_cache/1`, `evict_client/1`, `clear_clients/0` — manage
+    cached clients
+  - `client_stats/0` — inspect cached clients and their connections
+  - `preconnect/2` — open connections ahead of the first request
   """
 
   alias CloakedReq.AdapterError
+  alias CloakedReq.Client
   alias CloakedReq.CookieJar
   alias CloakedReq.Error
   alias CloakedReq.Native
   alias CloakedReq.Request
   alias CloakedReq.Response
+  alias CloakedReq.Streaming
 
-  @custom_req_options [:cookie_jar, :impersonate, :insecure_skip_verify, :local_address, :max_body_size]
+  @custom_req_options [
+    :allow_redirect_downgrade,
+    :ca_certificates,
+    :ca_certificates_only,
+    :client,
+    :client_certificate,
+    :connect_timeout,
+    :cookie_jar,
+    :dns,
+    :happy_eyeballs_timeout,
+    :http2_keep_alive,
+    :impersonate,
+    :insecure_skip_verify,
+    :interface,
+    :ip_family,
+    :local_address,
+    :low_speed,
+    :max_body_size,
+    :max_connections_per_host,
+    :nonblocking,
+    :pinned_public_keys,
+    :pool_idle_timeout,
+    :pool_max_idle_per_host,
+    :proxy,
+    :resolve,
+    :tls_keylog,
+    :total_timeout,
+    :write_timeout
+  ]
+
+  # Extra wait past `total_timeout` so the native timeout error arrives first.
+  @await_grace_ms 1_000
+
+  @client_cache_options [:max_clients, :idle_timeout]
+  @default_max_clients 100
+  @default_client_idle_timeout 300_000
+  # Clients are keyed by options only, so any valid URL selects the same one.
+  @client_key_url "http://localhost/"
 
   @doc """
   Attaches `CloakedReq` adapter behavior to an existing `Req.Request`.
 
   Supported custom adapter options:
 
+  - `:allow_redirect_downgrade` - boolean; follow `https` to `http` redirects
+    instead of failing (default: `false`)
+  - `:ca_certificates` - extra trust anchors: a PEM binary, a path to a PEM file,
+    or a list of them; files are read once, when the options are attached
+  - `:ca_certificates_only` - boolean; trust `:ca_certificates` instead of, not in
+    addition to, the bundled roots (default: `false`)
+  - `:client` - `%CloakedReq.Client{}` to send the request with instead of a
+    cached client; its client options override the request's
+  - `:client_certificate` - mutual TLS identity: keyword list with `:cert` and
+    `:key` (PEM binaries, leaf certificate first), or `:pkcs12` (DER binary) and
+    an optional `:password`
+  - `:connect_timeout` - milliseconds allowed for the TCP connect, TLS handshake
+    and proxy tunnel; fails with a `:connect_timeout_error` (default: `10_000`)
   - `:cookie_jar` - `%CloakedReq.CookieJar{}` for automatic cookie persistence
+  - `:dns` - keyword list configuring the DNS resolver: `:nameservers` (IP
+    addresses, `"ip:port"` strings, or `:cloudflare`, `:google` or `:quad9`),
+    `:protocol` (`:udp`, `:tls` or `:https`), `:tls_name` (server name checked
+    for DoT and DoH), `:cache_size` (records) and `:min_ttl`/`:max_ttl`
+    (seconds). Without `:nameservers` the system nameservers are used
+  - `:happy_eyeballs_timeout` - milliseconds to wait on the preferred address
+    family before racing the other one, or `false` to try addresses one after
+    another (default: `300`)
+  - `:http2_keep_alive` - keyword list with `:interval` (milliseconds between
+    HTTP/2 PING frames), `:timeout` (milliseconds to wait for the acknowledgement
+    before closing the connection, default `20_000`) and `:while_idle` (also ping
+    connections without open requests, default `false`). Impersonation profiles
+    keep their HTTP/2 fingerprint (default: no pings)
   - `:impersonate` - profile atom (e.g. `:chrome_136`, `:"safari_17.4.1"`)
   - `:insecure_skip_verify` - boolean
+  - `:interface` - network interface name (e.g. `"eth1"`, `"wg0"`) that outbound
+    sockets are bound to (`SO_BINDTODEVICE` on Linux). Linux kernels before 5.7
+    require `CAP_NET_RAW` for it
+  - `:ip_family` - `:any`, `:ipv4`, `:ipv6`, `:prefer_ipv4` or `:prefer_ipv6`.
+    `:ipv4` and `:ipv6` only look up and connect to that family; the `:prefer_*`
+    values try it first and fall back to the other (default: `:any`, which keeps
+    the resolver's order)
   - `:local_address` - outbound source IP as string, IPv4 tuple, or IPv6 tuple
+  - `:low_speed` - keyword list with `:bytes_per_second` and `:seconds`; aborts
+    the body download with a `:low_speed_error` when it averages fewer bytes per
+    second than that over a whole window of `:seconds`
   - `:max_body_size` - positive integer or `:unlimited` (default: 10 MB)
+  - `:max_connections_per_host` - positive integer or `:infinity`; at most this
+    many connections to one origin are open at a time, idle pooled ones
+    included (browsers use `6`). A request that finds none free waits until one
+    is returned to the pool or closed. HTTP/2 requests share one connection
+    (default: `:infinity`)
+  - `:nonblocking` - boolean; run the request on the native runtime and wait for
+    its reply by message, which lets it be cancelled when the caller exits or
+    times out. `false` holds a dirty IO scheduler until the request completes
+    and cannot be cancelled (default: `true`)
+  - `:pinned_public_keys` - map of host patterns (`"api.example.com"` or
+    `"*.example.com"`) to base64 SHA-256 hashes of accepted SubjectPublicKeyInfo;
+    connections to a matching host whose certificate chain carries none of them
+    fail with a `:certificate_pin_error`. The chain is checked right after the
+    TLS handshake, before the request is sent
+  - `:pool_idle_timeout` - milliseconds an idle connection stays in the pool, or
+    `:infinity` (default: `90_000`)
+  - `:pool_max_idle_per_host` - non-negative integer; idle connections kept per
+    host, `0` disables reuse (default: `20`)
+  - `:proxy` - proxy URL (`http`, `https`, `socks5`, `socks5h`) or keyword list with
+    `:url`, `:username` and `:password`
+  - `:resolve` - static DNS overrides like curl's `--resolve`: a map of
+    `"host:port"` to an IP address or a list of them. Connections to that host
+    and port go to the given addresses, while the URL, SNI, `Host` header and
+    cookies keep the real host name
+  - `:tls_keylog` - file path that TLS session secrets are appended to (NSS key
+    log format, readable by Wireshark), or `true` to use `SSLKEYLOGFILE`. For
+    debugging only: anyone with the file can decrypt captured traffic
+  - `:total_timeout` - milliseconds for the whole request, redirects and body
+    download included, or `:infinity`; fails with a `:total_timeout_error`
+    (default: `:infinity`)
+  - `:write_timeout` - milliseconds the connection may take to accept the next
+    piece of the request body, up to writing the last one to the socket, or
+    `:infinity`; fails with a `:write_timeout_error` (default: `:infinity`)
+
+  Req's `:receive_timeout` (default: `15_000`) is the longest wait for the
+  response head and then between body chunks; it fails with a
+  `:read_timeout_error`.
+
+  Redirects are followed natively according to Req's `:redirect` (`true`, `false`
+  or `:same_origin`), `:max_redirects` and `:redirect_trusted` options, and Req's
+  own redirect step is removed.
 
   ## Examples
 
@@ -38,7 +158,7 @@ defmodule CloakedReq do
   def attach(%Req.Request{} = request, options \\ []) when is_list(options) do
     request
     |> register_options()
-    |> Req.Request.merge_options(options)
+    |> Req.Request.merge_options(Request.load_ca_certificates(options))
     |> put_adapter()
   end
 
@@ -61,16 +181,178 @@ defmodule CloakedReq do
     |> put_adapter()
   end
 
+  @doc """
+  Sets the bounds of the client cache.
+
+  Requests with the same client options (profile, TLS settings, source address,
+  proxy, DNS, pool settings and so on) share one cached client and its
+  connection pool. Once the cache is full, the least recently used client is
+  dropped; clients without requests for `:idle_timeout` are dropped as well.
+  A client is not idle while a request or stream still uses it; its idle time
+  starts once the last one finishes. Requests in flight keep using a dropped
+  client until they finish.
+
+  Options:
+
+  - `:max_clients` - positive integer (default: `100`)
+  - `:idle_timeout` - milliseconds, or `:infinity` to keep idle clients
+    (default: `300_000`)
+
+  ## Examples
+
+      iex> CloakedReq.configure_client_cache(max_clients: 100, idle_timeout: 300_000)
+      :ok
+  """
+  @spec configure_client_cache(keyword()) :: :ok | {:error, Error.t()}
+  def configure_client_cache(options \\ []) when is_list(options) do
+    with :ok <- validate_client_cache_options(options),
+         {:ok, max_clients} <- validate_max_clients(Keyword.get(options, :max_clients, @default_max_clients)),
+         {:ok, idle_timeout} <-
+           validate_client_idle_timeout(Keyword.get(options, :idle_timeout, @default_client_idle_timeout)) do
+      Native.configure_client_cache(max_clients, idle_timeout)
+    end
+  end
+
+  @doc """
+  Drops the cached client used by requests with the given adapter options, so
+  the next such request builds a fresh one.
+
+  Takes a request with `CloakedReq` attached, or the options that would be
+  passed to `attach/2`. Requests in flight on that client are not affected.
+  """
+  @spec evict_client(Req.Request.t() | keyword()) :: :ok | {:error, Error.t()}
+  def evict_client(%Req.Request{} = request) do
+    with {:ok, {payload, _body}} <- Request.to_native_payload(request) do
+      Native.evict_client(payload)
+      :ok
+    end
+  end
+
+  def evict_client(options) when is_list(options) do
+    with {:ok, payload} <- options_payload(options) do
+      Native.evict_client(payload)
+      :ok
+    end
+  end
+
+  @doc """
+  Drops every cached client. Requests in flight are not affected.
+  """
+  @spec clear_clients() :: :ok
+  def clear_clients do
+    Native.clear_clients()
+    :ok
+  end
+
+  @doc """
+  Opens connections to `origins` ahead of the first request, so that request
+  skips DNS resolution, the TCP connect and the TLS handshake.
+
+  Takes the `attach/2` options of the requests that will follow: they select the
+  cached client, or the `:client`, whose pool keeps the connections. No request
+  is sent: each connection goes to the pool as soon as it is established. Over
+  HTTP/2 the session is kept open too. Connections stay pooled for
+  `:pool_idle_timeout`.
+
+  Origins are warmed up concurrently. Returns `{:ok, results}`, mapping each
+  origin to `:ok` or `{:error, %CloakedReq.Error{}}`, or `{:error, error}` for
+  invalid options.
+
+  ## Examples
+
+      {:ok, %{"https://example.com" => :ok}} =
+        CloakedReq.preconnect(["https://example.com"], impersonate: :chrome_136)
+  """
+  @spec preconnect([String.t()], keyword()) ::
+          {:ok, %{String.t() => :ok | {:error, Error.t()}}} | {:error, Error.t()}
+  def preconnect(origins, options \\ []) when is_list(origins) and is_list(options) do
+    client = Keyword.get(options, :client)
+
+    with :ok <- validate_origins(origins),
+         :ok <- validate_client(client),
+         {:ok, payload} <- options |> merge_client_options(client) |> options_payload(),
+         {:ok, outcomes} <- Native.preconnect(payload, origins, if(client, do: client.ref)) do
+      {:ok, Map.new(outcomes)}
+    end
+  end
+
+  @typedoc """
+  A cached client, see `client_stats/0`.
+  """
+  @type client_stats :: %{
+          impersonate: atom() | nil,
+          insecure_skip_verify: boolean(),
+          local_address: String.t() | nil,
+          interface: String.t() | nil,
+          proxy: String.t() | nil,
+          created_at: DateTime.t(),
+          last_used_at: DateTime.t(),
+          hosts: %{String.t() => %{connections: non_neg_integer(), in_flight: non_neg_integer()}}
+        }
+
+  @doc """
+  Lists the cached clients, oldest first.
+
+  Each entry shows the options that set the client apart (profile, TLS
+  verification flag, source address, interface and proxy, with any proxy
+  password replaced by `***`), when it was built and last used, and per origin
+  (`"https://example.com"`):
+
+  - `:connections` - open connections, busy or idle in the pool, counted from
+    when they are established until they are closed.
+  - `:in_flight` - requests sent and not yet done with their response. Each
+    HTTP/1 request has a connection to itself; HTTP/2 requests share one.
+
+  Clients from `CloakedReq.Client.new/1` are not cached and not listed.
+  """
+  @spec client_stats() :: [client_stats()]
+  def client_stats do
+    Enum.map(Native.client_stats(), fn stats ->
+      %{
+        impersonate: stats.emulation && String.to_existing_atom(stats.emulation),
+        insecure_skip_verify: stats.insecure_skip_verify,
+        local_address: stats.local_address,
+        interface: stats.interface,
+        proxy: stats.proxy,
+        created_at: DateTime.from_unix!(stats.created_at_ms, :millisecond),
+        last_used_at: DateTime.from_unix!(stats.last_used_at_ms, :millisecond),
+        hosts: Map.new(stats.hosts, &{&1.origin, %{connections: &1.connections, in_flight: &1.in_flight}})
+      }
+    end)
+  end
+
+  @doc """
+  Drops all cached DNS answers, so the next requests resolve host names again.
+
+  Covers the default resolver and every resolver configured through `:dns`.
+  Connections that are already open are kept.
+  """
+  @spec flush_dns_cache() :: :ok
+  def flush_dns_cache do
+    CloakedReq.Native.flush_dns_cache()
+  end
+
+  @doc false
+  @spec options_payload(keyword()) :: {:ok, map()} | {:error, Error.t()}
+  def options_payload(options) when is_list(options) do
+    request = [url: @client_key_url] |> Req.new() |> attach(options)
+
+    with {:ok, {payload, _body}} <- Request.to_native_payload(request) do
+      {:ok, payload}
+    end
+  end
+
   @doc false
   @spec run(Req.Request.t()) :: {Req.Request.t(), Req.Response.t() | Exception.t()}
   def run(%Req.Request{} = request) do
     jar = Req.Request.get_option(request, :cookie_jar, nil)
+    client = Req.Request.get_option(request, :client, nil)
 
     with :ok <- validate_cookie_jar(jar),
-         jar_ref = if(jar, do: jar.ref),
-         {:ok, {payload, body}} <- Request.to_native_payload(request),
-         {:ok, response_meta, response_body} <- Native.perform_request(payload, body, jar_ref),
-         {:ok, req_response} <- Response.from_native(response_meta, response_body) do
+         :ok <- validate_client(client),
+         refs = {if(jar, do: jar.ref), if(client, do: client.ref)},
+         {:ok, {payload, body}} <- request |> with_client_options(client) |> Request.to_native_payload(),
+         {:ok, request, req_response} <- perform(request, payload, body, refs) do
       {request, req_response}
     else
       {:error, %Error{} = error} ->
@@ -78,6 +360,94 @@ defmodule CloakedReq do
     end
   end
 
+  # Cookie jar and client references, either of which may be nil.
+  @typep refs :: {reference() | nil, reference() | nil}
+
+  @spec perform(Req.Request.t(), map(), binary() | nil, refs()) ::
+          {:ok, Req.Request.t(), Req.Response.t()} | {:error, Error.t()}
+  defp perform(%Req.Request{into: nil} = request, payload, body, refs) do
+    with {:ok, nonblocking} <- validate_nonblocking(Req.Request.get_option(request, :nonblocking, true)),
+         {:ok, response_meta, response_body} <- execute(nonblocking, payload, body, refs),
+         {:ok, req_response} <- Response.from_native(response_meta, response_body) do
+      {:ok, request, req_response}
+    end
+  end
+
+  defp perform(%Req.Request{} = request, payload, body, {jar_ref, client_ref}) do
+    Streaming.run(request, payload, body, jar_ref, client_ref)
+  end
+
+  @spec execute(boolean(), map(), binary() | nil, refs()) :: {:ok, map(), binary()} | {:error, Error.t()}
+  defp execute(false, payload, body, {jar_ref, client_ref}),
+    do: Native.perform_request(payload, body, jar_ref, client_ref)
+
+  defp execute(true, payload, body, {jar_ref, client_ref}) do
+    with {:ok, ref} <- Native.start_request(payload, body, jar_ref, client_ref) do
+      Native.await_request(ref, await_timeout(payload))
+    end
+  end
+
+  # Every timeout is enforced natively; this only bounds the wait for the reply.
+  @spec await_timeout(map()) :: timeout()
+  defp await_timeout(%{total_timeout_ms: nil}), do: :infinity
+  defp await_timeout(%{total_timeout_ms: total_timeout}), do: total_timeout + @await_grace_ms
+
+  @spec validate_client_cache_options(keyword()) :: :ok | {:error, Error.t()}
+  defp validate_client_cache_options(options) do
+    case Keyword.keys(options) -- @client_cache_options do
+      [] -> :ok
+      unknown -> {:error, Error.new(:invalid_request, "unknown client cache options", %{options: unknown})}
+    end
+  end
+
+  @spec validate_max_clients(term()) :: {:ok, pos_integer()} | {:error, Error.t()}
+  defp validate_max_clients(value) when is_integer(value) and value > 0, do: {:ok, value}
+
+  defp validate_max_clients(_value) do
+    {:error, Error.new(:invalid_request, "max_clients must be a positive integer")}
+  end
+
+  @spec validate_client_idle_timeout(term()) :: {:ok, pos_integer() | nil} | {:error, Error.t()}
+  defp validate_client_idle_timeout(:infinity), do: {:ok, nil}
+  defp validate_client_idle_timeout(value) when is_integer(value) and value > 0, do: {:ok, value}
+
+  defp validate_client_idle_timeout(_value) do
+    {:error, Error.new(:invalid_request, "idle_timeout must be a positive integer or :infinity")}
+  end
+
+  @spec validate_nonblocking(term()) :: {:ok, boolean()} | {:error, Error.t()}
+  defp validate_nonblocking(value) when is_boolean(value), do: {:ok, value}
+
+  defp validate_nonblocking(_value) do
+    {:error, Error.new(:invalid_request, "nonblocking must be a boolean")}
+  end
+
+  @spec validate_client(nil | Client.t()) :: :ok | {:error, Error.t()}
+  defp validate_client(nil), do: :ok
+  defp validate_client(%Client{}), do: :ok
+
+  defp validate_client(_value) do
+    {:error, Error.new(:invalid_request, "client must be a %CloakedReq.Client{}")}
+  end
+
+  # The client was built from its own options, so the payload carries them too.
+  @spec with_client_options(Req.Request.t(), Client.t() | nil) :: Req.Request.t()
+  defp with_client_options(request, nil), do: request
+  defp with_client_options(request, %Client{options: options}), do: Req.Request.merge_options(request, options)
+
+  @spec merge_client_options(keyword(), Client.t() | nil) :: keyword()
+  defp merge_client_options(options, nil), do: options
+  defp merge_client_options(options, %Client{options: client_options}), do: Keyword.merge(options, client_options)
+
+  @spec validate_origins(term()) :: :ok | {:error, Error.t()}
+  defp validate_origins(origins) do
+    if Enum.all?(origins, &is_binary/1) do
+      :ok
+    else
+      {:error, Error.new(:invalid_request, "origins must be a list of URL strings")}
+    end
+  end
+
   @spec validate_cookie_jar(nil | CookieJar.t()) :: :ok | {:error, Error.t()}
   defp validate_cookie_jar(nil), do: :ok
   defp validate_cookie_jar(%CookieJar{}), do: :ok
@@ -91,8 +461,9 @@ defmodule CloakedReq do
     Req.Request.register_options(request, @custom_req_options)
   end
 
+  # Redirects are followed natively, so Req's own redirect step is dropped.
   @spec put_adapter(Req.Request.t()) :: Req.Request.t()
   defp put_adapter(%Req.Request{} = request) do
-    %{request | adapter: &run/1}
+    %{request | adapter: &run/1, response_steps: Keyword.delete(request.response_steps, :redirect)}
   end
 end
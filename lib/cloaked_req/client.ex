@@ -0,0 +1,87 @@
+defmodule CloakedReq.Client do
+  @moduledoc """
+  Reference to a Rust-side HTTP client (wreq `Client`) with its own connection
+  pool.
+
+  Without a client, requests share clients from a global cache keyed by their
+  client options, which can be evicted at any time. A client built here belongs
+  to its owner instead: it is never evicted, shares no connections or TLS
+  sessions with other clients, and is garbage-collected by the BEAM once it is
+  no longer referenced and no request is using it.
+
+  Requests made with `client: client` use its client options, which override
+  the same options set on the request itself.
+
+  ## Examples
+
+      {:ok, client} = CloakedReq.Client.new(impersonate: :chrome_136, proxy: "http://tenant-a.proxy:8080")
+
+      Req.new(url: "https://example.com")
+      |> CloakedReq.attach(client: client)
+      |> Req.get!()
+  """
+
+  alias CloakedReq.Error
+  alias CloakedReq.Native
+  alias CloakedReq.Request
+
+  @client_options [
+    :ca_certificates,
+    :ca_certificates_only,
+    :client_certificate,
+    :connect_timeout,
+    :dns,
+    :happy_eyeballs_timeout,
+    :http2_keep_alive,
+    :impersonate,
+    :insecure_skip_verify,
+    :interface,
+    :ip_family,
+    :local_address,
+    :max_connections_per_host,
+    :pinned_public_keys,
+    :pool_idle_timeout,
+    :pool_max_idle_per_host,
+    :proxy,
+    :resolve,
+    :tls_keylog
+  ]
+
+  @enforce_keys [:ref, :options]
+  defstruct [:ref, :options]
+
+  @type t :: %__MODULE__{ref: reference(), options: keyword()}
+
+  @doc """
+  Builds a client from client options.
+
+  Accepts the `CloakedReq.attach/2` options that shape a client: TLS and
+  impersonation, source address and interface, proxy, DNS, IP family, connect
+  timeout and connection pool options. Returns `{:ok, client}` or
+  `{:error, %CloakedReq.Error{}}`.
+
+  ## Examples
+
+      iex> {:ok, client} = CloakedReq.Client.new(impersonate: :chrome_136)
+      iex> is_reference(client.ref)
+      true
+  """
+  @spec new(keyword()) :: {:ok, t()} | {:error, Error.t()}
+  def new(options \\ []) when is_list(options) do
+    options = Request.load_ca_certificates(options)
+
+    with :ok <- validate_options(options),
+         {:ok, payload} <- CloakedReq.options_payload(options),
+         {:ok, ref} <- Native.create_client(payload) do
+      {:ok, %__MODULE__{ref: ref, options: options}}
+    end
+  end
+
+  @spec validate_options(keyword()) :: :ok | {:error, Error.t()}
+  defp validate_options(options) do
+    case Keyword.keys(options) -- @client_options do
+      [] -> :ok
+      other -> {:error, Error.new(:invalid_request, "not client options", %{options: other})}
+    end
+  end
+end
@@ -1,11 +1,19 @@
 defmodule CloakedReq.CookieJar do
   @moduledoc """
-  Reference to a Rust-side cookie jar (wreq `Jar`).
+  Reference to a Rust-side RFC 6265 cookie jar.
 
   Cookies are automatically stored from `set-cookie` response headers
   and sent with subsequent requests sharing the same jar. The jar is
   garbage-collected by the BEAM when no longer referenced.
 
+  Cookies can be listed with `cookies/1` and `cookies_for/2` and changed with
+  `put/3`, `delete/4` and `clear/1`. A jar can be written out with `export/2`
+  and rebuilt with `import/2`, as JSON or as a Netscape cookies.txt file.
+
+  A jar from `open/2` is bound to a file: it starts with the cookies saved
+  there and is saved again in the background after every response or call that
+  changes it.
+
   ## Examples
 
       jar = CloakedReq.CookieJar.new()
@@ -21,10 +29,33 @@ defmodule CloakedReq.CookieJar do
       |> Req.get!()
   """
 
+  alias CloakedReq.Error
+  alias CloakedReq.Native
+
+  @formats [:json, :netscape]
+
   @enforce_keys [:ref]
-  defstruct [:ref]
+  defstruct [:ref, path: nil]
 
-  @type t :: %__MODULE__{ref: reference()}
+  @type t :: %__MODULE__{ref: reference(), path: Path.t() | nil}
+  @type format :: :json | :netscape
+
+  @typedoc """
+  A stored cookie. `domain` has no leading dot; `host_only` cookies are sent to
+  that exact host only, others to its subdomains as well. `expires` is `nil`
+  for session cookies.
+  """
+  @type cookie :: %{
+          name: String.t(),
+          value: String.t(),
+          domain: String.t(),
+          host_only: boolean(),
+          path: String.t(),
+          expires: DateTime.t() | nil,
+          secure: boolean(),
+          http_only: boolean(),
+          same_site: :strict | :lax | :none | nil
+        }
 
   @doc """
   Creates a new empty cookie jar.
@@ -38,6 +69,249 @@ defmodule CloakedReq.CookieJar do
   """
   @spec new() :: t()
   def new do
-    %__MODULE__{ref: CloakedReq.Native.create_cookie_jar()}
+    %__MODULE__{ref: Native.create_cookie_jar()}
+  end
+
+  @doc """
+  Opens a cookie jar bound to the file at `path`.
+
+  The jar starts with the cookies saved in the file, or empty if there is no
+  file yet. Whenever a response or a call to `put/3`, `delete/4` or `clear/1`
+  changes the jar, a save is queued on a native background thread: the jar is
+  written to a temporary file next to `path`, which is then renamed over
+  `path`. Changes made while a save is queued are written by that same save. A
+  node stopping halfway through a save leaves the previous contents in place.
+  Expired cookies are not saved.
+
+  Saving after a response or call never fails or delays it. A failed save is
+  retried after the next change; `last_save_error/1` tells whether the last
+  one failed, and `save/1` writes the file at once. The file is created
+  readable by its owner only.
+
+  Options:
+
+    * `:format` - `:json` (default) or `:netscape`, see `export/2`.
+
+  Returns `{:ok, jar}` or `{:error, %CloakedReq.Error{}}` when the file cannot
+  be read or parsed.
+
+  ## Examples
+
+      {:ok, jar} = CloakedReq.CookieJar.open("/var/lib/scraper/cookies.json")
+
+      Req.new(url: "https://example.com/login")
+      |> CloakedReq.attach(cookie_jar: jar)
+      |> Req.post!(body: "user=admin&pass=secret")
+  """
+  @spec open(Path.t(), keyword()) :: {:ok, t()} | {:error, Error.t()}
+  def open(path, options \\ []) when is_binary(path) and is_list(options) do
+    path = Path.expand(path)
+
+    with {:ok, format} <- open_format(options),
+         {:ok, ref} <- Native.open_cookie_jar(path, format) do
+      {:ok, %__MODULE__{ref: ref, path: path}}
+    end
+  end
+
+  @doc """
+  Writes a jar from `open/2` to its file now, if it changed since the last
+  save. Returns `:ok` or `{:error, %CloakedReq.Error{}}`, for instance when the
+  directory is not writable.
+  """
+  @spec save(t()) :: :ok | {:error, Error.t()}
+  def save(%__MODULE__{path: nil}) do
+    {:error, Error.new(:invalid_request, "cookie jar is not bound to a file")}
+  end
+
+  def save(%__MODULE__{ref: ref}) do
+    Native.save_cookie_jar(ref)
+  end
+
+  @doc """
+  Returns why the last save of a jar from `open/2` failed, whether it ran in
+  the background or through `save/1`, or `nil` if it succeeded. The error is
+  cleared by the next successful save.
+  """
+  @spec last_save_error(t()) :: Error.t() | nil
+  def last_save_error(%__MODULE__{path: nil}), do: nil
+
+  def last_save_error(%__MODULE__{ref: ref}) do
+    case Native.cookie_jar_save_error(ref) do
+      :ok -> nil
+      {:error, %Error{} = error} -> error
+    end
+  end
+
+  @doc """
+  Lists every unexpired cookie in the jar, sorted by domain, path and name.
+
+  ## Examples
+
+      iex> jar = CloakedReq.CookieJar.new()
+      iex> :ok = CloakedReq.CookieJar.put(jar, "https://example.com/", "sid=abc; Path=/; HttpOnly")
+      iex> [cookie] = CloakedReq.CookieJar.cookies(jar)
+      iex> Map.take(cookie, [:name, :value, :domain, :host_only, :http_only])
+      %{name: "sid", value: "abc", domain: "example.com", host_only: true, http_only: true}
+  """
+  @spec cookies(t()) :: [cookie()]
+  def cookies(%__MODULE__{ref: ref}) do
+    ref |> Native.cookie_jar_cookies() |> Enum.map(&to_cookie/1)
+  end
+
+  @doc """
+  Lists the cookies the jar sends with a request to `url`, in the order they
+  appear in its `cookie` header.
+
+  ## Examples
+
+      iex> jar = CloakedReq.CookieJar.new()
+      iex> :ok = CloakedReq.CookieJar.put(jar, "https://example.com/", "sid=abc; Path=/account")
+      iex> {:ok, [%{name: "sid"}]} = CloakedReq.CookieJar.cookies_for(jar, "https://example.com/account/settings")
+      iex> CloakedReq.CookieJar.cookies_for(jar, "https://example.com/")
+      {:ok, []}
+  """
+  @spec cookies_for(t(), String.t() | URI.t()) :: {:ok, [cookie()]} | {:error, Error.t()}
+  def cookies_for(%__MODULE__{ref: ref}, url) do
+    with {:ok, url} <- normalize_url(url),
+         {:ok, cookies} <- Native.cookie_jar_cookies_for(ref, url) do
+      {:ok, Enum.map(cookies, &to_cookie/1)}
+    end
+  end
+
+  @doc """
+  Stores a cookie given as a `set-cookie` header value, as if the response to
+  a request to `url` had carried it.
+
+  The same rules apply as to cookies from responses: a `Domain` attribute must
+  match the host of `url` and must not be a public suffix, and a cookie that
+  has already expired removes the stored one instead. Returns `:ok` or
+  `{:error, %CloakedReq.Error{}}`.
+
+  ## Examples
+
+      iex> jar = CloakedReq.CookieJar.new()
+      iex> CloakedReq.CookieJar.put(jar, "https://www.example.com/", "sid=abc; Domain=example.com")
+      :ok
+      iex> {:error, error} = CloakedReq.CookieJar.put(jar, "https://example.com/", "sid=abc; Domain=com")
+      iex> error.message
+      "cookie domain is a public suffix or does not match the url host"
+  """
+  @spec put(t(), String.t() | URI.t(), String.t()) :: :ok | {:error, Error.t()}
+  def put(%__MODULE__{ref: ref}, url, set_cookie) when is_binary(set_cookie) do
+    with {:ok, url} <- normalize_url(url) do
+      Native.put_cookie(ref, url, set_cookie)
+    end
+  end
+
+  @doc """
+  Removes the cookie with the given name, domain and path. A leading dot on
+  `domain` is ignored. Returns `true` when the jar had the cookie, `false`
+  otherwise.
+
+  ## Examples
+
+      iex> jar = CloakedReq.CookieJar.new()
+      iex> CloakedReq.CookieJar.put(jar, "https://example.com/", "sid=abc")
+      :ok
+      iex> CloakedReq.CookieJar.delete(jar, "sid", "example.com")
+      true
+      iex> CloakedReq.CookieJar.delete(jar, "sid", "example.com")
+      false
+  """
+  @spec delete(t(), String.t(), String.t(), String.t()) :: boolean()
+  def delete(%__MODULE__{ref: ref}, name, domain, path \\ "/")
+      when is_binary(name) and is_binary(domain) and is_binary(path) do
+    Native.delete_cookie(ref, name, domain, path)
+  end
+
+  @doc """
+  Removes every cookie from the jar.
+  """
+  @spec clear(t()) :: :ok
+  def clear(%__MODULE__{ref: ref}) do
+    Native.clear_cookie_jar(ref)
+  end
+
+  @doc """
+  Serializes every unexpired cookie in the jar.
+
+  Formats:
+
+    * `:json` (default) - `{"version": 1, "cookies": [...]}` with each cookie's
+      name, value, domain, host-only flag, path, expiry (Unix seconds, `null`
+      for session cookies), Secure, HttpOnly and SameSite. Cookies are sorted
+      by domain, path and name, so the output is stable.
+    * `:netscape` - a cookies.txt file as read by curl and wget. HttpOnly
+      cookies carry the `#HttpOnly_` prefix, session cookies expire at `0`.
+      The format has no field for SameSite, which is dropped.
+
+  ## Examples
+
+      iex> jar = CloakedReq.CookieJar.new()
+      iex> CloakedReq.CookieJar.export(jar)
+      ~s({"version":1,"cookies":[]})
+  """
+  @spec export(t(), format()) :: binary()
+  def export(%__MODULE__{ref: ref}, format \\ :json) when format in @formats do
+    Native.export_cookie_jar(ref, format)
   end
+
+  @doc """
+  Builds a new jar from data written by `export/2` or, for `:netscape`, by
+  any tool that writes cookies.txt files.
+
+  Expired cookies are skipped. Cookies whose domain is a public suffix, and
+  names or values with control characters, `;` or `,` (or `=` and spaces in
+  names), are refused like they are in `set-cookie` headers. Returns
+  `{:ok, jar}` or `{:error, %CloakedReq.Error{}}`.
+
+  ## Examples
+
+      iex> {:ok, jar} = CloakedReq.CookieJar.import(~s({"version":1,"cookies":[]}))
+      iex> %CloakedReq.CookieJar{} = jar
+      iex> {:error, error} = CloakedReq.CookieJar.import("not json")
+      iex> error.message
+      "invalid cookie JSON"
+  """
+  @spec import(binary(), format()) :: {:ok, t()} | {:error, Error.t()}
+  def import(data, format \\ :json)
+
+  def import(data, format) when is_binary(data) and format in @formats do
+    if String.valid?(data) do
+      with {:ok, ref} <- Native.import_cookie_jar(data, format) do
+        {:ok, %__MODULE__{ref: ref}}
+      end
+    else
+      {:error, Error.new(:invalid_request, "cookie data must be valid UTF-8")}
+    end
+  end
+
+  def import(data, _format) when not is_binary(data) do
+    {:error, Error.new(:invalid_request, "cookie data must be a binary")}
+  end
+
+  def import(_data, _format) do
+    {:error, Error.new(:invalid_request, "format must be :json or :netscape")}
+  end
+
+  @spec normalize_url(term()) :: {:ok, String.t()} | {:error, Error.t()}
+  defp normalize_url(%URI{} = url), do: {:ok, URI.to_string(url)}
+  defp normalize_url(url) when is_binary(url), do: {:ok, url}
+  defp normalize_url(_url), do: {:error, Error.new(:invalid_request, "url must be a string or URI")}
+
+  @spec to_cookie(map()) :: cookie()
+  defp to_cookie(%{expires: nil} = cookie), do: cookie
+  defp to_cookie(%{expires: expires} = cookie), do: %{cookie | expires: DateTime.from_unix!(expires)}
+
+  @spec open_format(keyword()) :: {:ok, format()} | {:error, Error.t()}
+  defp open_format(options) do
+    case Keyword.keys(options) -- [:format] do
+      [] -> validate_format(Keyword.get(options, :format, :json))
+      other -> {:error, Error.new(:invalid_request, "unknown cookie jar options", %{options: other})}
+    end
+  end
+
+  @spec validate_format(term()) :: {:ok, format()} | {:error, Error.t()}
+  defp validate_format(format) when format in @formats, do: {:ok, format}
+  defp validate_format(_format), do: {:error, Error.new(:invalid_request, "format must be :json or :netscape")}
 end
@@ -31,52 +31,443 @@ defmodule CloakedReq.Native do
     nif_create_cookie_jar()
   end
 
+  @doc """
+  Loads the cookie jar saved at `path`, or starts an empty one if the file does
+  not exist, and binds it to `path`.
+
+  Returns `{:ok, ref}` or `{:error, %CloakedReq.Error{}}`.
+  """
+  @spec open_cookie_jar(String.t(), :json | :netscape) :: {:ok, reference()} | {:error, Error.t()}
+  def open_cookie_jar(path, format) when is_binary(path) and format in [:json, :netscape] do
+    path
+    |> nif_open_cookie_jar(format)
+    |> decode_handle()
+  end
+
+  @doc """
+  Writes a file-backed cookie jar resource to its file if it changed since the
+  last save. Returns `:ok` or `{:error, %CloakedReq.Error{}}`.
+  """
+  @spec save_cookie_jar(reference()) :: :ok | {:error, Error.t()}
+  def save_cookie_jar(jar_ref) do
+    case nif_save_cookie_jar(jar_ref) do
+      :ok -> :ok
+      {:error, error_map} -> {:error, to_error(error_map)}
+      other -> {:error, unexpected_response(other)}
+    end
+  end
+
+  @doc """
+  Returns `{:error, %CloakedReq.Error{}}` with the reason the last save of a
+  file-backed cookie jar resource failed, or `:ok` if it did not.
+  """
+  @spec cookie_jar_save_error(reference()) :: :ok | {:error, Error.t()}
+  def cookie_jar_save_error(jar_ref) do
+    case nif_cookie_jar_save_error(jar_ref) do
+      :ok -> :ok
+      {:error, error_map} -> {:error, to_error(error_map)}
+      other -> {:error, unexpected_response(other)}
+    end
+  end
+
+  @doc """
+  Serializes the cookies of a cookie jar resource as `:json` or `:netscape`.
+  """
+  @spec export_cookie_jar(reference(), :json | :netscape) :: binary()
+  def export_cookie_jar(jar_ref, format) when format in [:json, :netscape] do
+    nif_export_cookie_jar(jar_ref, format)
+  end
+
+  @doc """
+  Builds a new cookie jar resource from data written by `export_cookie_jar/2`.
+
+  Returns `{:ok, ref}` or `{:error, %CloakedReq.Error{}}` for malformed data.
+  """
+  @spec import_cookie_jar(binary(), :json | :netscape) :: {:ok, reference()} | {:error, Error.t()}
+  def import_cookie_jar(data, format) when is_binary(data) and format in [:json, :netscape] do
+    data
+    |> nif_import_cookie_jar(format)
+    |> decode_handle()
+  end
+
+  @doc """
+  Lists every unexpired cookie in a cookie jar resource as maps with atom keys
+  and expiries in Unix seconds.
+  """
+  @spec cookie_jar_cookies(reference()) :: [map()]
+  def cookie_jar_cookies(jar_ref) do
+    nif_cookie_jar_cookies(jar_ref)
+  end
+
+  @doc """
+  Lists the cookies a cookie jar resource sends to `url`, in header order.
+
+  Returns `{:ok, cookies}` or `{:error, %CloakedReq.Error{}}` for an invalid URL.
+  """
+  @spec cookie_jar_cookies_for(reference(), String.t()) :: {:ok, [map()]} | {:error, Error.t()}
+  def cookie_jar_cookies_for(jar_ref, url) when is_binary(url) do
+    case nif_cookie_jar_cookies_for(jar_ref, url) do
+      {:ok, cookies} when is_list(cookies) -> {:ok, cookies}
+      {:error, error_map} -> {:error, to_error(error_map)}
+      other -> {:error, unexpected_response(other)}
+    end
+  end
+
+  @doc """
+  Stores a `set-cookie` value in a cookie jar resource as if `url` had sent it.
+
+  Returns `:ok` or `{:error, %CloakedReq.Error{}}`.
+  """
+  @spec put_cookie(reference(), String.t(), String.t()) :: :ok | {:error, Error.t()}
+  def put_cookie(jar_ref, url, set_cookie) when is_binary(url) and is_binary(set_cookie) do
+    case nif_put_cookie(jar_ref, url, set_cookie) do
+      :ok -> :ok
+      {:error, error_map} -> {:error, to_error(error_map)}
+      other -> {:error, unexpected_response(other)}
+    end
+  end
+
+  @doc """
+  Removes the cookie with the given name, domain and path from a cookie jar
+  resource. Returns whether the jar had it.
+  """
+  @spec delete_cookie(reference(), String.t(), String.t(), String.t()) :: boolean()
+  def delete_cookie(jar_ref, name, domain, path) do
+    nif_delete_cookie(jar_ref, name, domain, path)
+  end
+
+  @doc """
+  Removes every cookie from a cookie jar resource.
+  """
+  @spec clear_cookie_jar(reference()) :: :ok
+  def clear_cookie_jar(jar_ref) do
+    nif_clear_cookie_jar(jar_ref)
+  end
+
+  @doc """
+  Builds a client from the client options in a request payload.
+
+  Returns `{:ok, ref}` with an opaque reference managed by the BEAM garbage
+  collector, or `{:error, %CloakedReq.Error{}}`.
+  """
+  @spec create_client(map()) :: {:ok, reference()} | {:error, Error.t()}
+  def create_client(payload) when is_map(payload) do
+    payload
+    |> safe_nif_create_client()
+    |> decode_handle()
+  end
+
   @doc """
   Sends the request metadata and body to the Rust NIF.
 
   The metadata map is passed directly to the NIF (decoded via Rustler's NifMap).
   The body is passed as a raw binary (or nil). An optional cookie jar reference
-  enables automatic cookie persistence across requests.
+  enables automatic cookie persistence across requests, and an optional client
+  reference from `create_client/1` replaces the cached client.
   Returns `{:ok, response_meta, body}` or `{:error, %CloakedReq.Error{}}`.
+
+  The call holds a dirty IO scheduler until the request completes and cannot
+  be cancelled, not even by exiting the calling process. Use `start_request/3`
+  for requests that must be.
+  """
+  @spec perform_request(map(), binary() | nil, reference() | nil, reference() | nil) ::
+          {:ok, map(), binary()} | {:error, Error.t()}
+  def perform_request(payload, body, cookie_jar_ref \\ nil, client_ref \\ nil)
+
+  def perform_request(payload, body, cookie_jar_ref, client_ref) when is_map(payload) do
+    payload
+    |> safe_nif_perform_request(body, cookie_jar_ref, client_ref)
+    |> decode_result()
+  end
+
+  def perform_request(_payload, _body, _cookie_jar_ref, _client_ref) do
+    {:error, Error.new(:invalid_request, "native payload must be a map")}
+  end
+
+  @doc """
+  Starts a request without holding a scheduler and returns its request handle.
+
+  The request runs on the native runtime. The calling process later receives a
+  single `{handle, result}` message, where `result` is `{:ok, meta, body}` or
+  `{:error, error_map}`. Wait for it with `await_request/2` or abort it with
+  `cancel_request/1`. The request is cancelled automatically if the calling
+  process exits.
   """
-  @spec perform_request(map(), binary() | nil, reference() | nil) :: {:ok, map(), binary()} | {:error, Error.t()}
-  def perform_request(payload, body, cookie_jar_ref \\ nil)
+  @spec start_request(map(), binary() | nil, reference() | nil, reference() | nil) ::
+          {:ok, reference()} | {:error, Error.t()}
+  def start_request(payload, body, cookie_jar_ref \\ nil, client_ref \\ nil)
 
-  def perform_request(payload, body, cookie_jar_ref) when is_map(payload) do
-    case safe_nif_perform_request(payload, body, cookie_jar_ref) do
-      {:ok, meta, response_body} when is_map(meta) and is_binary(response_body) ->
-        {:ok, meta, response_body}
+  def start_request(payload, body, cookie_jar_ref, client_ref) when is_map(payload) do
+    payload
+    |> safe_nif_start_request(body, cookie_jar_ref, client_ref)
+    |> decode_handle()
+  end
 
-      {:error, %{"type" => type, "message" => message, "details" => details}}
-      when is_binary(type) and is_binary(message) ->
-        error_type = to_error_type(type)
-        {:error, Error.new(error_type, message, details)}
+  def start_request(_payload, _body, _cookie_jar_ref, _client_ref) do
+    {:error, Error.new(:invalid_request, "native payload must be a map")}
+  end
 
-      other ->
-        {:error, Error.new(:native_error, "unexpected native response", %{response: inspect(other)})}
+  @doc """
+  Waits up to `timeout` milliseconds (or `:infinity`) for the reply to a request started with
+  `start_request/3`.
+
+  Returns `{:ok, response_meta, body}` or `{:error, %CloakedReq.Error{}}`. On
+  timeout the request is cancelled.
+  """
+  @spec await_request(reference(), timeout()) :: {:ok, map(), binary()} | {:error, Error.t()}
+  def await_request(handle, timeout) when is_reference(handle) do
+    receive do
+      {^handle, result} -> decode_result(result)
+    after
+      timeout ->
+        cancel_request(handle)
+        {:error, Error.new(:transport_error, "timed out waiting for native response", %{timeout_ms: timeout})}
     end
   end
 
-  def perform_request(_payload, _body, _cookie_jar_ref) do
+  @doc """
+  Starts a streaming request and returns its request handle.
+
+  The calling process receives `{handle, {:response, meta}}` once response headers
+  arrive, `{handle, {:data, chunk}}` for every body chunk, and finally
+  `{handle, :done}` or `{handle, {:error, error_map}}`. Decode them with
+  `parse_message/2`. The stream is cancelled automatically if the calling process exits.
+
+  After the headers, the native task reads on only when asked: each call to
+  `demand_chunk/1` is answered by one body message, so a slow consumer slows
+  the transfer down instead of filling its mailbox.
+  """
+  @spec start_stream(map(), binary() | nil, reference() | nil, reference() | nil) ::
+          {:ok, reference()} | {:error, Error.t()}
+  def start_stream(payload, body, cookie_jar_ref \\ nil, client_ref \\ nil)
+
+  def start_stream(payload, body, cookie_jar_ref, client_ref) when is_map(payload) do
+    payload
+    |> safe_nif_start_stream(body, cookie_jar_ref, client_ref)
+    |> decode_handle()
+  end
+
+  def start_stream(_payload, _body, _cookie_jar_ref, _client_ref) do
     {:error, Error.new(:invalid_request, "native payload must be a map")}
   end
 
-  defp safe_nif_perform_request(payload, body, cookie_jar_ref) do
-    nif_perform_request(payload, body, cookie_jar_ref)
+  @doc """
+  Asks a stream started with `start_stream/3` for its next body message:
+  `{:data, chunk}`, `:done` or `{:error, error_map}`.
+  """
+  @spec demand_chunk(reference()) :: :ok
+  def demand_chunk(handle) when is_reference(handle) do
+    nif_demand_chunk(handle)
+  end
+
+  @doc """
+  Aborts a request started with `start_request/3` or `start_stream/3`.
+
+  The native task is dropped together with its connection, and messages already
+  delivered for `handle` are removed from the mailbox. A reply being sent while
+  the request is cancelled is delivered before the native call returns, so it
+  is removed too. Cancelling a finished request is a no-op.
+  """
+  @spec cancel_request(reference()) :: :ok
+  def cancel_request(handle) when is_reference(handle) do
+    nif_cancel_request(handle)
+    flush(handle)
+  end
+
+  @doc """
+  Bounds the client cache to `max_clients` clients, each dropped after
+  `idle_timeout_ms` without requests (`nil` keeps idle clients).
+  """
+  @spec configure_client_cache(pos_integer(), pos_integer() | nil) :: :ok
+  def configure_client_cache(max_clients, idle_timeout_ms) do
+    nif_configure_client_cache(max_clients, idle_timeout_ms)
+  end
+
+  @doc """
+  Drops the cached client that a request with `payload` would use. Returns
+  whether there was one.
+  """
+  @spec evict_client(map()) :: boolean()
+  def evict_client(payload) when is_map(payload) do
+    nif_evict_client(payload)
+  end
+
+  @doc """
+  Drops every cached client and returns how many there were.
+  """
+  @spec clear_clients() :: non_neg_integer()
+  def clear_clients do
+    nif_clear_clients()
+  end
+
+  @doc """
+  Opens a connection to each of `origins` with the client a request with
+  `payload` would use, or the client behind `client_ref`. Returns
+  `{:ok, [{origin, :ok | {:error, %CloakedReq.Error{}}}]}` in the order given.
+
+  The connections are opened on the native runtime, like `start_request/3`,
+  and the calling process waits for the reply without holding a scheduler.
+  """
+  @spec preconnect(map(), [String.t()], reference() | nil) ::
+          {:ok, [{String.t(), :ok | {:error, Error.t()}}]} | {:error, Error.t()}
+  def preconnect(payload, origins, client_ref \\ nil) when is_map(payload) and is_list(origins) do
+    with {:ok, handle} <- payload |> safe_nif_preconnect(origins, client_ref) |> decode_handle() do
+      receive do
+        {^handle, result} -> decode_preconnect(result)
+      end
+    end
+  end
+
+  @doc """
+  Describes every cached client, oldest first, with times in Unix
+  milliseconds and connection counts per origin.
+  """
+  @spec client_stats() :: [map()]
+  def client_stats do
+    nif_client_stats()
+  end
+
+  @doc """
+  Drops every cached DNS answer, for the system resolver and every `:dns`
+  configuration in use.
+  """
+  @spec flush_dns_cache() :: :ok
+  def flush_dns_cache do
+    nif_flush_dns_cache()
+  end
+
+  @doc """
+  Decodes a message sent by a streaming request started with `start_stream/3`.
+
+  Returns `:unknown` for messages that do not belong to `handle`.
+  """
+  @spec parse_message(reference(), term()) ::
+          {:ok, {:response, map()} | {:data, binary()} | :done} | {:error, Error.t()} | :unknown
+  def parse_message(handle, {handle, {:response, meta}}) when is_map(meta), do: {:ok, {:response, meta}}
+  def parse_message(handle, {handle, {:data, chunk}}) when is_binary(chunk), do: {:ok, {:data, chunk}}
+  def parse_message(handle, {handle, :done}), do: {:ok, :done}
+  def parse_message(handle, {handle, {:error, error_map}}), do: {:error, to_error(error_map)}
+  def parse_message(_handle, _message), do: :unknown
+
+  defp safe_nif_create_client(payload) do
+    nif_create_client(payload)
+  rescue
+    error in [ErlangError] ->
+      {:error, %{"type" => "nif_panic", "message" => Exception.message(error), "details" => %{}}}
+  end
+
+  defp safe_nif_preconnect(payload, origins, client_ref) do
+    nif_preconnect(payload, origins, client_ref)
   rescue
     error in [ErlangError] ->
       {:error, %{"type" => "nif_panic", "message" => Exception.message(error), "details" => %{}}}
   end
 
+  defp safe_nif_perform_request(payload, body, cookie_jar_ref, client_ref) do
+    nif_perform_request(payload, body, cookie_jar_ref, client_ref)
+  rescue
+    error in [ErlangError] ->
+      {:error, %{"type" => "nif_panic", "message" => Exception.message(error), "details" => %{}}}
+  end
+
+  defp safe_nif_start_request(payload, body, cookie_jar_ref, client_ref) do
+    nif_start_request(payload, body, cookie_jar_ref, client_ref)
+  rescue
+    error in [ErlangError] ->
+      {:error, %{"type" => "nif_panic", "message" => Exception.message(error), "details" => %{}}}
+  end
+
+  defp safe_nif_start_stream(payload, body, cookie_jar_ref, client_ref) do
+    nif_start_stream(payload, body, cookie_jar_ref, client_ref)
+  rescue
+    error in [ErlangError] ->
+      {:error, %{"type" => "nif_panic", "message" => Exception.message(error), "details" => %{}}}
+  end
+
+  @spec decode_result(term()) :: {:ok, map(), binary()} | {:error, Error.t()}
+  defp decode_result({:ok, meta, response_body}) when is_map(meta) and is_binary(response_body) do
+    {:ok, meta, response_body}
+  end
+
+  defp decode_result({:error, error_map}), do: {:error, to_error(error_map)}
+  defp decode_result(other), do: {:error, unexpected_response(other)}
+
+  @spec decode_preconnect(term()) ::
+          {:ok, [{String.t(), :ok | {:error, Error.t()}}]} | {:error, Error.t()}
+  defp decode_preconnect({:ok, outcomes}) when is_list(outcomes) do
+    {:ok, Enum.map(outcomes, &decode_preconnect_outcome/1)}
+  end
+
+  defp decode_preconnect({:error, error_map}), do: {:error, to_error(error_map)}
+  defp decode_preconnect(other), do: {:error, unexpected_response(other)}
+
+  defp decode_preconnect_outcome({origin, :ok}), do: {origin, :ok}
+  defp decode_preconnect_outcome({origin, {:error, error_map}}), do: {origin, {:error, to_error(error_map)}}
+
+  @spec decode_handle(term()) :: {:ok, reference()} | {:error, Error.t()}
+  defp decode_handle({:ok, handle}) when is_reference(handle), do: {:ok, handle}
+  defp decode_handle({:error, error_map}), do: {:error, to_error(error_map)}
+  defp decode_handle(other), do: {:error, unexpected_response(other)}
+
+  @spec flush(reference()) :: :ok
+  defp flush(handle) do
+    receive do
+      {^handle, _} -> flush(handle)
+    after
+      0 -> :ok
+    end
+  end
+
+  @spec to_error(term()) :: Error.t()
+  defp to_error(%{"type" => type, "message" => message, "details" => details})
+       when is_binary(type) and is_binary(message) do
+    Error.new(to_error_type(type), message, details)
+  end
+
+  defp to_error(other), do: unexpected_response({:error, other})
+
+  @spec unexpected_response(term()) :: Error.t()
+  defp unexpected_response(response) do
+    Error.new(:native_error, "unexpected native response", %{response: inspect(response)})
+  end
+
   @spec to_error_type(String.t()) :: atom()
   defp to_error_type("nif_panic"), do: :nif_panic
   defp to_error_type("decode_request"), do: :decode_request
   defp to_error_type("invalid_request"), do: :invalid_request
   defp to_error_type("transport_error"), do: :transport_error
+  defp to_error_type("redirect_error"), do: :redirect_error
+  defp to_error_type("certificate_pin_error"), do: :certificate_pin_error
+  defp to_error_type("connect_timeout_error"), do: :connect_timeout_error
+  defp to_error_type("read_timeout_error"), do: :read_timeout_error
+  defp to_error_type("write_timeout_error"), do: :write_timeout_error
+  defp to_error_type("total_timeout_error"), do: :total_timeout_error
+  defp to_error_type("low_speed_error"), do: :low_speed_error
   defp to_error_type("runtime_error"), do: :runtime_error
   defp to_error_type("invalid_native_response"), do: :invalid_native_response
   defp to_error_type(_), do: :native_error
 
   defp nif_create_cookie_jar, do: :erlang.nif_error(:nif_not_loaded)
-  defp nif_perform_request(_payload, _body, _cookie_jar_ref), do: :erlang.nif_error(:nif_not_loaded)
+  defp nif_open_cookie_jar(_path, _format), do: :erlang.nif_error(:nif_not_loaded)
+  defp nif_save_cookie_jar(_jar_ref), do: :erlang.nif_error(:nif_not_loaded)
+  defp nif_cookie_jar_save_error(_jar_ref), do: :erlang.nif_error(:nif_not_loaded)
+  defp nif_export_cookie_jar(_jar_ref, _format), do: :erlang.nif_error(:nif_not_loaded)
+  defp nif_import_cookie_jar(_data, _format), do: :erlang.nif_error(:nif_not_loaded)
+  defp nif_cookie_jar_cookies(_jar_ref), do: :erlang.nif_error(:nif_not_loaded)
+  defp nif_cookie_jar_cookies_for(_jar_ref, _url), do: :erlang.nif_error(:nif_not_loaded)
+  defp nif_put_cookie(_jar_ref, _url, _set_cookie), do: :erlang.nif_error(:nif_not_loaded)
+  defp nif_delete_cookie(_jar_ref, _name, _domain, _path), do: :erlang.nif_error(:nif_not_loaded)
+  defp nif_clear_cookie_jar(_jar_ref), do: :erlang.nif_error(:nif_not_loaded)
+  defp nif_create_client(_payload), do: :erlang.nif_error(:nif_not_loaded)
+  defp nif_perform_request(_payload, _body, _cookie_jar_ref, _client_ref), do: :erlang.nif_error(:nif_not_loaded)
+  defp nif_start_request(_payload, _body, _cookie_jar_ref, _client_ref), do: :erlang.nif_error(:nif_not_loaded)
+  defp nif_start_stream(_payload, _body, _cookie_jar_ref, _client_ref), do: :erlang.nif_error(:nif_not_loaded)
+  defp nif_preconnect(_payload, _origins, _client_ref), do: :erlang.nif_error(:nif_not_loaded)
+  defp nif_demand_chunk(_handle), do: :erlang.nif_error(:nif_not_loaded)
+  defp nif_cancel_request(_handle), do: :erlang.nif_error(:nif_not_loaded)
+  defp nif_configure_client_cache(_max_clients, _idle_timeout_ms), do: :erlang.nif_error(:nif_not_loaded)
+  defp nif_evict_client(_payload), do: :erlang.nif_error(:nif_not_loaded)
+  defp nif_clear_clients, do: :erlang.nif_error(:nif_not_loaded)
+  defp nif_client_stats, do: :erlang.nif_error(:nif_not_loaded)
+  defp nif_flush_dns_cache, do: :erlang.nif_error(:nif_not_loaded)
 end
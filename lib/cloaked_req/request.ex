@@ -2,14 +2,25 @@ defmodule CloakedReq.Request do
   @moduledoc """
   Converts a `Req.Request` into the metadata map and body expected by the Rust NIF.
 
-  Validates and normalizes all adapter options (impersonate, timeout, body size,
-  TLS verification). The metadata map is JSON-encoded by `CloakedReq.Native`
-  before passing to the NIF; the body is passed as a raw binary.
+  Validates and normalizes all adapter options (impersonate, timeouts, body size,
+  TLS verification, source address and interface, client certificate, public key
+  pins, key log, DNS resolver and overrides, IP family, connection pool, proxy,
+  redirects). The metadata map is JSON-encoded by `CloakedReq.Native` before
+  passing to the NIF; the body is passed as a raw binary.
   """
 
   alias CloakedReq.Error
 
   @default_max_body_size 10_485_760
+  @dns_options [:nameservers, :protocol, :tls_name, :cache_size, :min_ttl, :max_ttl]
+  @dns_ports %{udp: 53, tls: 853, https: 443}
+  @dns_providers %{
+    cloudflare: {["1.1.1.1", "1.0.0.1", "2606:4700:4700::1111", "2606:4700:4700::1001"], "cloudflare-dns.com"},
+    google: {["8.8.8.8", "8.8.4.4", "2001:4860:4860::8888", "2001:4860:4860::8844"], "dns.google"},
+    quad9: {["9.9.9.9", "149.112.112.112", "2620:fe::fe", "2620:fe::9"], "dns.quad9.net"}
+  }
+  @ip_families [:any, :ipv4, :ipv6, :prefer_ipv4, :prefer_ipv6]
+  @proxy_schemes ["http", "https", "socks5", "socks5h"]
 
   @doc """
   Builds a native payload tuple from a `Req.Request`.
@@ -28,29 +39,124 @@ defmodule CloakedReq.Request do
          {:ok, emulation} <- normalize_impersonate(Req.Request.get_option(request, :impersonate)),
          {:ok, receive_timeout} <-
            normalize_receive_timeout(Req.Request.get_option(request, :receive_timeout, 15_000)),
+         {:ok, connect_timeout} <-
+           normalize_timeout(Req.Request.get_option(request, :connect_timeout, 10_000), :connect_timeout),
+         {:ok, write_timeout} <-
+           normalize_timeout(Req.Request.get_option(request, :write_timeout, :infinity), :write_timeout),
+         {:ok, total_timeout} <-
+           normalize_timeout(Req.Request.get_option(request, :total_timeout, :infinity), :total_timeout),
+         {:ok, low_speed} <- normalize_low_speed(Req.Request.get_option(request, :low_speed)),
          {:ok, insecure_skip_verify} <-
            normalize_insecure_skip_verify(Req.Request.get_option(request, :insecure_skip_verify, false)),
          {:ok, local_address} <-
-           normalize_local_address(Req.Request.get_option(request, :local_address)) do
+           normalize_local_address(Req.Request.get_option(request, :local_address)),
+         {:ok, interface} <- normalize_interface(Req.Request.get_option(request, :interface)),
+         {:ok, proxy} <- normalize_proxy(Req.Request.get_option(request, :proxy)),
+         {:ok, client_certificate} <-
+           normalize_client_certificate(Req.Request.get_option(request, :client_certificate)),
+         {:ok, ca_certificates} <-
+           normalize_ca_certificates(
+             Req.Request.get_option(request, :ca_certificates),
+             Req.Request.get_option(request, :ca_certificates_only, false)
+           ),
+         {:ok, pins} <- normalize_pins(Req.Request.get_option(request, :pinned_public_keys)),
+         {:ok, tls_keylog} <- normalize_tls_keylog(Req.Request.get_option(request, :tls_keylog, false)),
+         {:ok, resolve} <- normalize_resolve(Req.Request.get_option(request, :resolve)),
+         {:ok, dns} <- normalize_dns(Req.Request.get_option(request, :dns)),
+         {:ok, ip_family} <- normalize_ip_family(Req.Request.get_option(request, :ip_family, :any)),
+         {:ok, happy_eyeballs_ms} <-
+           normalize_happy_eyeballs_timeout(Req.Request.get_option(request, :happy_eyeballs_timeout, 300)),
+         {:ok, pool_idle_timeout} <-
+           normalize_timeout(Req.Request.get_option(request, :pool_idle_timeout, 90_000), :pool_idle_timeout),
+         {:ok, pool_max_idle_per_host} <-
+           normalize_pool_max_idle_per_host(Req.Request.get_option(request, :pool_max_idle_per_host, 20)),
+         {:ok, max_connections_per_host} <-
+           normalize_max_connections_per_host(Req.Request.get_option(request, :max_connections_per_host, :infinity)),
+         {:ok, http2_keep_alive} <-
+           normalize_http2_keep_alive(Req.Request.get_option(request, :http2_keep_alive)),
+         {:ok, redirect} <- normalize_redirect(request) do
       {:ok,
        {%{
           method: request.method |> Atom.to_string() |> String.upcase(),
           url: URI.to_string(request.url),
           headers: flat_headers,
           receive_timeout_ms: receive_timeout,
+          connect_timeout_ms: connect_timeout,
+          write_timeout_ms: write_timeout,
+          total_timeout_ms: total_timeout,
+          low_speed: low_speed,
           emulation: emulation,
           insecure_skip_verify: insecure_skip_verify,
           max_body_size_bytes: max_body_size,
-          local_address: local_address
+          local_address: local_address,
+          interface: interface,
+          proxy: proxy,
+          redirect: redirect,
+          client_certificate: client_certificate,
+          ca_certificates: ca_certificates,
+          pins: pins,
+          tls_keylog: tls_keylog,
+          resolve: resolve,
+          dns: dns,
+          ip_family: ip_family,
+          happy_eyeballs_ms: happy_eyeballs_ms,
+          pool_idle_timeout_ms: pool_idle_timeout,
+          pool_max_idle_per_host: pool_max_idle_per_host,
+          max_connections_per_host: max_connections_per_host,
+          http2_keep_alive: http2_keep_alive
         }, body}}
     end
   end
 
+  @spec normalize_redirect(Req.Request.t()) :: {:ok, map()} | {:error, Error.t()}
+  defp normalize_redirect(request) do
+    with {:ok, mode} <- normalize_redirect_mode(Req.Request.get_option(request, :redirect, true)),
+         {:ok, max} <- normalize_max_redirects(Req.Request.get_option(request, :max_redirects, 10)),
+         {:ok, trusted} <-
+           normalize_boolean(Req.Request.get_option(request, :redirect_trusted, false), :redirect_trusted),
+         {:ok, allow_downgrade} <-
+           normalize_boolean(
+             Req.Request.get_option(request, :allow_redirect_downgrade, false),
+             :allow_redirect_downgrade
+           ) do
+      {:ok, %{mode: mode, max: max, trusted: trusted, allow_downgrade: allow_downgrade}}
+    end
+  end
+
+  @spec normalize_redirect_mode(term()) :: {:ok, :none | :follow | :same_origin} | {:error, Error.t()}
+  defp normalize_redirect_mode(true), do: {:ok, :follow}
+  defp normalize_redirect_mode(false), do: {:ok, :none}
+  defp normalize_redirect_mode(:same_origin), do: {:ok, :same_origin}
+
+  defp normalize_redirect_mode(_value) do
+    {:error, Error.new(:invalid_request, "redirect must be a boolean or :same_origin")}
+  end
+
+  @spec normalize_max_redirects(term()) :: {:ok, non_neg_integer()} | {:error, Error.t()}
+  defp normalize_max_redirects(value) when is_integer(value) and value >= 0, do: {:ok, value}
+
+  defp normalize_max_redirects(_value) do
+    {:error, Error.new(:invalid_request, "max_redirects must be a non-negative integer")}
+  end
+
+  @spec normalize_boolean(term(), atom()) :: {:ok, boolean()} | {:error, Error.t()}
+  defp normalize_boolean(value, _option) when is_boolean(value), do: {:ok, value}
+
+  defp normalize_boolean(_value, option) do
+    {:error, Error.new(:invalid_request, "#{option} must be a boolean")}
+  end
+
   @spec validate_into(Req.Request.t()) :: :ok | {:error, Error.t()}
   defp validate_into(%Req.Request{into: nil}), do: :ok
+  defp validate_into(%Req.Request{into: :self}), do: :ok
+  defp validate_into(%Req.Request{into: fun}) when is_function(fun, 2), do: :ok
 
-  defp validate_into(%Req.Request{}) do
-    {:error, Error.new(:invalid_request, "streaming into is not supported by CloakedReq adapter")}
+  defp validate_into(%Req.Request{into: into}) do
+    if Collectable.impl_for(into) do
+      :ok
+    else
+      {:error, Error.new(:invalid_request, "into must be a 2-arity function, :self, or a collectable")}
+    end
   end
 
   @spec validate_url(URI.t()) :: :ok | {:error, Error.t()}
@@ -114,6 +220,41 @@ defmodule CloakedReq.Request do
     {:error, Error.new(:invalid_request, "receive_timeout must be a positive integer")}
   end
 
+  @spec normalize_timeout(term(), atom()) :: {:ok, nil | pos_integer()} | {:error, Error.t()}
+  defp normalize_timeout(:infinity, option) when option != :connect_timeout, do: {:ok, nil}
+  defp normalize_timeout(value, _option) when is_integer(value) and value > 0, do: {:ok, value}
+
+  defp normalize_timeout(_value, :connect_timeout) do
+    {:error, Error.new(:invalid_request, "connect_timeout must be a positive integer")}
+  end
+
+  defp normalize_timeout(_value, option) do
+    {:error, Error.new(:invalid_request, "#{option} must be a positive integer or :infinity")}
+  end
+
+  @spec normalize_low_speed(term()) :: {:ok, nil | map()} | {:error, Error.t()}
+  defp normalize_low_speed(nil), do: {:ok, nil}
+
+  defp normalize_low_speed(opts) when is_list(opts) do
+    with true <- Keyword.keyword?(opts) and Keyword.keys(opts) -- [:bytes_per_second, :seconds] == [],
+         bytes_per_second when is_integer(bytes_per_second) and bytes_per_second > 0 <- opts[:bytes_per_second],
+         seconds when is_integer(seconds) and seconds > 0 <- opts[:seconds] do
+      {:ok, %{bytes_per_second: bytes_per_second, seconds: seconds}}
+    else
+      _invalid -> {:error, invalid_low_speed_error()}
+    end
+  end
+
+  defp normalize_low_speed(_opts), do: {:error, invalid_low_speed_error()}
+
+  @spec invalid_low_speed_error() :: Error.t()
+  defp invalid_low_speed_error do
+    Error.new(
+      :invalid_request,
+      "low_speed must be a keyword list with positive integer :bytes_per_second and :seconds"
+    )
+  end
+
   @spec normalize_insecure_skip_verify(term()) :: {:ok, boolean()} | {:error, Error.t()}
   defp normalize_insecure_skip_verify(value) when is_boolean(value), do: {:ok, value}
 
@@ -148,6 +289,535 @@ defmodule CloakedReq.Request do
     {:error, Error.new(:invalid_request, "local_address must be an IP address string or tuple")}
   end
 
+  # Linux rules for interface names: 1 to 15 bytes, without `/`, `:` or whitespace.
+  @spec normalize_interface(term()) :: {:ok, nil | String.t()} | {:error, Error.t()}
+  defp normalize_interface(nil), do: {:ok, nil}
+
+  defp normalize_interface(value) when is_binary(value) do
+    if value not in [".", ".."] and byte_size(value) <= 15 and value =~ ~r/\A[^\/:\s\x00]+\z/ do
+      {:ok, value}
+    else
+      {:error, Error.new(:invalid_request, "interface is not a valid network interface name")}
+    end
+  end
+
+  defp normalize_interface(_value) do
+    {:error, Error.new(:invalid_request, "interface must be a network interface name string")}
+  end
+
+  @spec normalize_ip_family(term()) :: {:ok, atom()} | {:error, Error.t()}
+  defp normalize_ip_family(family) when family in @ip_families, do: {:ok, family}
+
+  defp normalize_ip_family(_family) do
+    {:error,
+     Error.new(:invalid_request, "ip_family must be one of #{Enum.map_join(@ip_families, ", ", &inspect/1)}")}
+  end
+
+  @spec normalize_happy_eyeballs_timeout(term()) :: {:ok, nil | non_neg_integer()} | {:error, Error.t()}
+  defp normalize_happy_eyeballs_timeout(false), do: {:ok, nil}
+  defp normalize_happy_eyeballs_timeout(value) when is_integer(value) and value >= 0, do: {:ok, value}
+
+  defp normalize_happy_eyeballs_timeout(_value) do
+    {:error, Error.new(:invalid_request, "happy_eyeballs_timeout must be a non-negative integer or false")}
+  end
+
+  @spec normalize_pool_max_idle_per_host(term()) :: {:ok, non_neg_integer()} | {:error, Error.t()}
+  defp normalize_pool_max_idle_per_host(value) when is_integer(value) and value >= 0, do: {:ok, value}
+
+  defp normalize_pool_max_idle_per_host(_value) do
+    {:error, Error.new(:invalid_request, "pool_max_idle_per_host must be a non-negative integer")}
+  end
+
+  @spec normalize_max_connections_per_host(term()) :: {:ok, nil | pos_integer()} | {:error, Error.t()}
+  defp normalize_max_connections_per_host(:infinity), do: {:ok, nil}
+
+  defp normalize_max_connections_per_host(value) when is_integer(value) and value > 0 and value <= 0xFFFFFFFF,
+    do: {:ok, value}
+
+  defp normalize_max_connections_per_host(_value) do
+    {:error, Error.new(:invalid_request, "max_connections_per_host must be a positive integer or :infinity")}
+  end
+
+  @spec normalize_http2_keep_alive(term()) :: {:ok, nil | map()} | {:error, Error.t()}
+  defp normalize_http2_keep_alive(nil), do: {:ok, nil}
+
+  defp normalize_http2_keep_alive(opts) when is_list(opts) do
+    with true <- Keyword.keyword?(opts) and Keyword.keys(opts) -- [:interval, :timeout, :while_idle] == [],
+         interval when is_integer(interval) and interval > 0 <- opts[:interval],
+         timeout when is_integer(timeout) and timeout > 0 <- Keyword.get(opts, :timeout, 20_000),
+         while_idle when is_boolean(while_idle) <- Keyword.get(opts, :while_idle, false) do
+      {:ok, %{interval_ms: interval, timeout_ms: timeout, while_idle: while_idle}}
+    else
+      _invalid -> {:error, invalid_http2_keep_alive_error()}
+    end
+  end
+
+  defp normalize_http2_keep_alive(_opts), do: {:error, invalid_http2_keep_alive_error()}
+
+  @spec invalid_http2_keep_alive_error() :: Error.t()
+  defp invalid_http2_keep_alive_error do
+    Error.new(
+      :invalid_request,
+      "http2_keep_alive must be a keyword list with a positive integer :interval, " <>
+        "an optional positive integer :timeout and an optional boolean :while_idle"
+    )
+  end
+
+  @spec normalize_proxy(term()) :: {:ok, nil | map()} | {:error, Error.t()}
+  defp normalize_proxy(nil), do: {:ok, nil}
+  defp normalize_proxy(url) when is_binary(url), do: normalize_proxy(url: url)
+
+  defp normalize_proxy(opts) when is_list(opts) do
+    with true <- Keyword.keyword?(opts),
+         url when is_binary(url) <- opts[:url],
+         :ok <- validate_proxy_url(url),
+         {:ok, username, password} <- normalize_proxy_credentials(opts[:username], opts[:password]) do
+      {:ok, %{url: url, username: username, password: password}}
+    else
+      {:error, %Error{}} = error -> error
+      _invalid -> {:error, invalid_proxy_error()}
+    end
+  end
+
+  defp normalize_proxy(_value), do: {:error, invalid_proxy_error()}
+
+  @spec invalid_proxy_error() :: Error.t()
+  defp invalid_proxy_error do
+    Error.new(:invalid_request, "proxy must be a URL string or a keyword list with :url")
+  end
+
+  @spec validate_proxy_url(String.t()) :: :ok | {:error, Error.t()}
+  defp validate_proxy_url(url) do
+    case URI.parse(url) do
+      %URI{scheme: scheme, host: host} when scheme in @proxy_schemes and is_binary(host) and host != "" ->
+        :ok
+
+      _uri ->
+        {:error, Error.new(:invalid_request, "proxy must be an absolute http, https, socks5 or socks5h URL")}
+    end
+  end
+
+  @spec normalize_proxy_credentials(term(), term()) ::
+          {:ok, nil | String.t(), nil | String.t()} | {:error, Error.t()}
+  defp normalize_proxy_credentials(nil, nil), do: {:ok, nil, nil}
+  defp normalize_proxy_credentials(username, nil) when is_binary(username), do: {:ok, username, nil}
+
+  defp normalize_proxy_credentials(username, password) when is_binary(username) and is_binary(password) do
+    {:ok, username, password}
+  end
+
+  defp normalize_proxy_credentials(_username, _password) do
+    {:error, Error.new(:invalid_request, "proxy username and password must be strings")}
+  end
+
+  @spec normalize_client_certificate(term()) :: {:ok, nil | map()} | {:error, Error.t()}
+  defp normalize_client_certificate(nil), do: {:ok, nil}
+
+  defp normalize_client_certificate(opts) when is_list(opts) do
+    if Keyword.keyword?(opts) do
+      opts |> Map.new() |> client_certificate_payload()
+    else
+      {:error, invalid_client_certificate_error()}
+    end
+  end
+
+  defp normalize_client_certificate(_value), do: {:error, invalid_client_certificate_error()}
+
+  @spec client_certificate_payload(map()) :: {:ok, map()} | {:error, Error.t()}
+  defp client_certificate_payload(%{cert: cert, key: key} = opts)
+       when is_binary(cert) and is_binary(key) and map_size(opts) == 2 do
+    {:ok, %{cert_pem: cert, key_pem: key, pkcs12: nil, password: nil}}
+  end
+
+  defp client_certificate_payload(%{pkcs12: bundle} = opts) when is_binary(bundle) do
+    case Map.delete(opts, :pkcs12) do
+      empty when map_size(empty) == 0 ->
+        {:ok, %{cert_pem: nil, key_pem: nil, pkcs12: bundle, password: nil}}
+
+      %{password: password} = rest when is_binary(password) and map_size(rest) == 1 ->
+        {:ok, %{cert_pem: nil, key_pem: nil, pkcs12: bundle, password: password}}
+
+      _invalid ->
+        {:error, invalid_client_certificate_error()}
+    end
+  end
+
+  defp client_certificate_payload(_opts), do: {:error, invalid_client_certificate_error()}
+
+  @spec invalid_client_certificate_error() :: Error.t()
+  defp invalid_client_certificate_error do
+    Error.new(
+      :invalid_request,
+      "client_certificate must be a keyword list with :cert and :key, or :pkcs12 and an optional :password"
+    )
+  end
+
+  @spec normalize_ca_certificates(term(), term()) :: {:ok, nil | map()} | {:error, Error.t()}
+  defp normalize_ca_certificates(nil, _only), do: {:ok, nil}
+
+  defp normalize_ca_certificates(_certificates, only) when not is_boolean(only) do
+    {:error, Error.new(:invalid_request, "ca_certificates_only must be a boolean")}
+  end
+
+  defp normalize_ca_certificates(certificates, only) when is_binary(certificates) do
+    normalize_ca_certificates([certificates], only)
+  end
+
+  defp normalize_ca_certificates([_ | _] = certificates, only) do
+    certificates
+    |> Enum.reduce_while({:ok, []}, fn certificate, {:ok, pems} ->
+      case read_ca_certificate(certificate) do
+        {:ok, pem} -> {:cont, {:ok, [pem | pems]}}
+        {:error, %Error{}} = error -> {:halt, error}
+      end
+    end)
+    |> case do
+      {:ok, pems} -> {:ok, %{pems: Enum.reverse(pems), only: only}}
+      {:error, %Error{}} = error -> error
+    end
+  end
+
+  defp normalize_ca_certificates(_certificates, _only) do
+    {:error, Error.new(:invalid_request, "ca_certificates must be a PEM binary, a file path, or a list of them")}
+  end
+
+  @doc false
+  # Reads `:ca_certificates` files once, when the options are attached, so requests
+  # skip the file system. Paths that do not load are kept and reported per request.
+  @spec load_ca_certificates(keyword()) :: keyword()
+  def load_ca_certificates(options) when is_list(options) do
+    case Keyword.fetch(options, :ca_certificates) do
+      {:ok, certificates} when is_list(certificates) ->
+        Keyword.put(options, :ca_certificates, Enum.map(certificates, &load_ca_certificate/1))
+
+      {:ok, certificate} ->
+        Keyword.put(options, :ca_certificates, load_ca_certificate(certificate))
+
+      :error ->
+        options
+    end
+  end
+
+  @spec load_ca_certificate(term()) :: term()
+  defp load_ca_certificate(value) when is_binary(value) do
+    with false <- String.contains?(value, "-----BEGIN"),
+         {:ok, pem} <- File.read(value),
+         true <- String.contains?(pem, "-----BEGIN CERTIFICATE-----") do
+      pem
+    else
+      _unchanged -> value
+    end
+  end
+
+  defp load_ca_certificate(value), do: value
+
+  # A binary holding a PEM block is used as is; any other binary is a file path.
+  @spec read_ca_certificate(term()) :: {:ok, String.t()} | {:error, Error.t()}
+  defp read_ca_certificate(value) when is_binary(value) do
+    pem =
+      if String.contains?(value, "-----BEGIN") do
+        {:ok, value}
+      else
+        File.read(value)
+      end
+
+    case pem do
+      {:ok, pem} ->
+        if String.contains?(pem, "-----BEGIN CERTIFICATE-----") do
+          {:ok, pem}
+        else
+          {:error, Error.new(:invalid_request, "ca_certificates must contain PEM certificates")}
+        end
+
+      {:error, reason} ->
+        {:error, Error.new(:invalid_request, "ca_certificates file could not be read", %{path: value, reason: reason})}
+    end
+  end
+
+  defp read_ca_certificate(_value) do
+    {:error, Error.new(:invalid_request, "ca_certificates must be a PEM binary, a file path, or a list of them")}
+  end
+
+  # Pins are checked by the client while connecting, so they are sorted to keep
+  # the same pins on the same cached client.
+  @spec normalize_pins(term()) :: {:ok, [map()]} | {:error, Error.t()}
+  defp normalize_pins(nil), do: {:ok, []}
+
+  defp normalize_pins(pins) when is_map(pins) do
+    pins
+    |> Enum.reduce_while({:ok, []}, fn {host, hashes}, {:ok, acc} ->
+      case normalize_pin(host, List.wrap(hashes)) do
+        {:ok, pin} -> {:cont, {:ok, [pin | acc]}}
+        {:error, %Error{}} = error -> {:halt, error}
+      end
+    end)
+    |> case do
+      {:ok, pins} -> {:ok, Enum.sort(pins)}
+      {:error, %Error{}} = error -> error
+    end
+  end
+
+  defp normalize_pins(_pins) do
+    {:error, Error.new(:invalid_request, "pinned_public_keys must map host patterns to SHA-256 pins")}
+  end
+
+  @spec normalize_pin(term(), list()) :: {:ok, map()} | {:error, Error.t()}
+  defp normalize_pin(host, [_ | _] = hashes) when is_binary(host) and host != "" do
+    if Enum.all?(hashes, &valid_pin?/1) do
+      {:ok, %{host: String.downcase(host), sha256: Enum.map(hashes, &strip_pin_prefix/1)}}
+    else
+      {:error, Error.new(:invalid_request, "pinned public keys must be base64 SHA-256 hashes", %{host: host})}
+    end
+  end
+
+  defp normalize_pin(_host, _hashes) do
+    {:error, Error.new(:invalid_request, "pinned_public_keys must map host patterns to SHA-256 pins")}
+  end
+
+  # Accepts curl's `sha256//` prefix in addition to bare base64.
+  @spec valid_pin?(term()) :: boolean()
+  defp valid_pin?(hash) when is_binary(hash) do
+    match?({:ok, <<_::binary-size(32)>>}, hash |> strip_pin_prefix() |> Base.decode64())
+  end
+
+  defp valid_pin?(_hash), do: false
+
+  @spec strip_pin_prefix(String.t()) :: String.t()
+  defp strip_pin_prefix("sha256//" <> hash), do: hash
+  defp strip_pin_prefix(hash), do: hash
+
+  # Entries are sorted so the same overrides always map to the same cached client.
+  @spec normalize_resolve(term()) :: {:ok, [map()]} | {:error, Error.t()}
+  defp normalize_resolve(nil), do: {:ok, []}
+
+  defp normalize_resolve(overrides) when is_map(overrides) do
+    overrides
+    |> Enum.reduce_while({:ok, []}, fn {target, addresses}, {:ok, acc} ->
+      with {:ok, host, port} <- parse_resolve_target(target),
+           {:ok, addresses} <- normalize_resolve_addresses(target, List.wrap(addresses)) do
+        {:cont, {:ok, [%{host: host, port: port, addresses: addresses} | acc]}}
+      else
+        {:error, %Error{}} = error -> {:halt, error}
+      end
+    end)
+    |> case do
+      {:ok, entries} -> {:ok, Enum.sort_by(entries, &{&1.host, &1.port})}
+      {:error, %Error{}} = error -> error
+    end
+  end
+
+  defp normalize_resolve(_overrides) do
+    {:error, Error.new(:invalid_request, "resolve must be a map of \"host:port\" to IP addresses")}
+  end
+
+  @spec parse_resolve_target(term()) :: {:ok, String.t(), 1..65_535} | {:error, Error.t()}
+  defp parse_resolve_target(target) when is_binary(target) do
+    with [_, host, port] <- Regex.run(~r/^\[?([^\[\]]+?)\]?:(\d{1,5})$/, target),
+         {port, ""} when port in 1..65_535 <- Integer.parse(port) do
+      {:ok, String.downcase(host), port}
+    else
+      _invalid -> {:error, Error.new(:invalid_request, "resolve keys must be \"host:port\" strings", %{value: target})}
+    end
+  end
+
+  defp parse_resolve_target(target) do
+    {:error, Error.new(:invalid_request, "resolve keys must be \"host:port\" strings", %{value: target})}
+  end
+
+  @spec normalize_resolve_addresses(String.t(), list()) :: {:ok, [String.t()]} | {:error, Error.t()}
+  defp normalize_resolve_addresses(target, [_ | _] = addresses) do
+    parsed = Enum.map(addresses, &parse_ip_address/1)
+
+    if :error in parsed do
+      {:error, invalid_resolve_addresses_error(target)}
+    else
+      {:ok, for({:ok, address} <- parsed, do: address |> :inet.ntoa() |> List.to_string())}
+    end
+  end
+
+  defp normalize_resolve_addresses(target, _addresses), do: {:error, invalid_resolve_addresses_error(target)}
+
+  @spec invalid_resolve_addresses_error(String.t()) :: Error.t()
+  defp invalid_resolve_addresses_error(target) do
+    Error.new(:invalid_request, "resolve addresses must be IP addresses", %{target: target})
+  end
+
+  @spec parse_ip_address(term()) :: {:ok, :inet.ip_address()} | :error
+  defp parse_ip_address(address) when is_binary(address) do
+    case address |> String.to_charlist() |> :inet.parse_strict_address() do
+      {:ok, parsed} -> {:ok, parsed}
+      {:error, _reason} -> :error
+    end
+  end
+
+  defp parse_ip_address(address) when is_tuple(address) do
+    if :inet.is_ip_address(address), do: {:ok, address}, else: :error
+  end
+
+  defp parse_ip_address(_address), do: :error
+
+  @spec normalize_dns(term()) :: {:ok, nil | map()} | {:error, Error.t()}
+  defp normalize_dns(nil), do: {:ok, nil}
+
+  defp normalize_dns(opts) when is_list(opts) do
+    if Keyword.keyword?(opts) and Keyword.keys(opts) -- @dns_options == [] do
+      build_dns(opts)
+    else
+      {:error, invalid_dns_error()}
+    end
+  end
+
+  defp normalize_dns(_opts), do: {:error, invalid_dns_error()}
+
+  @spec build_dns(keyword()) :: {:ok, map()} | {:error, Error.t()}
+  defp build_dns(opts) do
+    with {:ok, protocol} <- normalize_dns_protocol(Keyword.get(opts, :protocol, :udp)),
+         {:ok, nameservers, default_tls_name} <- dns_provider(Keyword.get(opts, :nameservers, [])),
+         {:ok, nameservers} <- normalize_nameservers(nameservers, @dns_ports[protocol]),
+         :ok <- validate_encrypted_nameservers(nameservers, protocol),
+         {:ok, tls_name} <- normalize_dns_tls_name(Keyword.get(opts, :tls_name, default_tls_name), protocol),
+         {:ok, cache_size} <- normalize_optional_count(opts[:cache_size], :cache_size),
+         {:ok, min_ttl} <- normalize_optional_count(opts[:min_ttl], :min_ttl),
+         {:ok, max_ttl} <- normalize_optional_count(opts[:max_ttl], :max_ttl),
+         :ok <- validate_ttl_bounds(min_ttl, max_ttl) do
+      {:ok,
+       %{
+         nameservers: nameservers,
+         protocol: protocol,
+         tls_name: tls_name,
+         cache_size: cache_size,
+         min_ttl_secs: min_ttl,
+         max_ttl_secs: max_ttl
+       }}
+    end
+  end
+
+  @spec invalid_dns_error() :: Error.t()
+  defp invalid_dns_error do
+    Error.new(:invalid_request, "dns must be a keyword list of #{Enum.map_join(@dns_options, ", ", &inspect/1)}")
+  end
+
+  @spec normalize_dns_protocol(term()) :: {:ok, :udp | :tls | :https} | {:error, Error.t()}
+  defp normalize_dns_protocol(protocol) when protocol in [:udp, :tls, :https], do: {:ok, protocol}
+
+  defp normalize_dns_protocol(_protocol) do
+    {:error, Error.new(:invalid_request, "dns protocol must be :udp, :tls or :https")}
+  end
+
+  @spec dns_provider(term()) :: {:ok, term(), String.t() | nil} | {:error, Error.t()}
+  defp dns_provider(provider) when is_atom(provider) do
+    case Map.fetch(@dns_providers, provider) do
+      {:ok, {nameservers, tls_name}} -> {:ok, nameservers, tls_name}
+      :error -> {:error, invalid_nameservers_error()}
+    end
+  end
+
+  defp dns_provider(nameservers), do: {:ok, nameservers, nil}
+
+  @spec normalize_nameservers(term(), 1..65_535) :: {:ok, [String.t()]} | {:error, Error.t()}
+  defp normalize_nameservers(nameservers, default_port) when is_list(nameservers) do
+    Enum.reduce_while(nameservers, {:ok, []}, fn nameserver, {:ok, acc} ->
+      case parse_nameserver(nameserver, default_port) do
+        {:ok, address, port} -> {:cont, {:ok, [format_socket_address(address, port) | acc]}}
+        :error -> {:halt, {:error, invalid_nameservers_error()}}
+      end
+    end)
+    |> case do
+      {:ok, nameservers} -> {:ok, Enum.reverse(nameservers)}
+      {:error, %Error{}} = error -> error
+    end
+  end
+
+  defp normalize_nameservers(_nameservers, _default_port), do: {:error, invalid_nameservers_error()}
+
+  @spec parse_nameserver(term(), 1..65_535) :: {:ok, :inet.ip_address(), 1..65_535} | :error
+  defp parse_nameserver({address, port}, _default_port) when is_integer(port) and port in 1..65_535 do
+    with {:ok, address} <- parse_ip_address(address), do: {:ok, address, port}
+  end
+
+  defp parse_nameserver(nameserver, default_port) when is_binary(nameserver) do
+    case parse_ip_address(nameserver) do
+      {:ok, address} ->
+        {:ok, address, default_port}
+
+      :error ->
+        with [_, host, port] <- Regex.run(~r/^\[?([^\[\]]+?)\]?:(\d{1,5})$/, nameserver),
+             {port, ""} when port in 1..65_535 <- Integer.parse(port),
+             {:ok, address} <- parse_ip_address(host) do
+          {:ok, address, port}
+        else
+          _invalid -> :error
+        end
+    end
+  end
+
+  defp parse_nameserver(nameserver, default_port) do
+    with {:ok, address} <- parse_ip_address(nameserver), do: {:ok, address, default_port}
+  end
+
+  @spec format_socket_address(:inet.ip_address(), 1..65_535) :: String.t()
+  defp format_socket_address({_, _, _, _} = address, port), do: "#{:inet.ntoa(address)}:#{port}"
+  defp format_socket_address(address, port), do: "[#{:inet.ntoa(address)}]:#{port}"
+
+  @spec invalid_nameservers_error() :: Error.t()
+  defp invalid_nameservers_error do
+    Error.new(
+      :invalid_request,
+      "dns nameservers must be a list of IP addresses or \"ip:port\" strings, or :cloudflare, :google or :quad9"
+    )
+  end
+
+  @spec validate_encrypted_nameservers([String.t()], :udp | :tls | :https) :: :ok | {:error, Error.t()}
+  defp validate_encrypted_nameservers([], protocol) when protocol in [:tls, :https] do
+    {:error, Error.new(:invalid_request, "dns protocol #{inspect(protocol)} requires :nameservers")}
+  end
+
+  defp validate_encrypted_nameservers(_nameservers, _protocol), do: :ok
+
+  @spec normalize_dns_tls_name(term(), :udp | :tls | :https) :: {:ok, nil | String.t()} | {:error, Error.t()}
+  defp normalize_dns_tls_name(nil, protocol) when protocol in [:tls, :https] do
+    {:error, Error.new(:invalid_request, "dns tls_name is required for :tls and :https")}
+  end
+
+  defp normalize_dns_tls_name(nil, :udp), do: {:ok, nil}
+  defp normalize_dns_tls_name(tls_name, _protocol) when is_binary(tls_name) and tls_name != "", do: {:ok, tls_name}
+
+  defp normalize_dns_tls_name(_tls_name, _protocol) do
+    {:error, Error.new(:invalid_request, "dns tls_name must be a non-empty string")}
+  end
+
+  @spec normalize_optional_count(term(), atom()) :: {:ok, nil | non_neg_integer()} | {:error, Error.t()}
+  defp normalize_optional_count(nil, _option), do: {:ok, nil}
+  defp normalize_optional_count(value, _option) when is_integer(value) and value >= 0, do: {:ok, value}
+
+  defp normalize_optional_count(_value, option) do
+    {:error, Error.new(:invalid_request, "dns #{option} must be a non-negative integer")}
+  end
+
+  @spec validate_ttl_bounds(nil | non_neg_integer(), nil | non_neg_integer()) :: :ok | {:error, Error.t()}
+  defp validate_ttl_bounds(min_ttl, max_ttl) when is_integer(min_ttl) and is_integer(max_ttl) and min_ttl > max_ttl do
+    {:error, Error.new(:invalid_request, "dns min_ttl must not exceed max_ttl")}
+  end
+
+  defp validate_ttl_bounds(_min_ttl, _max_ttl), do: :ok
+
+  # The path is expanded here so equivalent spellings share one cached client.
+  @spec normalize_tls_keylog(term()) :: {:ok, nil | String.t()} | {:error, Error.t()}
+  defp normalize_tls_keylog(false), do: {:ok, nil}
+
+  defp normalize_tls_keylog(true) do
+    case System.get_env("SSLKEYLOGFILE", "") |> String.trim() do
+      "" -> {:error, Error.new(:invalid_request, "tls_keylog: true requires SSLKEYLOGFILE to be set")}
+      path -> {:ok, Path.expand(path)}
+    end
+  end
+
+  defp normalize_tls_keylog(path) when is_binary(path) and path != "", do: {:ok, Path.expand(path)}
+
+  defp normalize_tls_keylog(_value) do
+    {:error, Error.new(:invalid_request, "tls_keylog must be a file path or a boolean")}
+  end
+
   @spec ntoa_to_string(:inet.ip_address()) :: {:ok, String.t()} | {:error, Error.t()}
   defp ntoa_to_string(addr) do
     case :inet.ntoa(addr) do
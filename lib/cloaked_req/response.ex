@@ -13,6 +13,25 @@ defmodule CloakedReq.Response do
   Expects atom-keyed `:status` and `:headers` in the metadata map (produced by
   Rustler's NifMap). Headers arrive as `{name, value}` tuples directly from Rust.
   Returns `{:ok, %Req.Response{}}` or `{:error, %CloakedReq.Error{}}`.
+
+  The final URL is stored under the `:cloaked_req_url` private key and the
+  redirects that led to the response under `:cloaked_req_redirects`, oldest
+  first. Each redirect is a map with `:url`, `:status`, `:headers` (in
+  `Req.Response` form) and the `:cookies_accepted` and `:cookies_rejected`
+  Set-Cookie values of that hop.
+
+  Request timing is stored under `:cloaked_req_timing` as a map of
+  microsecond durations: `:dns_us`, `:connect_us`, `:tls_us`, `:ttfb_us`,
+  `:body_us` and `:total_us`. Connection phases are `nil` when a pooled
+  connection was reused, and `:ttfb_us` starts once a new connection is set
+  up. `CloakedReq.Streaming` leaves `:body_us` and `:total_us` out.
+
+  The HTTP version of the response (`"HTTP/1.1"`, `"HTTP/2"`, ...) is stored
+  under `:cloaked_req_http_version`. For TLS connections `:cloaked_req_tls`
+  holds the negotiated `:alpn` protocol (`nil` when none was chosen), the TLS
+  `:version`, the `:cipher` suite, `:session_resumed` and `:peer_certificates`,
+  the server's DER encoded certificate chain, leaf first; it is `nil` for plain
+  HTTP.
   """
   @spec from_native(map(), binary()) :: {:ok, ReqResponse.t()} | {:error, Error.t()}
   def from_native(%{status: status, headers: headers} = meta, body)
@@ -28,10 +47,43 @@ defmodule CloakedReq.Response do
           response
       end
 
+    response =
+      case meta do
+        %{redirects: redirects} when is_list(redirects) ->
+          ReqResponse.put_private(response, :cloaked_req_redirects, Enum.map(redirects, &redirect_hop/1))
+
+        _ ->
+          response
+      end
+
+    response =
+      case meta do
+        %{timing: timing} when is_map(timing) -> ReqResponse.put_private(response, :cloaked_req_timing, timing)
+        _ -> response
+      end
+
+    response =
+      case meta do
+        %{http_version: version, tls: tls} when is_binary(version) and (is_map(tls) or is_nil(tls)) ->
+          response
+          |> ReqResponse.put_private(:cloaked_req_http_version, version)
+          |> ReqResponse.put_private(:cloaked_req_tls, tls)
+
+        _ ->
+          response
+      end
+
     {:ok, response}
   end
 
   def from_native(_meta, _body) do
     {:error, Error.new(:invalid_native_response, "native response has an invalid shape")}
   end
+
+  @spec redirect_hop(map()) :: map()
+  defp redirect_hop(%{headers: headers} = hop) do
+    hop
+    |> Map.take([:url, :status, :cookies_accepted, :cookies_rejected])
+    |> Map.put(:headers, ReqResponse.new(headers: headers).headers)
+  end
 end
@@ -0,0 +1,182 @@
+defmodule CloakedReq.Streaming do
+  @moduledoc """
+  Delivers streamed native responses into Req `into:` targets.
+
+  The NIF sends the response head, each body chunk and a final message to the
+  calling process (see `CloakedReq.Native.start_stream/3`), tagged with the
+  request handle. This module turns those messages into a `Req.Response` for
+  `into: fun`, `into: collectable` and `into: :self`. Halting or cancelling the
+  body aborts the native request.
+
+  Body chunks are asked for one at a time with `CloakedReq.Native.demand_chunk/1`,
+  once the previous one has been handled, so at most one chunk waits in the
+  mailbox. With `into: :self`, the next chunk is asked for as each message is
+  parsed with `Req.parse_message/2`; a response that is neither read to the end
+  nor cancelled with `Req.cancel_async_response/1` holds its connection until
+  the calling process exits.
+  """
+
+  alias CloakedReq.AdapterError
+  alias CloakedReq.Error
+  alias CloakedReq.Native
+  alias CloakedReq.Response
+
+  # Extra wait past the native timeouts so their errors arrive first.
+  @await_grace_ms 1_000
+
+  @doc """
+  Runs a streaming request and feeds the body into `request.into`.
+
+  Returns `{:ok, request, response}` or `{:error, %CloakedReq.Error{}}`.
+  """
+  @spec run(Req.Request.t(), map(), binary() | nil, reference() | nil, reference() | nil) ::
+          {:ok, Req.Request.t(), Req.Response.t()} | {:error, Error.t()}
+  def run(%Req.Request{into: into} = request, payload, body, cookie_jar_ref, client_ref) do
+    head_timeout = if payload.total_timeout_ms, do: payload.total_timeout_ms + @await_grace_ms, else: :infinity
+
+    with {:ok, ref} <- Native.start_stream(payload, body, cookie_jar_ref, client_ref),
+         {:ok, meta} <- await_head(ref, head_timeout),
+         {:ok, response} <- from_native(ref, meta) do
+      collect(into, request, response, ref, payload.receive_timeout_ms + @await_grace_ms)
+    end
+  end
+
+  @spec await_head(reference(), timeout()) :: {:ok, map()} | {:error, Error.t()}
+  defp await_head(ref, timeout) do
+    receive do
+      {^ref, _} = message ->
+        case Native.parse_message(ref, message) do
+          {:ok, {:response, meta}} -> {:ok, meta}
+          {:error, %Error{}} = error -> error
+          _other -> cancel_with(ref, Error.new(:invalid_native_response, "native stream did not start with headers"))
+        end
+    after
+      timeout -> cancel_with(ref, timeout_error(timeout))
+    end
+  end
+
+  @spec from_native(reference(), map()) :: {:ok, Req.Response.t()} | {:error, Error.t()}
+  defp from_native(ref, meta) do
+    case Response.from_native(meta, "") do
+      {:ok, response} -> {:ok, drop_body_timing(response)}
+      {:error, error} -> cancel_with(ref, error)
+    end
+  end
+
+  # The head is sent before the body is read, so there is no body or total
+  # time to report.
+  @spec drop_body_timing(Req.Response.t()) :: Req.Response.t()
+  defp drop_body_timing(%Req.Response{private: %{cloaked_req_timing: timing}} = response) when is_map(timing) do
+    Req.Response.put_private(response, :cloaked_req_timing, Map.drop(timing, [:body_us, :total_us]))
+  end
+
+  defp drop_body_timing(response), do: response
+
+  @spec collect(term(), Req.Request.t(), Req.Response.t(), reference(), pos_integer()) ::
+          {:ok, Req.Request.t(), Req.Response.t()} | {:error, Error.t()}
+  defp collect(:self, request, response, ref, _timeout) do
+    Native.demand_chunk(ref)
+
+    async = %Req.Response.Async{
+      pid: self(),
+      ref: ref,
+      stream_fun: &parse_async_message/2,
+      cancel_fun: &cancel/1
+    }
+
+    {:ok, request, %{response | body: async}}
+  end
+
+  defp collect(fun, request, response, ref, timeout) when is_function(fun, 2) do
+    case reduce_body(ref, timeout, {request, response}, &fun.({:data, &1}, &2)) do
+      {:ok, {request, response}} -> {:ok, request, response}
+      {:error, error, _acc} -> {:error, error}
+    end
+  end
+
+  defp collect(collectable, request, response, ref, timeout) do
+    {initial, collector} = Collectable.into(collectable)
+
+    case reduce_body(ref, timeout, initial, &{:cont, collector.(&2, {:cont, &1})}) do
+      {:ok, acc} ->
+        {:ok, request, %{response | body: collector.(acc, :done)}}
+
+      {:error, error, acc} ->
+        collector.(acc, :halt)
+        {:error, error}
+    end
+  end
+
+  # Reduces body chunks with `fun`, which returns `{:cont, acc}` or `{:halt, acc}`.
+  # Each chunk is asked for once the previous one has been reduced.
+  @spec reduce_body(reference(), pos_integer(), acc, (binary(), acc -> {:cont | :halt, acc})) ::
+          {:ok, acc} | {:error, Error.t(), acc}
+        when acc: term()
+  defp reduce_body(ref, timeout, acc, fun) do
+    Native.demand_chunk(ref)
+    receive_body(ref, timeout, acc, fun)
+  end
+
+  @spec receive_body(reference(), pos_integer(), acc, (binary(), acc -> {:cont | :halt, acc})) ::
+          {:ok, acc} | {:error, Error.t(), acc}
+        when acc: term()
+  defp receive_body(ref, timeout, acc, fun) do
+    receive do
+      {^ref, _} = message ->
+        ref
+        |> Native.parse_message(message)
+        |> handle_body_message(ref, timeout, acc, fun)
+    after
+      timeout ->
+        cancel(ref)
+        {:error, timeout_error(timeout), acc}
+    end
+  end
+
+  @spec handle_body_message(term(), reference(), pos_integer(), acc, (binary(), acc -> {:cont | :halt, acc})) ::
+          {:ok, acc} | {:error, Error.t(), acc}
+        when acc: term()
+  defp handle_body_message({:ok, {:data, chunk}}, ref, timeout, acc, fun) do
+    case fun.(chunk, acc) do
+      {:cont, acc} ->
+        reduce_body(ref, timeout, acc, fun)
+
+      {:halt, acc} ->
+        cancel(ref)
+        {:ok, acc}
+    end
+  end
+
+  defp handle_body_message({:ok, :done}, _ref, _timeout, acc, _fun), do: {:ok, acc}
+  defp handle_body_message({:error, %Error{} = error}, _ref, _timeout, acc, _fun), do: {:error, error, acc}
+  defp handle_body_message(_other, ref, timeout, acc, fun), do: receive_body(ref, timeout, acc, fun)
+
+  @spec parse_async_message(reference(), term()) ::
+          {:ok, [{:data, binary()} | :done]} | {:error, Exception.t()} | :unknown
+  defp parse_async_message(ref, message) do
+    case Native.parse_message(ref, message) do
+      {:ok, {:data, chunk}} ->
+        Native.demand_chunk(ref)
+        {:ok, [data: chunk]}
+
+      {:ok, :done} -> {:ok, [:done]}
+      {:error, error} -> {:error, AdapterError.exception(error)}
+      _other -> :unknown
+    end
+  end
+
+  # Aborts the native stream and drops messages already in the mailbox.
+  @spec cancel(reference()) :: :ok
+  defp cancel(ref), do: Native.cancel_request(ref)
+
+  @spec cancel_with(reference(), Error.t()) :: {:error, Error.t()}
+  defp cancel_with(ref, %Error{} = error) do
+    cancel(ref)
+    {:error, error}
+  end
+
+  @spec timeout_error(timeout()) :: Error.t()
+  defp timeout_error(timeout) do
+    Error.new(:transport_error, "timed out waiting for streamed response", %{timeout_ms: timeout})
+  end
+end
@@ -69,7 +69,7 @@ defmodule CloakedReq.MixProject do
         "wreq" => "https://docs.rs/wreq/latest/wreq/"
       },
       files:
-        ~w(lib native/cloaked_req_native/src native/cloaked_req_native/Cargo.toml native/cloaked_req_native/Cargo.lock checksum-*.exs mix.exs README.md CHANGELOG.md usage-rules.md LICENSE*)
+        ~w(lib native/cloaked_req_native/src native/cloaked_req_native/Cargo.toml native/cloaked_req_native/Cargo.lock native/cloaked_req_native/vendor checksum-*.exs mix.exs README.md CHANGELOG.md usage-rules.md LICENSE*)
     ]
   end
 
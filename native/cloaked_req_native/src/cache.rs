@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Default bound on the number of cached clients.
+pub const DEFAULT_MAX_ENTRIES: usize = 100;
+
+/// Default time a client may go unused before it is reaped. Longer than the
+/// default pool idle timeout, so its connections have closed by then.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Cache bounded by entry count, evicting the least recently used entry, and
+/// by idle time, dropping entries nobody asked for within `idle_timeout`.
+///
+/// Values are handed out as `Arc`s, so removing an entry only drops the
+/// cache's reference: whoever still holds the value keeps using it. An entry
+/// still held outside the cache counts as in use, so it is never reaped as
+/// idle.
+pub struct BoundedCache<K, V> {
+    entries: HashMap<K, CacheEntry<V>>,
+    max_entries: usize,
+    idle_timeout: Option<Duration>,
+}
+
+struct CacheEntry<V> {
+    value: Arc<V>,
+    created: Instant,
+    last_used: Mutex<Instant>,
+}
+
+/// A cached value with when it was added and last handed out.
+pub struct EntryInfo<'a, K, V> {
+    pub key: &'a K,
+    pub value: &'a Arc<V>,
+    pub created: Instant,
+    pub last_used: Instant,
+}
+
+impl<V> CacheEntry<V> {
+    fn last_used(&self) -> Instant {
+        *self.last_used.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<K: Hash + Eq + Clone, V> BoundedCache<K, V> {
+    pub fn new(max_entries: usize, idle_timeout: Option<Duration>) -> Self {
+        Self {
+            entries: HashMap::new(),
+            max_entries: max_entries.max(1),
+            idle_timeout,
+        }
+    }
+
+    /// Changes the bounds, dropping whatever no longer fits.
+    pub fn configure(&mut self, max_entries: usize, idle_timeout: Option<Duration>) {
+        self.max_entries = max_entries.max(1);
+        self.idle_timeout = idle_timeout;
+        self.reap();
+        while self.entries.len() > self.max_entries {
+            self.evict_least_recently_used();
+        }
+    }
+
+    /// Returns the value for `key` and marks it as used. Takes `&self` so
+    /// lookups can share a read lock.
+    pub fn get(&self, key: &K) -> Option<Arc<V>> {
+        let entry = self.entries.get(key)?;
+        *entry.last_used.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+        Some(entry.value.clone())
+    }
+
+    /// Adds `value`, first dropping idle entries and then the least recently
+    /// used ones until there is room.
+    pub fn insert(&mut self, key: K, value: Arc<V>) {
+        self.reap();
+        while !self.entries.contains_key(&key) && self.entries.len() >= self.max_entries {
+            self.evict_least_recently_used();
+        }
+        let now = Instant::now();
+        self.entries.insert(
+            key,
+            CacheEntry {
+                value,
+                created: now,
+                last_used: Mutex::new(now),
+            },
+        );
+    }
+
+    /// Every entry, in no particular order. Does not mark them as used.
+    pub fn entries(&self) -> impl Iterator<Item = EntryInfo<'_, K, V>> {
+        self.entries.iter().map(|(key, entry)| EntryInfo {
+            key,
+            value: &entry.value,
+            created: entry.created,
+            last_used: entry.last_used(),
+        })
+    }
+
+    pub fn remove(&mut self, key: &K) -> bool {
+        self.entries.remove(key).is_some()
+    }
+
+    /// Drops every entry and returns how many there were.
+    pub fn clear(&mut self) -> usize {
+        let count = self.entries.len();
+        self.entries.clear();
+        count
+    }
+
+    /// Drops entries unused for longer than the idle timeout and returns how
+    /// many were dropped. Entries still held outside the cache are kept and
+    /// marked as used, so their idle time starts once the last holder lets
+    /// go.
+    pub fn reap(&mut self) -> usize {
+        self.reap_at(Instant::now())
+    }
+
+    fn reap_at(&mut self, now: Instant) -> usize {
+        let Some(idle_timeout) = self.idle_timeout else {
+            return 0;
+        };
+        let before = self.entries.len();
+        self.entries.retain(|_, entry| {
+            let last_used = entry.last_used.get_mut().unwrap_or_else(|e| e.into_inner());
+            if Arc::strong_count(&entry.value) > 1 {
+                *last_used = now;
+                return true;
+            }
+            now.saturating_duration_since(*last_used) < idle_timeout
+        });
+        before - self.entries.len()
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used())
+            .map(|(key, _)| key.clone());
+        if let Some(key) = oldest {
+            self.entries.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn touch(cache: &BoundedCache<&'static str, u32>, key: &'static str) {
+        std::thread::sleep(Duration::from_millis(2));
+        cache.get(&key).expect("entry should be cached");
+    }
+
+    #[test]
+    fn evicts_least_recently_used_entry_when_full() {
+        let mut cache = BoundedCache::new(2, None);
+        cache.insert("a", Arc::new(1));
+        cache.insert("b", Arc::new(2));
+        touch(&cache, "a");
+
+        cache.insert("c", Arc::new(3));
+
+        assert!(cache.get(&"a").is_some());
+        assert!(cache.get(&"b").is_none(), "b was used least recently");
+        assert!(cache.get(&"c").is_some());
+    }
+
+    #[test]
+    fn reaps_entries_idle_past_the_timeout() {
+        let mut cache = BoundedCache::new(10, Some(Duration::from_secs(60)));
+        cache.insert("idle", Arc::new(1));
+        cache.insert("busy", Arc::new(2));
+        let later = Instant::now() + Duration::from_secs(61);
+        *cache.entries[&"busy"].last_used.lock().unwrap() = later;
+
+        assert_eq!(cache.reap_at(later), 1);
+        assert!(cache.get(&"idle").is_none());
+        assert!(cache.get(&"busy").is_some());
+    }
+
+    #[test]
+    fn keeps_entries_in_use_past_the_timeout() {
+        let mut cache = BoundedCache::new(10, Some(Duration::from_secs(60)));
+        cache.insert("streaming", Arc::new(1));
+        let in_use = cache.get(&"streaming").expect("entry should be cached");
+        let later = Instant::now() + Duration::from_secs(61);
+
+        assert_eq!(cache.reap_at(later), 0);
+        drop(in_use);
+        assert_eq!(
+            cache.reap_at(later + Duration::from_secs(59)),
+            0,
+            "idle time counts from the last reap that saw it in use"
+        );
+        assert_eq!(cache.reap_at(later + Duration::from_secs(60)), 1);
+    }
+
+    #[test]
+    fn removed_values_stay_usable_by_their_holders() {
+        let mut cache = BoundedCache::new(1, None);
+        cache.insert("a", Arc::new(7));
+        let in_flight = cache.get(&"a").expect("entry should be cached");
+
+        assert!(cache.remove(&"a"));
+        cache.insert("b", Arc::new(8));
+        assert_eq!(cache.clear(), 1);
+
+        assert_eq!(*in_flight, 7);
+        assert!(!cache.remove(&"a"));
+    }
+
+    #[test]
+    fn lists_entries_without_touching_them() {
+        let mut cache = BoundedCache::new(2, None);
+        cache.insert("a", Arc::new(1));
+        touch(&cache, "a");
+
+        let entries: Vec<_> = cache.entries().collect();
+        let last_used = entries[0].last_used;
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!((*entries[0].key, **entries[0].value), ("a", 1));
+        assert!(entries[0].created < last_used);
+        assert_eq!(
+            cache.entries().next().map(|entry| entry.last_used),
+            Some(last_used)
+        );
+    }
+
+    #[test]
+    fn configure_shrinks_to_the_new_bound() {
+        let mut cache = BoundedCache::new(3, None);
+        cache.insert("a", Arc::new(1));
+        cache.insert("b", Arc::new(2));
+        cache.insert("c", Arc::new(3));
+        touch(&cache, "a");
+
+        cache.configure(1, None);
+
+        assert_eq!(cache.entries.len(), 1);
+        assert!(cache.get(&"a").is_some());
+    }
+}
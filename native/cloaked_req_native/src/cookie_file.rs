@@ -0,0 +1,285 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use serde_json::json;
+
+use crate::cookies::{self, CookieStore, NativeCookieFormat};
+use crate::error::NativeError;
+
+/// Tells apart the temporary files of saves running at the same time.
+static TEMP_FILES: AtomicU64 = AtomicU64::new(0);
+
+/// The file a persistent cookie jar is loaded from and saved to.
+///
+/// Every save writes a temporary file next to it and renames it into place,
+/// so the file always holds one complete export, even if the node stops
+/// halfway through a save.
+pub struct CookieFile {
+    path: PathBuf,
+    format: NativeCookieFormat,
+    /// [`CookieStore::changes`] as of the last save. Held while writing, so
+    /// saves never overlap.
+    saved: Mutex<u64>,
+    /// A save has been queued with [`CookieFile::queue`] and not started yet.
+    queued: AtomicBool,
+    /// Why the last save failed, until a save succeeds.
+    last_error: Mutex<Option<NativeError>>,
+}
+
+impl CookieFile {
+    /// Loads the jar saved at `path`. A missing file is an empty jar; it is
+    /// created on the first change.
+    pub fn open(
+        path: &str,
+        format: NativeCookieFormat,
+    ) -> Result<(Self, CookieStore), NativeError> {
+        let store = match fs::read_to_string(path) {
+            Ok(data) => CookieStore::from_cookies(cookies::import(&data, format)?),
+            Err(reason) if reason.kind() == ErrorKind::NotFound => CookieStore::default(),
+            Err(reason) => {
+                return Err(NativeError::new(
+                    "invalid_request",
+                    "cookie file could not be read",
+                    json!({"path": path, "reason": reason.to_string()}),
+                ))
+            }
+        };
+
+        let file = Self::new(PathBuf::from(path), format, store.changes());
+        Ok((file, store))
+    }
+
+    fn new(path: PathBuf, format: NativeCookieFormat, saved: u64) -> Self {
+        Self {
+            path,
+            format,
+            saved: Mutex::new(saved),
+            queued: AtomicBool::new(false),
+            last_error: Mutex::new(None),
+        }
+    }
+
+    /// Queues a save for a change. Returns `false` when a save is queued
+    /// already; that save writes this change too.
+    pub fn queue(&self) -> bool {
+        !self.queued.swap(true, Ordering::AcqRel)
+    }
+
+    /// Runs the save claimed with [`CookieFile::queue`]. Changes made from
+    /// here on queue the next save. A failure is kept for
+    /// [`CookieFile::last_error`].
+    pub fn save_queued(&self, store: &CookieStore) {
+        self.queued.store(false, Ordering::Release);
+        let _ = self.save(store);
+    }
+
+    /// Why the last save failed, if it did.
+    pub fn last_error(&self) -> Option<NativeError> {
+        self.last_error
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Writes `store` to the file, unless it has not changed since the last
+    /// save. Expired cookies are dropped from the store first.
+    pub fn save(&self, store: &CookieStore) -> Result<(), NativeError> {
+        let result = self.write(store);
+        *self.last_error.lock().unwrap_or_else(|e| e.into_inner()) = result.as_ref().err().cloned();
+        result
+    }
+
+    fn write(&self, store: &CookieStore) -> Result<(), NativeError> {
+        let mut saved = self.saved.lock().unwrap_or_else(|e| e.into_inner());
+        store.purge_expired();
+        let changes = store.changes();
+        if changes == *saved {
+            return Ok(());
+        }
+
+        write_atomically(&self.path, store.export(self.format).as_bytes()).map_err(|reason| {
+            NativeError::new(
+                "runtime_error",
+                "cookie file could not be written",
+                json!({"path": self.path.to_string_lossy(), "reason": reason.to_string()}),
+            )
+        })?;
+        *saved = changes;
+        Ok(())
+    }
+}
+
+/// Replaces the contents of `path` with `data` through a temporary file in
+/// the same directory, so readers see either the old or the new contents.
+/// The directory is synced after the rename, so the new file survives a
+/// crash.
+fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "path has no file name"))?;
+    let mut temp_name = OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        TEMP_FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+    let temp_path = path.with_file_name(temp_name);
+
+    let written = create_private(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(data)?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&temp_path, path))
+        .and_then(|()| sync_parent(path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    written
+}
+
+/// Flushes the directory entry of `path` to disk.
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> io::Result<()> {
+    let parent = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    File::open(parent)?.sync_all()
+}
+
+/// Directories cannot be opened as files here; the rename is all there is.
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Creates a file only its owner can read, since cookies carry sessions.
+fn create_private(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "cloaked_req_cookie_file_{}_{name}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("temp dir should be created");
+        dir.join("cookies.json")
+    }
+
+    fn leftovers(path: &Path) -> Vec<OsString> {
+        fs::read_dir(path.parent().expect("path should have a parent"))
+            .expect("temp dir should be readable")
+            .map(|entry| entry.expect("entry should be readable").file_name())
+            .filter(|name| name != "cookies.json")
+            .collect()
+    }
+
+    #[test]
+    fn saves_changes_and_loads_them_back() {
+        let path = temp_path("round_trip");
+        let path_text = path.to_str().expect("temp path should be UTF-8");
+        let page = Url::parse("https://example.com/").expect("url should parse");
+
+        let (file, store) =
+            CookieFile::open(path_text, NativeCookieFormat::Json).expect("missing file is empty");
+        file.save(&store).expect("nothing to save");
+        assert!(!path.exists(), "an unchanged jar is not written");
+
+        store.set_cookie("sid=abc; Path=/", &page);
+        file.save(&store).expect("jar should be saved");
+        assert!(leftovers(&path).is_empty());
+
+        let (_, loaded) =
+            CookieFile::open(path_text, NativeCookieFormat::Json).expect("saved file should load");
+        assert_eq!(loaded.header_for(&page).as_deref(), Some("sid=abc"));
+    }
+
+    #[test]
+    fn rejects_unreadable_files_and_reports_failed_saves() {
+        let path = temp_path("errors");
+        fs::write(&path, "not json").expect("file should be written");
+        let path_text = path.to_str().expect("temp path should be UTF-8");
+
+        let err = CookieFile::open(path_text, NativeCookieFormat::Json)
+            .err()
+            .expect("corrupt file should fail");
+        assert_eq!(err.message, "invalid cookie JSON");
+
+        let dir = path.parent().expect("path should have a parent");
+        let dir_text = dir.to_str().expect("temp path should be UTF-8");
+        let err = CookieFile::open(dir_text, NativeCookieFormat::Json)
+            .err()
+            .expect("directory should fail");
+        assert_eq!(err.message, "cookie file could not be read");
+
+        let file = CookieFile::new(
+            dir.join("missing").join("cookies.json"),
+            NativeCookieFormat::Json,
+            0,
+        );
+        let store = CookieStore::default();
+        store.set_cookie(
+            "sid=abc",
+            &Url::parse("https://example.com/").expect("url should parse"),
+        );
+        let err = file.save(&store).expect_err("save should fail");
+        assert_eq!(err.message, "cookie file could not be written");
+        assert_eq!(leftovers(&path), Vec::<OsString>::new());
+        assert_eq!(
+            file.last_error().map(|err| err.message),
+            Some(err.message.clone())
+        );
+
+        fs::create_dir(dir.join("missing")).expect("dir should be created");
+        file.save(&store).expect("save should be retried");
+        assert!(file.last_error().is_none());
+    }
+
+    #[test]
+    fn queued_saves_are_coalesced() {
+        let path = temp_path("queued");
+        let path_text = path.to_str().expect("temp path should be UTF-8");
+        let page = Url::parse("https://example.com/").expect("url should parse");
+        let (file, store) =
+            CookieFile::open(path_text, NativeCookieFormat::Json).expect("missing file is empty");
+
+        store.set_cookie("a=1", &page);
+        assert!(file.queue());
+        store.set_cookie("b=2", &page);
+        assert!(!file.queue(), "the queued save writes b too");
+
+        file.save_queued(&store);
+        let (_, loaded) =
+            CookieFile::open(path_text, NativeCookieFormat::Json).expect("saved file should load");
+        assert_eq!(loaded.header_for(&page).as_deref(), Some("a=1; b=2"));
+        assert!(file.queue(), "later changes queue the next save");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn syncs_the_directory_of_bare_and_nested_paths() {
+        let path = temp_path("sync");
+
+        assert!(sync_parent(&path).is_ok());
+        assert!(sync_parent(Path::new("cookies.json")).is_ok());
+        assert!(sync_parent(&path.join("missing").join("cookies.json")).is_err());
+    }
+}
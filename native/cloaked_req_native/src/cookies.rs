@@ -0,0 +1,869 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rustler::{NifMap, NifUnitEnum};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use url::Url;
+
+use crate::error::NativeError;
+
+/// Version of the JSON export format, bumped on incompatible changes.
+const JSON_FORMAT_VERSION: u32 = 1;
+
+/// Latest expiry kept, 9999-12-31T23:59:59Z. Later ones (a huge Max-Age, for
+/// one) are capped so every expiry converts to a `DateTime`.
+const MAX_EXPIRES: i64 = 253_402_300_799;
+
+const NETSCAPE_HEADER: &str = "# Netscape HTTP Cookie File\n";
+
+/// Prefix curl uses to mark HttpOnly cookies in cookies.txt files.
+const HTTP_ONLY_PREFIX: &str = "#HttpOnly_";
+
+/// Serialization formats for exported cookie jars.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, NifUnitEnum)]
+pub enum NativeCookieFormat {
+    /// `{"version": 1, "cookies": [...]}` with every cookie attribute.
+    Json,
+    /// Netscape cookies.txt, as read and written by curl and browsers'
+    /// export extensions. It has no field for SameSite.
+    Netscape,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, NifUnitEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+/// A cookie as the jar keeps it, and as it is exported and handed to Elixir.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, NifMap)]
+pub struct StoredCookie {
+    pub name: String,
+    pub value: String,
+    /// Lowercase, without a leading dot: the Domain attribute, or the host
+    /// that set the cookie when `host_only` is set.
+    pub domain: String,
+    /// Sent to `domain` only, not to its subdomains.
+    pub host_only: bool,
+    pub path: String,
+    /// Unix time in seconds; `None` for session cookies.
+    pub expires: Option<i64>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<SameSite>,
+}
+
+impl StoredCookie {
+    fn same_slot(&self, other: &StoredCookie) -> bool {
+        self.name == other.name && self.domain == other.domain && self.path == other.path
+    }
+
+    fn is_expired(&self, now: i64) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    fn matches(&self, url: &Url, now: i64) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let host = host.to_ascii_lowercase();
+        let domain_matches = if self.host_only {
+            host == self.domain
+        } else {
+            domain_match(&host, &self.domain)
+        };
+
+        domain_matches
+            && path_match(url.path(), &self.path)
+            && (!self.secure || matches!(url.scheme(), "https" | "wss"))
+            && !self.is_expired(now)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonExport {
+    version: u32,
+    cookies: Vec<StoredCookie>,
+}
+
+/// RFC 6265 cookie store behind a cookie jar resource.
+///
+/// Unlike wreq's `Jar`, it keeps every attribute of a cookie, including
+/// whether it is host-only, so a jar can be exported and rebuilt.
+#[derive(Debug, Default)]
+pub struct CookieStore {
+    cookies: RwLock<Cookies>,
+    /// Bumped on every change, so a saved copy can tell it is stale.
+    changes: AtomicU64,
+}
+
+/// Cookies indexed by domain, so a request only looks at the domains its host
+/// matches.
+#[derive(Debug, Default)]
+struct Cookies {
+    /// Each cookie with its creation number; a replaced cookie keeps its
+    /// number.
+    by_domain: HashMap<String, Vec<(u64, StoredCookie)>>,
+    created: u64,
+}
+
+impl Cookies {
+    fn iter(&self) -> impl Iterator<Item = &(u64, StoredCookie)> {
+        self.by_domain.values().flatten()
+    }
+
+    /// Drops every expired cookie. Not a change: expired cookies are never
+    /// sent or exported.
+    fn purge_expired(&mut self, now: i64) {
+        self.by_domain.retain(|_, cookies| {
+            cookies.retain(|(_, cookie)| !cookie.is_expired(now));
+            !cookies.is_empty()
+        });
+    }
+}
+
+impl CookieStore {
+    pub fn from_cookies(cookies: Vec<StoredCookie>) -> Self {
+        let store = Self::default();
+        for cookie in cookies {
+            store.insert(cookie);
+        }
+        store
+    }
+
+    /// Stores the `Set-Cookie` value `header` received from `url`. The Domain
+    /// attribute must already have been checked against `url`. Returns
+    /// whether the header could be parsed.
+    pub fn set_cookie(&self, header: &str, url: &Url) -> bool {
+        match parse_set_cookie(header, url, unix_now()) {
+            Some(cookie) => {
+                self.insert(cookie);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Adds `cookie`, replacing the one with the same name, domain and path.
+    /// An expired cookie only removes that one. Expired cookies of the same
+    /// domain are dropped on the way.
+    pub fn insert(&self, mut cookie: StoredCookie) {
+        let now = unix_now();
+        cookie.expires = cookie.expires.map(|expires| expires.min(MAX_EXPIRES));
+        let mut cookies = self.cookies.write().unwrap_or_else(|e| e.into_inner());
+        let Cookies { by_domain, created } = &mut *cookies;
+        let key = cookie.domain.clone();
+        let domain = by_domain.entry(key.clone()).or_default();
+        domain.retain(|(_, stored)| !stored.is_expired(now));
+        let existing = domain
+            .iter()
+            .position(|(_, stored)| stored.same_slot(&cookie));
+
+        let changed = match existing {
+            Some(index) if cookie.is_expired(now) => {
+                domain.remove(index);
+                true
+            }
+            None if cookie.is_expired(now) => false,
+            Some(index) if domain[index].1 == cookie => false,
+            Some(index) => {
+                domain[index].1 = cookie;
+                true
+            }
+            None => {
+                *created += 1;
+                domain.push((*created, cookie));
+                true
+            }
+        };
+        if domain.is_empty() {
+            by_domain.remove(&key);
+        }
+        if changed {
+            self.changed();
+        }
+    }
+
+    /// The cookies sent with a request to `url`, in the order they appear in
+    /// its `Cookie` header: longer paths first, then oldest first, as RFC 6265
+    /// recommends.
+    pub fn cookies_for(&self, url: &Url) -> Vec<StoredCookie> {
+        let Some(host) = url.host_str().map(str::to_ascii_lowercase) else {
+            return Vec::new();
+        };
+        let now = unix_now();
+        let cookies = self.cookies.read().unwrap_or_else(|e| e.into_inner());
+        // The host and every parent domain, the only ones it can match.
+        let domains = std::iter::successors(Some(host.as_str()), |domain| {
+            domain.split_once('.').map(|(_, parent)| parent)
+        });
+        let mut matching: Vec<&(u64, StoredCookie)> = domains
+            .filter_map(|domain| cookies.by_domain.get(domain))
+            .flatten()
+            .filter(|(_, cookie)| cookie.matches(url, now))
+            .collect();
+        matching.sort_by_key(|(created, cookie)| (std::cmp::Reverse(cookie.path.len()), *created));
+        matching
+            .into_iter()
+            .map(|(_, cookie)| cookie.clone())
+            .collect()
+    }
+
+    /// The `Cookie` header value for a request to `url`.
+    pub fn header_for(&self, url: &Url) -> Option<String> {
+        let matching = self.cookies_for(url);
+        if matching.is_empty() {
+            return None;
+        }
+
+        let pairs: Vec<String> = matching
+            .iter()
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect();
+        Some(pairs.join("; "))
+    }
+
+    /// Removes the cookie with this name, domain and path. Returns whether
+    /// there was one.
+    pub fn remove(&self, name: &str, domain: &str, path: &str) -> bool {
+        let domain = normalize_domain(domain);
+        let mut cookies = self.cookies.write().unwrap_or_else(|e| e.into_inner());
+        let Some(stored) = cookies.by_domain.get_mut(&domain) else {
+            return false;
+        };
+        let before = stored.len();
+        stored.retain(|(_, cookie)| !(cookie.name == name && cookie.path == path));
+        let removed = stored.len() != before;
+        if stored.is_empty() {
+            cookies.by_domain.remove(&domain);
+        }
+        if removed {
+            self.changed();
+        }
+        removed
+    }
+
+    pub fn clear(&self) {
+        let mut cookies = self.cookies.write().unwrap_or_else(|e| e.into_inner());
+        if !cookies.by_domain.is_empty() {
+            cookies.by_domain.clear();
+            self.changed();
+        }
+    }
+
+    /// Drops every expired cookie, as saving a jar does.
+    pub fn purge_expired(&self) {
+        self.cookies
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .purge_expired(unix_now());
+    }
+
+    /// How many times the store has changed. Cookies expiring do not count.
+    pub fn changes(&self) -> u64 {
+        self.changes.load(Ordering::Acquire)
+    }
+
+    /// Called with the write lock held.
+    fn changed(&self) {
+        self.changes.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Every unexpired cookie, sorted by domain, path and name.
+    pub fn cookies(&self) -> Vec<StoredCookie> {
+        let now = unix_now();
+        let mut cookies: Vec<StoredCookie> = self
+            .cookies
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|(_, cookie)| cookie)
+            .filter(|cookie| !cookie.is_expired(now))
+            .cloned()
+            .collect();
+        cookies.sort_by(|a, b| (&a.domain, &a.path, &a.name).cmp(&(&b.domain, &b.path, &b.name)));
+        cookies
+    }
+
+    pub fn export(&self, format: NativeCookieFormat) -> String {
+        let cookies = self.cookies();
+        match format {
+            NativeCookieFormat::Json => serde_json::to_string(&JsonExport {
+                version: JSON_FORMAT_VERSION,
+                cookies,
+            })
+            .expect("cookies must serialize"),
+            NativeCookieFormat::Netscape => {
+                let mut text = NETSCAPE_HEADER.to_string();
+                for cookie in &cookies {
+                    text.push_str(&netscape_line(cookie));
+                    text.push('\n');
+                }
+                text
+            }
+        }
+    }
+}
+
+/// Parses an exported jar. Expired cookies are dropped; cookies scoped to a
+/// public suffix are refused, as they would be in a `Set-Cookie` header.
+pub fn import(data: &str, format: NativeCookieFormat) -> Result<Vec<StoredCookie>, NativeError> {
+    let cookies = match format {
+        NativeCookieFormat::Json => parse_json(data)?,
+        NativeCookieFormat::Netscape => parse_netscape(data)?,
+    };
+
+    let now = unix_now();
+    let mut imported = Vec::with_capacity(cookies.len());
+    for mut cookie in cookies {
+        cookie.domain = normalize_domain(&cookie.domain);
+        if cookie.name.is_empty() || cookie.domain.is_empty() || !cookie.path.starts_with('/') {
+            return Err(invalid_cookie(
+                &cookie,
+                "cookie needs a name, a domain and a path",
+            ));
+        }
+        if !is_valid_pair(&cookie.name, &cookie.value) {
+            return Err(invalid_cookie(
+                &cookie,
+                "cookie name or value cannot go into a cookie header",
+            ));
+        }
+        if !cookie.host_only && psl::domain(cookie.domain.as_bytes()).is_none() {
+            return Err(invalid_cookie(&cookie, "cookie domain is a public suffix"));
+        }
+        if !cookie.is_expired(now) {
+            imported.push(cookie);
+        }
+    }
+    Ok(imported)
+}
+
+fn parse_json(data: &str) -> Result<Vec<StoredCookie>, NativeError> {
+    let export: JsonExport = serde_json::from_str(data).map_err(|reason| {
+        NativeError::new(
+            "invalid_request",
+            "invalid cookie JSON",
+            json!({"reason": reason.to_string()}),
+        )
+    })?;
+    if export.version != JSON_FORMAT_VERSION {
+        return Err(NativeError::new(
+            "invalid_request",
+            "unsupported cookie JSON version",
+            json!({"version": export.version}),
+        ));
+    }
+    Ok(export.cookies)
+}
+
+fn parse_netscape(data: &str) -> Result<Vec<StoredCookie>, NativeError> {
+    let mut cookies = Vec::new();
+    for (index, line) in data.lines().enumerate() {
+        let (line, http_only) = match line.strip_prefix(HTTP_ONLY_PREFIX) {
+            Some(rest) => (rest, true),
+            None => (line, false),
+        };
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split('\t').collect();
+        let parsed = match fields.as_slice() {
+            [domain, subdomains, path, secure, expires, name, rest @ ..] if rest.len() <= 1 => {
+                netscape_flag(subdomains)
+                    .zip(netscape_flag(secure))
+                    .zip(expires.trim().parse::<i64>().ok())
+                    .map(|((subdomains, secure), expires)| StoredCookie {
+                        name: name.to_string(),
+                        value: rest.first().copied().unwrap_or_default().to_string(),
+                        domain: domain.to_string(),
+                        host_only: !subdomains,
+                        path: path.to_string(),
+                        expires: (expires != 0).then_some(expires),
+                        secure,
+                        http_only,
+                        same_site: None,
+                    })
+            }
+            _ => None,
+        };
+
+        let cookie = parsed.ok_or_else(|| {
+            NativeError::new(
+                "invalid_request",
+                "invalid cookies.txt line",
+                json!({"line": index + 1}),
+            )
+        })?;
+        cookies.push(cookie);
+    }
+    Ok(cookies)
+}
+
+fn netscape_flag(value: &str) -> Option<bool> {
+    match value {
+        "TRUE" => Some(true),
+        "FALSE" => Some(false),
+        _ => None,
+    }
+}
+
+fn netscape_line(cookie: &StoredCookie) -> String {
+    let flag = |value: bool| if value { "TRUE" } else { "FALSE" };
+    format!(
+        "{}{}{}\t{}\t{}\t{}\t{}\t{}\t{}",
+        if cookie.http_only {
+            HTTP_ONLY_PREFIX
+        } else {
+            ""
+        },
+        if cookie.host_only { "" } else { "." },
+        cookie.domain,
+        flag(!cookie.host_only),
+        cookie.path,
+        flag(cookie.secure),
+        cookie.expires.unwrap_or(0),
+        cookie.name,
+        cookie.value,
+    )
+}
+
+fn invalid_cookie(cookie: &StoredCookie, message: &str) -> NativeError {
+    NativeError::new(
+        "invalid_request",
+        message,
+        json!({"name": cookie.name, "domain": cookie.domain, "path": cookie.path}),
+    )
+}
+
+/// Parses a `Set-Cookie` value received from `url` at Unix time `now`.
+fn parse_set_cookie(header: &str, url: &Url, now: i64) -> Option<StoredCookie> {
+    let parsed = cookie::Cookie::parse(header).ok()?;
+    if !is_valid_pair(parsed.name(), parsed.value()) {
+        return None;
+    }
+    let host = url.host_str()?.to_ascii_lowercase();
+    let domain = parsed
+        .domain()
+        .map(normalize_domain)
+        .filter(|domain| !domain.is_empty());
+    let path = parsed
+        .path()
+        .filter(|path| path.starts_with('/'))
+        .map_or_else(|| default_path(url), str::to_string);
+    // Max-Age wins over Expires.
+    let expires = match parsed.max_age() {
+        Some(max_age) => Some(now.saturating_add(max_age.whole_seconds())),
+        None => parsed
+            .expires_datetime()
+            .map(|expires| expires.unix_timestamp()),
+    };
+
+    Some(StoredCookie {
+        name: parsed.name().to_string(),
+        value: parsed.value().to_string(),
+        host_only: domain.is_none(),
+        domain: domain.unwrap_or(host),
+        path,
+        expires,
+        secure: parsed.secure().unwrap_or(false),
+        http_only: parsed.http_only().unwrap_or(false),
+        same_site: parsed.same_site().map(|same_site| match same_site {
+            cookie::SameSite::Strict => SameSite::Strict,
+            cookie::SameSite::Lax => SameSite::Lax,
+            cookie::SameSite::None => SameSite::None,
+        }),
+    })
+}
+
+/// Whether `name=value` can go into a `Cookie` header as is. Control
+/// characters, `;` and `,` would end the pair or the header, and the name
+/// needs to be a single word without `=`.
+fn is_valid_pair(name: &str, value: &str) -> bool {
+    let separator = |c: char| c.is_ascii_control() || c == ';' || c == ',';
+    !name.is_empty()
+        && !name.contains(|c: char| separator(c) || c == '=' || c.is_whitespace())
+        && !value.contains(separator)
+}
+
+fn normalize_domain(domain: &str) -> String {
+    domain.trim_start_matches('.').to_ascii_lowercase()
+}
+
+/// Default cookie path, RFC 6265 section 5.1.4: the request path up to, but
+/// not including, its last `/`.
+fn default_path(url: &Url) -> String {
+    match url.path().rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(index) => url.path()[..index].to_string(),
+    }
+}
+
+/// RFC 6265 section 5.1.3.
+fn domain_match(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.len() > domain.len()
+            && host.ends_with(domain)
+            && host.as_bytes()[host.len() - domain.len() - 1] == b'.')
+}
+
+/// RFC 6265 section 5.1.4.
+fn path_match(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path[cookie_path.len()..].starts_with('/')))
+}
+
+pub fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| {
+            i64::try_from(since.as_secs()).unwrap_or(i64::MAX)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(value: &str) -> Url {
+        Url::parse(value).expect("url should parse")
+    }
+
+    fn store_with(headers: &[&str], from: &str) -> CookieStore {
+        let store = CookieStore::default();
+        for header in headers {
+            assert!(store.set_cookie(header, &url(from)));
+        }
+        store
+    }
+
+    #[test]
+    fn host_only_cookies_skip_subdomains() {
+        let store = store_with(
+            &["host=1; Path=/", "shared=2; Domain=.Example.com; Path=/"],
+            "https://example.com/login",
+        );
+
+        assert_eq!(
+            store.header_for(&url("https://example.com/")).as_deref(),
+            Some("host=1; shared=2")
+        );
+        assert_eq!(
+            store
+                .header_for(&url("https://www.example.com/"))
+                .as_deref(),
+            Some("shared=2")
+        );
+        assert_eq!(store.header_for(&url("https://other.com/")), None);
+    }
+
+    #[test]
+    fn applies_path_secure_and_expiry_rules() {
+        let store = store_with(
+            &[
+                "root=1",
+                "deep=2; Path=/account",
+                "secure=3; Path=/; Secure",
+                "gone=4; Path=/; Max-Age=0",
+            ],
+            "https://example.com/index.html",
+        );
+
+        assert_eq!(
+            store
+                .header_for(&url("https://example.com/account/settings"))
+                .as_deref(),
+            Some("deep=2; root=1; secure=3")
+        );
+        assert_eq!(
+            store
+                .header_for(&url("http://example.com/accounts"))
+                .as_deref(),
+            Some("root=1")
+        );
+    }
+
+    #[test]
+    fn replaces_and_removes_cookies_in_the_same_slot() {
+        let store = store_with(&["a=1; Path=/", "b=2; Path=/"], "https://example.com/");
+
+        store.set_cookie("a=3; Path=/", &url("https://example.com/"));
+        assert_eq!(
+            store.header_for(&url("https://example.com/")).as_deref(),
+            Some("a=3; b=2")
+        );
+
+        store.set_cookie(
+            "a=; Path=/; Expires=Thu, 01 Jan 1970 00:00:00 GMT",
+            &url("https://example.com/"),
+        );
+        assert_eq!(
+            store.header_for(&url("https://example.com/")).as_deref(),
+            Some("b=2")
+        );
+    }
+
+    #[test]
+    fn removes_cookies_by_name_domain_and_path() {
+        let store = store_with(
+            &["a=1; Path=/", "a=2; Path=/app", "b=3; Domain=example.com"],
+            "https://example.com/",
+        );
+
+        assert!(!store.remove("a", "example.com", "/other"));
+        assert!(store.remove("a", ".Example.com", "/app"));
+        let names: Vec<_> = store
+            .cookies_for(&url("https://example.com/app"))
+            .into_iter()
+            .map(|cookie| (cookie.name, cookie.value))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("a".to_string(), "1".to_string()),
+                ("b".to_string(), "3".to_string())
+            ]
+        );
+
+        store.clear();
+        assert!(store.cookies().is_empty());
+    }
+
+    #[test]
+    fn drops_expired_cookies_on_insert_and_purge() {
+        let store = store_with(&["a=1"], "https://example.com/");
+        let expired = |domain: &str| StoredCookie {
+            name: "old".to_string(),
+            value: "1".to_string(),
+            domain: domain.to_string(),
+            host_only: true,
+            path: "/".to_string(),
+            expires: Some(1),
+            secure: false,
+            http_only: false,
+            same_site: None,
+        };
+        let stored = |store: &CookieStore| {
+            let cookies = store.cookies.read().expect("lock should not be poisoned");
+            let mut stored: Vec<_> = cookies
+                .iter()
+                .map(|(_, cookie)| format!("{}={}", cookie.domain, cookie.name))
+                .collect();
+            stored.sort();
+            stored
+        };
+        {
+            let mut cookies = store.cookies.write().expect("lock should not be poisoned");
+            for domain in ["example.com", "other.example"] {
+                cookies
+                    .by_domain
+                    .entry(domain.to_string())
+                    .or_default()
+                    .push((0, expired(domain)));
+            }
+        }
+        let changes = store.changes();
+
+        store.set_cookie("b=2", &url("https://example.com/"));
+        assert_eq!(
+            stored(&store),
+            vec!["example.com=a", "example.com=b", "other.example=old"]
+        );
+
+        store.purge_expired();
+        assert_eq!(stored(&store), vec!["example.com=a", "example.com=b"]);
+        assert_eq!(store.changes(), changes + 1, "purging is not a change");
+    }
+
+    #[test]
+    fn counts_only_changes_that_alter_the_jar() {
+        let store = store_with(&["a=1; Path=/"], "https://example.com/");
+        let page = url("https://example.com/");
+        let after_first = store.changes();
+
+        store.set_cookie("a=1; Path=/", &page);
+        store.set_cookie("b=; Max-Age=0", &page);
+        assert!(!store.remove("b", "example.com", "/"));
+        assert_eq!(store.changes(), after_first);
+
+        store.set_cookie("a=2; Path=/", &page);
+        store.clear();
+        store.clear();
+        assert_eq!(store.changes(), after_first + 2);
+    }
+
+    #[test]
+    fn parses_every_attribute() {
+        let cookie = parse_set_cookie(
+            "sid=abc; Domain=.Example.com; Path=/app; Max-Age=60; Secure; HttpOnly; SameSite=Lax",
+            &url("https://www.example.com/"),
+            1_000,
+        )
+        .expect("cookie should parse");
+
+        assert_eq!(
+            cookie,
+            StoredCookie {
+                name: "sid".to_string(),
+                value: "abc".to_string(),
+                domain: "example.com".to_string(),
+                host_only: false,
+                path: "/app".to_string(),
+                expires: Some(1_060),
+                secure: true,
+                http_only: true,
+                same_site: Some(SameSite::Lax),
+            }
+        );
+    }
+
+    #[test]
+    fn json_export_round_trips() {
+        let store = store_with(
+            &[
+                "sid=abc; Path=/; Max-Age=3600; HttpOnly; SameSite=Strict",
+                "pref=dark; Domain=example.com; Path=/",
+            ],
+            "https://www.example.com/",
+        );
+
+        let exported = store.export(NativeCookieFormat::Json);
+        let imported = import(&exported, NativeCookieFormat::Json).expect("export should import");
+
+        assert_eq!(imported, store.cookies());
+        assert!(exported.starts_with(r#"{"version":1,"cookies":[{"name":"pref""#));
+    }
+
+    #[test]
+    fn netscape_export_round_trips_without_same_site() {
+        let store = store_with(
+            &[
+                "sid=abc; Path=/; Max-Age=3600; Secure; HttpOnly; SameSite=Strict",
+                "pref=dark; Domain=example.com; Path=/docs",
+            ],
+            "https://www.example.com/",
+        );
+
+        let exported = store.export(NativeCookieFormat::Netscape);
+        let lines: Vec<&str> = exported.lines().collect();
+        let expires = store.cookies()[1].expires.expect("sid has an expiry");
+
+        assert_eq!(lines[0], "# Netscape HTTP Cookie File");
+        assert_eq!(lines[1], ".example.com\tTRUE\t/docs\tFALSE\t0\tpref\tdark");
+        assert_eq!(
+            lines[2],
+            format!("#HttpOnly_www.example.com\tFALSE\t/\tTRUE\t{expires}\tsid\tabc")
+        );
+
+        let imported =
+            import(&exported, NativeCookieFormat::Netscape).expect("export should import");
+        let mut expected = store.cookies();
+        expected[1].same_site = None;
+        assert_eq!(imported, expected);
+    }
+
+    #[test]
+    fn import_rejects_malformed_and_public_suffix_cookies() {
+        let err = import("example.com\tFALSE\t/\n", NativeCookieFormat::Netscape)
+            .expect_err("short line must fail");
+        assert_eq!(err.message, "invalid cookies.txt line");
+
+        let err = import(
+            ".co.uk\tTRUE\t/\tFALSE\t0\tevil\t1\n",
+            NativeCookieFormat::Netscape,
+        )
+        .expect_err("public suffix must fail");
+        assert_eq!(err.message, "cookie domain is a public suffix");
+
+        let err = import(
+            "example.com\tFALSE\t/\tFALSE\t0\tsid\tabc; admin=1\n",
+            NativeCookieFormat::Netscape,
+        )
+        .expect_err("a value with a separator must fail");
+        assert_eq!(
+            err.message,
+            "cookie name or value cannot go into a cookie header"
+        );
+
+        let err = import(r#"{"version":2,"cookies":[]}"#, NativeCookieFormat::Json)
+            .expect_err("unknown version must fail");
+        assert_eq!(err.message, "unsupported cookie JSON version");
+    }
+
+    #[test]
+    fn import_rejects_cookies_that_would_break_the_header() {
+        let json = |name: &str, value: &str| {
+            json!({
+                "version": JSON_FORMAT_VERSION,
+                "cookies": [{
+                    "name": name,
+                    "value": value,
+                    "domain": "example.com",
+                    "host_only": true,
+                    "path": "/",
+                    "expires": null,
+                    "secure": false,
+                    "http_only": false,
+                    "same_site": null,
+                }],
+            })
+            .to_string()
+        };
+
+        assert!(import(&json("sid", "abc"), NativeCookieFormat::Json).is_ok());
+        for (name, value) in [
+            ("sid", "abc\r\nx-injected: 1"),
+            ("sid", "a,b"),
+            ("sid", "a\u{0}b"),
+            ("a=b", "c"),
+            ("s id", "c"),
+            ("s;id", "c"),
+        ] {
+            let err = import(&json(name, value), NativeCookieFormat::Json)
+                .expect_err("cookie must be rejected");
+            assert_eq!(
+                err.message,
+                "cookie name or value cannot go into a cookie header"
+            );
+        }
+
+        for line in [
+            "example.com\tFALSE\t/\tFALSE\t0\ta=b\tc\n",
+            "example.com\tFALSE\t/\tFALSE\t0\tsid\ta,b\n",
+            "example.com\tFALSE\t/\tFALSE\t0\tsid\ta\rb\n",
+        ] {
+            assert!(import(line, NativeCookieFormat::Netscape).is_err());
+        }
+    }
+
+    #[test]
+    fn set_cookie_rejects_pairs_that_would_break_the_header() {
+        let from = url("https://example.com/");
+
+        assert!(parse_set_cookie("sid=a,b", &from, 0).is_none());
+        assert!(parse_set_cookie("s id=abc", &from, 0).is_none());
+        assert!(parse_set_cookie("sid=a\u{1}b", &from, 0).is_none());
+        assert!(parse_set_cookie("sid=\"abc\"", &from, 0).is_some());
+    }
+
+    #[test]
+    fn import_drops_expired_cookies_and_keeps_empty_values() {
+        let imported = import(
+            "# comment\n\nexample.com\tFALSE\t/\tFALSE\t1\told\tx\nexample.com\tFALSE\t/\tFALSE\t0\tempty\n",
+            NativeCookieFormat::Netscape,
+        )
+        .expect("file should import");
+
+        assert_eq!(imported.len(), 1);
+        assert_eq!(imported[0].name, "empty");
+        assert_eq!(imported[0].value, "");
+        assert_eq!(imported[0].expires, None);
+    }
+}
@@ -0,0 +1,603 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, LazyLock, Mutex, Weak};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use hickory_resolver::config::{
+    LookupIpStrategy, NameServerConfig, NameServerConfigGroup, ResolverConfig,
+};
+use hickory_resolver::name_server::TokioConnectionProvider;
+use hickory_resolver::proto::xfer::Protocol;
+use hickory_resolver::{ResolverBuilder, TokioResolver};
+use http::Uri;
+use serde_json::json;
+use tower::{BoxError, Layer, Service};
+use url::{Host, Url};
+use wreq::connect::{Conn, Unnameable};
+use wreq::dns::{Addrs, Name, Resolve, Resolving};
+
+use crate::cache::{self, BoundedCache};
+use crate::error::NativeError;
+use crate::request::{NativeDns, NativeDnsProtocol, NativeIpFamily, NativeResolve};
+use crate::timing;
+
+tokio::task_local! {
+    static TARGET: (String, u16);
+}
+
+/// Resolver shared by every client, configured like wreq's built-in one:
+/// system configuration when readable, defaults otherwise, and both IPv4 and
+/// IPv6 lookups so connects can race the two families.
+static SYSTEM_RESOLVER: LazyLock<Arc<TokioResolver>> =
+    LazyLock::new(|| Arc::new(system_builder().build()));
+
+/// Resolvers built for `:dns` configurations or single-family lookups.
+/// Clients with the same settings share one resolver, and with it one cache.
+type ResolverKey = (Option<NativeDns>, NativeIpFamily);
+
+/// Most configured resolvers kept for sharing. Each one holds its own answer
+/// cache and nameserver connections, and few `:dns` settings are in use at
+/// once, so this is well below the client cache bound.
+const MAX_CACHED_RESOLVERS: usize = 32;
+
+/// Bounded, so cycling through `:dns` settings does not keep a resolver for
+/// each one. Resolvers still held by a client are never dropped as idle; an
+/// evicted one lives on with the clients using it.
+static CONFIGURED_RESOLVERS: LazyLock<Mutex<BoundedCache<ResolverKey, TokioResolver>>> =
+    LazyLock::new(|| {
+        Mutex::new(BoundedCache::new(
+            MAX_CACHED_RESOLVERS,
+            Some(cache::DEFAULT_IDLE_TIMEOUT),
+        ))
+    });
+
+/// Every configured resolver still alive, cached or not, so
+/// [`flush_caches`] also reaches those evicted while clients still use them.
+static LIVE_RESOLVERS: LazyLock<Mutex<Vec<Weak<TokioResolver>>>> =
+    LazyLock::new(|| Mutex::new(Vec::new()));
+
+fn system_builder() -> ResolverBuilder<TokioConnectionProvider> {
+    let mut builder = TokioResolver::builder_tokio().unwrap_or_else(|_| {
+        TokioResolver::builder_with_config(
+            ResolverConfig::default(),
+            TokioConnectionProvider::default(),
+        )
+    });
+    builder.options_mut().ip_strategy = LookupIpStrategy::Ipv4AndIpv6;
+    builder
+}
+
+/// The resolver for a `:dns` configuration and address family. Only IPv4-only
+/// and IPv6-only lookups need their own resolver; preferences reorder answers.
+fn resolver_for(
+    dns: Option<&NativeDns>,
+    family: NativeIpFamily,
+) -> Result<Arc<TokioResolver>, NativeError> {
+    let family = match family {
+        NativeIpFamily::Ipv4 | NativeIpFamily::Ipv6 => family,
+        _ => NativeIpFamily::Any,
+    };
+    if dns.is_none() && family == NativeIpFamily::Any {
+        return Ok(SYSTEM_RESOLVER.clone());
+    }
+
+    let key = (dns.cloned(), family);
+    let mut resolvers = CONFIGURED_RESOLVERS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    if let Some(resolver) = resolvers.get(&key) {
+        return Ok(resolver);
+    }
+
+    let resolver = Arc::new(build_resolver(dns, family)?);
+    resolvers.insert(key, resolver.clone());
+
+    let mut live = LIVE_RESOLVERS.lock().unwrap_or_else(|e| e.into_inner());
+    live.retain(|resolver| resolver.strong_count() > 0);
+    live.push(Arc::downgrade(&resolver));
+    Ok(resolver)
+}
+
+/// The configured resolvers that are still alive.
+fn live_resolvers() -> Vec<Arc<TokioResolver>> {
+    LIVE_RESOLVERS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .filter_map(Weak::upgrade)
+        .collect()
+}
+
+fn build_resolver(
+    dns: Option<&NativeDns>,
+    family: NativeIpFamily,
+) -> Result<TokioResolver, NativeError> {
+    let mut builder = match dns {
+        Some(dns) if !dns.nameservers.is_empty() => {
+            let config = ResolverConfig::from_parts(None, Vec::new(), nameserver_group(dns)?);
+            TokioResolver::builder_with_config(config, TokioConnectionProvider::default())
+        }
+        _ => system_builder(),
+    };
+
+    let options = builder.options_mut();
+    options.ip_strategy = match family {
+        NativeIpFamily::Ipv4 => LookupIpStrategy::Ipv4Only,
+        NativeIpFamily::Ipv6 => LookupIpStrategy::Ipv6Only,
+        _ => LookupIpStrategy::Ipv4AndIpv6,
+    };
+    if let Some(dns) = dns {
+        if let Some(cache_size) = dns.cache_size {
+            options.cache_size = usize::try_from(cache_size).unwrap_or(usize::MAX);
+        }
+        options.positive_min_ttl = dns.min_ttl_secs.map(Duration::from_secs);
+        options.positive_max_ttl = dns.max_ttl_secs.map(Duration::from_secs);
+    }
+
+    Ok(builder.build())
+}
+
+/// Nameservers for a `:dns` configuration. Plain DNS queries each server over
+/// UDP with TCP fallback; DoT and DoH verify the server as `tls_name`.
+fn nameserver_group(dns: &NativeDns) -> Result<NameServerConfigGroup, NativeError> {
+    let encrypted = match dns.protocol {
+        NativeDnsProtocol::Udp => None,
+        NativeDnsProtocol::Tls => Some(Protocol::Tls),
+        NativeDnsProtocol::Https => Some(Protocol::Https),
+    };
+    if encrypted.is_some() && dns.tls_name.is_none() {
+        return Err(NativeError::new(
+            "invalid_request",
+            "dns tls_name is required for encrypted DNS",
+            json!({}),
+        ));
+    }
+
+    let mut servers = Vec::new();
+    for nameserver in &dns.nameservers {
+        let socket_addr = nameserver.parse::<SocketAddr>().map_err(|_| {
+            NativeError::new(
+                "invalid_request",
+                "invalid dns nameserver",
+                json!({"value": nameserver}),
+            )
+        })?;
+
+        match encrypted {
+            Some(protocol) => {
+                let mut server = NameServerConfig::new(socket_addr, protocol);
+                server.tls_dns_name = dns.tls_name.clone();
+                servers.push(server);
+            }
+            None => {
+                servers.push(NameServerConfig::new(socket_addr, Protocol::Udp));
+                servers.push(NameServerConfig::new(socket_addr, Protocol::Tcp));
+            }
+        }
+    }
+
+    Ok(servers.into())
+}
+
+/// Drops every cached DNS answer, for the system resolver and every
+/// configured one still in use. Lookups already in flight are unaffected.
+pub fn flush_caches() {
+    SYSTEM_RESOLVER.clear_cache();
+    for resolver in live_resolvers() {
+        resolver.clear_cache();
+    }
+}
+
+/// Connector layer that runs every connect with its own host and port as the
+/// target, so the resolver can apply the `:resolve` override for that port.
+///
+/// wreq only hands the host name to the resolver. The target is taken from the
+/// connect itself rather than from the request that started it, so a connect
+/// that waits for a free connection, or that the pool finishes in the
+/// background after the request got another connection, still resolves
+/// through the override.
+#[derive(Debug, Clone, Copy)]
+pub struct TargetLayer;
+
+impl<S> Layer<S> for TargetLayer {
+    type Service = Targeted<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Targeted { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Targeted<S> {
+    inner: S,
+}
+
+impl<S> Service<Unnameable> for Targeted<S>
+where
+    S: Service<Unnameable, Response = Conn, Error = BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = Conn;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Conn, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Unnameable) -> Self::Future {
+        let target = target(request.uri());
+        Box::pin(TARGET.scope(target, self.inner.call(request)))
+    }
+}
+
+/// The host and port a connect goes to, with the port defaulted by scheme.
+fn target(uri: &Uri) -> (String, u16) {
+    let default_port = match uri.scheme_str() {
+        Some("https") => 443,
+        _ => 80,
+    };
+    (
+        uri.host().unwrap_or_default().to_ascii_lowercase(),
+        uri.port_u16().unwrap_or(default_port),
+    )
+}
+
+/// Rejects a `url` whose host is an IP literal outside `family`. Such hosts
+/// never reach the resolver.
+pub fn check_literal_host(family: NativeIpFamily, url: &Url) -> Result<(), NativeError> {
+    let allowed = match (family, url.host()) {
+        (NativeIpFamily::Ipv4, Some(Host::Ipv6(_))) => false,
+        (NativeIpFamily::Ipv6, Some(Host::Ipv4(_))) => false,
+        _ => true,
+    };
+    if allowed {
+        return Ok(());
+    }
+
+    Err(NativeError::new(
+        "transport_error",
+        "url host is outside the requested IP family",
+        json!({"host": url.host_str(), "ip_family": format!("{family:?}").to_lowercase()}),
+    ))
+}
+
+/// Hickory DNS resolver that applies `:resolve` overrides and the IP family,
+/// and reports lookup time to the request timing.
+#[derive(Debug, Clone)]
+pub struct TimedResolver {
+    resolver: Arc<TokioResolver>,
+    family: NativeIpFamily,
+    overrides: Arc<HashMap<(String, u16), Vec<IpAddr>>>,
+}
+
+impl TimedResolver {
+    pub fn new(
+        dns: Option<&NativeDns>,
+        family: NativeIpFamily,
+        overrides: &[NativeResolve],
+    ) -> Result<Self, NativeError> {
+        let mut map: HashMap<(String, u16), Vec<IpAddr>> = HashMap::new();
+        for entry in overrides {
+            let addresses = entry
+                .addresses
+                .iter()
+                .map(|address| {
+                    address.parse::<IpAddr>().map_err(|_| {
+                        NativeError::new(
+                            "invalid_request",
+                            "invalid resolve address",
+                            json!({"host": entry.host, "value": address}),
+                        )
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            map.entry((entry.host.to_ascii_lowercase(), entry.port))
+                .or_default()
+                .extend(addresses);
+        }
+
+        Ok(Self {
+            resolver: resolver_for(dns, family)?,
+            family,
+            overrides: Arc::new(map),
+        })
+    }
+
+    /// The override for `name`, if it is the host of the current connect, see
+    /// [`TargetLayer`]. Proxy host names are never overridden.
+    fn override_for(&self, name: &Name) -> Option<(Vec<IpAddr>, u16)> {
+        if self.overrides.is_empty() {
+            return None;
+        }
+
+        let name = name.as_str().to_ascii_lowercase();
+        TARGET
+            .try_with(|(host, port)| {
+                if *host != name {
+                    return None;
+                }
+                self.overrides
+                    .get(&(name, *port))
+                    .map(|addresses| (addresses.clone(), *port))
+            })
+            .ok()
+            .flatten()
+    }
+}
+
+/// Keeps the addresses of `family`, preferred family first.
+///
+/// wreq races the two families with the first address deciding the preferred
+/// one, so a stable sort is enough to express a preference.
+fn arrange(mut addresses: Vec<IpAddr>, family: NativeIpFamily) -> Vec<IpAddr> {
+    match family {
+        NativeIpFamily::Any => {}
+        NativeIpFamily::Ipv4 => addresses.retain(IpAddr::is_ipv4),
+        NativeIpFamily::Ipv6 => addresses.retain(IpAddr::is_ipv6),
+        NativeIpFamily::PreferIpv4 => addresses.sort_by_key(IpAddr::is_ipv6),
+        NativeIpFamily::PreferIpv6 => addresses.sort_by_key(IpAddr::is_ipv4),
+    }
+    addresses
+}
+
+impl Resolve for TimedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let overridden = self.override_for(&name);
+        let resolver = self.resolver.clone();
+        let family = self.family;
+
+        Box::pin(async move {
+            let (addresses, port) = match overridden {
+                Some(overridden) => overridden,
+                None => {
+                    let started = Instant::now();
+                    let lookup = resolver.lookup_ip(name.as_str()).await;
+                    timing::record_dns(started, Instant::now());
+                    (lookup?.into_iter().collect(), 0)
+                }
+            };
+
+            let addresses = arrange(addresses, family);
+            if addresses.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrNotAvailable,
+                    format!(
+                        "{} has no address in the requested IP family",
+                        name.as_str()
+                    ),
+                )
+                .into());
+            }
+
+            let addrs: Addrs = Box::new(
+                addresses
+                    .into_iter()
+                    .map(move |ip_addr| SocketAddr::new(ip_addr, port)),
+            );
+            Ok(addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolver(host: &str, port: u16, addresses: &[&str]) -> TimedResolver {
+        TimedResolver::new(
+            None,
+            NativeIpFamily::Any,
+            &[NativeResolve {
+                host: host.to_string(),
+                port,
+                addresses: addresses.iter().map(|a| a.to_string()).collect(),
+            }],
+        )
+        .expect("overrides should parse")
+    }
+
+    fn lookup(resolver: &TimedResolver, name: &str, host: &str, port: u16) -> Vec<SocketAddr> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .expect("runtime should build");
+        let name = Name::from(name);
+
+        runtime
+            .block_on(TARGET.scope((host.to_string(), port), resolver.resolve(name)))
+            .expect("override should resolve")
+            .collect()
+    }
+
+    #[test]
+    fn overrides_host_on_matching_port() {
+        let resolver = resolver("API.example.com", 443, &["127.0.0.1", "::1"]);
+
+        assert_eq!(
+            lookup(&resolver, "api.example.com", "api.example.com", 443),
+            vec![
+                "127.0.0.1:443".parse::<SocketAddr>().unwrap(),
+                "[::1]:443".parse().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn only_overrides_target_host_and_port() {
+        let resolver = resolver("api.example.com", 443, &["127.0.0.1"]);
+        let name = Name::from("api.example.com");
+
+        let other_port = TARGET.sync_scope(("api.example.com".to_string(), 8443), || {
+            resolver.override_for(&name)
+        });
+        assert!(other_port.is_none());
+
+        let other_host = TARGET.sync_scope(("proxy.example.com".to_string(), 443), || {
+            resolver.override_for(&name)
+        });
+        assert!(other_host.is_none());
+    }
+
+    #[test]
+    fn targets_the_host_and_port_of_the_connect() {
+        let target = |uri: &str| target(&uri.parse().expect("uri should parse"));
+
+        assert_eq!(
+            target("https://API.example.com/"),
+            ("api.example.com".to_string(), 443)
+        );
+        assert_eq!(
+            target("http://api.example.com"),
+            ("api.example.com".to_string(), 80)
+        );
+        assert_eq!(
+            target("http://api.example.com:8080"),
+            ("api.example.com".to_string(), 8080)
+        );
+    }
+
+    fn dns(protocol: NativeDnsProtocol, tls_name: Option<&str>) -> NativeDns {
+        NativeDns {
+            nameservers: vec![
+                "1.1.1.1:853".to_string(),
+                "[2606:4700::1111]:853".to_string(),
+            ],
+            protocol,
+            tls_name: tls_name.map(str::to_string),
+            cache_size: Some(0),
+            min_ttl_secs: None,
+            max_ttl_secs: Some(60),
+        }
+    }
+
+    #[test]
+    fn plain_nameservers_use_udp_and_tcp() {
+        let servers = nameserver_group(&dns(NativeDnsProtocol::Udp, None))
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(servers.len(), 4);
+        assert_eq!(servers[0].protocol, Protocol::Udp);
+        assert_eq!(servers[1].protocol, Protocol::Tcp);
+    }
+
+    #[test]
+    fn encrypted_nameservers_require_tls_name() {
+        let servers = nameserver_group(&dns(NativeDnsProtocol::Tls, Some("one.one.one.one")))
+            .unwrap()
+            .into_inner();
+        assert_eq!(servers.len(), 2);
+        assert_eq!(servers[0].protocol, Protocol::Tls);
+        assert_eq!(servers[0].tls_dns_name.as_deref(), Some("one.one.one.one"));
+
+        let error = nameserver_group(&dns(NativeDnsProtocol::Https, None))
+            .expect_err("DoH needs a server name");
+        assert_eq!(error.message, "dns tls_name is required for encrypted DNS");
+    }
+
+    #[test]
+    fn applies_cache_options_and_shares_resolvers() {
+        let config = dns(NativeDnsProtocol::Udp, None);
+        let resolver = resolver_for(Some(&config), NativeIpFamily::PreferIpv6).unwrap();
+
+        assert_eq!(resolver.options().cache_size, 0);
+        assert_eq!(
+            resolver.options().positive_max_ttl,
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(resolver.config().name_servers().len(), 4);
+        let shared = CONFIGURED_RESOLVERS
+            .lock()
+            .unwrap()
+            .get(&(Some(config), NativeIpFamily::Any))
+            .expect("resolver should be cached");
+        assert!(Arc::ptr_eq(&shared, &resolver));
+    }
+
+    #[test]
+    fn evicted_resolvers_stay_reachable_while_in_use() {
+        let mut config = dns(NativeDnsProtocol::Udp, None);
+        config.cache_size = Some(7);
+        let resolver = resolver_for(Some(&config), NativeIpFamily::Ipv4).unwrap();
+
+        CONFIGURED_RESOLVERS
+            .lock()
+            .unwrap()
+            .remove(&(Some(config), NativeIpFamily::Ipv4));
+
+        assert!(live_resolvers()
+            .iter()
+            .any(|live| Arc::ptr_eq(live, &resolver)));
+    }
+
+    #[test]
+    fn single_family_lookups_get_their_own_resolver() {
+        let resolver = resolver_for(None, NativeIpFamily::Ipv6).unwrap();
+
+        assert_eq!(resolver.options().ip_strategy, LookupIpStrategy::Ipv6Only);
+        assert_eq!(
+            resolver_for(None, NativeIpFamily::PreferIpv4)
+                .unwrap()
+                .options()
+                .ip_strategy,
+            LookupIpStrategy::Ipv4AndIpv6
+        );
+    }
+
+    #[test]
+    fn rejects_literal_hosts_outside_family() {
+        let v4 = Url::parse("http://127.0.0.1/").unwrap();
+        let v6 = Url::parse("http://[::1]/").unwrap();
+
+        assert!(check_literal_host(NativeIpFamily::Ipv4, &v4).is_ok());
+        assert!(check_literal_host(NativeIpFamily::PreferIpv6, &v4).is_ok());
+        let error = check_literal_host(NativeIpFamily::Ipv4, &v6).expect_err("family mismatch");
+        assert_eq!(error.type_name, "transport_error");
+        assert!(check_literal_host(NativeIpFamily::Ipv6, &v4).is_err());
+    }
+
+    #[test]
+    fn arranges_addresses_by_family() {
+        let addresses: Vec<IpAddr> = vec![
+            "::1".parse().unwrap(),
+            "127.0.0.1".parse().unwrap(),
+            "::2".parse().unwrap(),
+        ];
+        let v4: IpAddr = "127.0.0.1".parse().unwrap();
+
+        assert_eq!(arrange(addresses.clone(), NativeIpFamily::Any), addresses);
+        assert_eq!(arrange(addresses.clone(), NativeIpFamily::Ipv4), vec![v4]);
+        assert_eq!(
+            arrange(addresses.clone(), NativeIpFamily::Ipv6),
+            vec![addresses[0], addresses[2]]
+        );
+        assert_eq!(
+            arrange(addresses.clone(), NativeIpFamily::PreferIpv4),
+            vec![v4, addresses[0], addresses[2]]
+        );
+        assert_eq!(
+            arrange(addresses.clone(), NativeIpFamily::PreferIpv6),
+            vec![addresses[0], addresses[2], v4]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_override_address() {
+        let error = TimedResolver::new(
+            None,
+            NativeIpFamily::Any,
+            &[NativeResolve {
+                host: "api.example.com".to_string(),
+                port: 443,
+                addresses: vec!["not-an-ip".to_string()],
+            }],
+        )
+        .expect_err("address must be an IP");
+
+        assert_eq!(error.message, "invalid resolve address");
+    }
+}
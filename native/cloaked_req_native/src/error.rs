@@ -1,7 +1,9 @@
+use std::fmt;
+
 use serde::Serialize;
 use serde_json::{json, Value};
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct NativeError {
     #[serde(rename = "type")]
     pub type_name: String,
@@ -33,6 +35,16 @@ impl NativeError {
     }
 }
 
+impl fmt::Display for NativeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Lets connector layers fail a connection with a specific error, which
+/// [`crate::timeouts::transport_error`] finds again in the wreq error chain.
+impl std::error::Error for NativeError {}
+
 #[cfg(test)]
 mod tests {
     use super::NativeError;
@@ -1,26 +1,55 @@
+mod cache;
+mod cookie_file;
+mod cookies;
+mod dns;
 mod error;
+mod pool;
+mod redirect;
+mod reply;
 mod request;
 mod response;
+mod stats;
+mod timeouts;
+mod timing;
+mod tls;
 
-use std::collections::HashMap;
+use std::any::Any;
+use std::future::Future;
 use std::panic::{catch_unwind, AssertUnwindSafe};
-use std::sync::{LazyLock, RwLock};
-use std::time::Duration;
+use std::sync::{Arc, LazyLock, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
+use cache::BoundedCache;
+use cookie_file::CookieFile;
+use cookies::{CookieStore, NativeCookieFormat, StoredCookie};
 use error::NativeError;
-use request::NativeRequest;
-use response::NativeResponseMeta;
+use pool::PooledClient;
+use reply::{Gate, Reply};
+use request::{
+    NativeDns, NativeHttp2KeepAlive, NativeIpFamily, NativePin, NativeProxy, NativeRedirectMode,
+    NativeRequest, NativeResolve,
+};
+use response::{DerBinary, NativeRedirectHop, NativeResponseMeta, NativeTiming, NativeTlsInfo};
 use rustler::serde::SerdeTerm;
 use rustler::types::binary::{Binary, NewBinary};
-use rustler::{Encoder, Env, ResourceArc, Term};
+use rustler::{Atom, Encoder, Env, LocalPid, Monitor, ResourceArc, Term};
 use serde_json::{json, Value};
-use wreq::cookie::{CookieStore, Cookies};
-use wreq::{Client, Method};
+use stats::{Clock, NativeClientStats};
+use tokio::sync::Semaphore;
+use tokio::task::AbortHandle;
+use tower::timeout::TimeoutLayer;
+use url::Url;
+use wreq::http2::Http2Options;
+use wreq::tls::{KeyLog, TlsInfo};
+use wreq::{Client, ClientBuilder, EmulationFactory, Method, Proxy};
 use wreq_util::Emulation;
 
 rustler::atoms! {
     ok,
-    error
+    error,
+    response_head = "response",
+    data,
+    done
 }
 
 /// Shared tokio runtime for all NIF calls. Created once on first use.
@@ -31,54 +60,294 @@ static RUNTIME: LazyLock<tokio::runtime::Runtime> = LazyLock::new(|| {
         .expect("tokio runtime must initialize")
 });
 
-/// Cache key: (emulation profile, insecure_skip_verify, local_address).
-type ClientKey = (Option<String>, bool, Option<String>);
+/// Cache key: every request option that changes how the client is built.
+///
+/// Certificate material is represented by SHA-256 fingerprints rather than
+/// kept in the key itself.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ClientKey {
+    emulation: Option<String>,
+    insecure_skip_verify: bool,
+    local_address: Option<String>,
+    interface: Option<String>,
+    proxy: Option<NativeProxy>,
+    client_certificate: Option<String>,
+    ca_certificates: Option<String>,
+    pins: Vec<NativePin>,
+    tls_keylog: Option<String>,
+    resolve: Vec<NativeResolve>,
+    dns: Option<NativeDns>,
+    ip_family: NativeIpFamily,
+    happy_eyeballs_ms: Option<u64>,
+    connect_timeout_ms: u64,
+    pool_idle_timeout_ms: Option<u64>,
+    pool_max_idle_per_host: usize,
+    max_connections_per_host: Option<u32>,
+    http2_keep_alive: Option<NativeHttp2KeepAlive>,
+}
+
+impl ClientKey {
+    fn new(request: &NativeRequest) -> Self {
+        Self {
+            emulation: request.emulation.clone(),
+            insecure_skip_verify: request.insecure_skip_verify,
+            local_address: request.local_address.clone(),
+            interface: request.interface.clone(),
+            proxy: request.proxy.clone(),
+            client_certificate: request
+                .client_certificate
+                .as_ref()
+                .map(tls::client_certificate_fingerprint),
+            ca_certificates: request
+                .ca_certificates
+                .as_ref()
+                .map(tls::ca_certificates_fingerprint),
+            pins: request.pins.clone(),
+            tls_keylog: request.tls_keylog.clone(),
+            resolve: request.resolve.clone(),
+            dns: request.dns.clone(),
+            ip_family: request.ip_family,
+            happy_eyeballs_ms: request.happy_eyeballs_ms,
+            connect_timeout_ms: request.connect_timeout_ms,
+            pool_idle_timeout_ms: request.pool_idle_timeout_ms,
+            pool_max_idle_per_host: request.pool_max_idle_per_host,
+            max_connections_per_host: request.max_connections_per_host,
+            http2_keep_alive: request.http2_keep_alive.clone(),
+        }
+    }
+}
+
+/// Proxy schemes accepted by `:proxy`. `socks5h` resolves hostnames on the proxy.
+const PROXY_SCHEMES: [&str; 4] = ["http", "https", "socks5", "socks5h"];
+
+/// How often clients idle past the cache's idle timeout are dropped.
+const CACHE_REAP_INTERVAL: Duration = Duration::from_secs(30);
 
 /// Persistent client pool. Clients are reused across NIF calls for connection
 /// pooling, TLS session resumption, and HTTP keep-alive.
-static CLIENT_CACHE: LazyLock<RwLock<HashMap<ClientKey, Client>>> =
-    LazyLock::new(|| RwLock::new(HashMap::new()));
+///
+/// The cache is bounded, so rotating through many profiles or source addresses
+/// does not keep a client and its connections alive for each one. Requests
+/// hold their own reference, so evicting a client never breaks them; its
+/// connections close once the last of them finishes.
+static CLIENT_CACHE: LazyLock<RwLock<BoundedCache<ClientKey, PooledClient>>> =
+    LazyLock::new(|| {
+        RUNTIME.spawn(reap_idle_clients());
+        RwLock::new(BoundedCache::new(
+            cache::DEFAULT_MAX_ENTRIES,
+            Some(cache::DEFAULT_IDLE_TIMEOUT),
+        ))
+    });
+
+async fn reap_idle_clients() {
+    loop {
+        tokio::time::sleep(CACHE_REAP_INTERVAL).await;
+        CLIENT_CACHE
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .reap();
+    }
+}
 
 /// Opaque cookie jar resource held by the BEAM.
 ///
-/// Wraps wreq's `Jar` (RFC 6265-compliant cookie store). The jar is
-/// automatically dropped when the Elixir term is garbage collected.
+/// Wraps an RFC 6265 [`CookieStore`], optionally bound to a file it is saved
+/// to after every change. The jar is automatically dropped when the Elixir
+/// term is garbage collected.
 struct CookieJarResource {
-    jar: wreq::cookie::Jar,
+    jar: Arc<CookieStore>,
+    file: Option<Arc<CookieFile>>,
 }
 
 impl rustler::Resource for CookieJarResource {}
 
-fn get_or_build_client(
-    emulation: Option<&str>,
-    insecure_skip_verify: bool,
-    local_address: Option<&str>,
-) -> Result<Client, NativeError> {
-    let key = (
-        emulation.map(|s| s.to_string()),
-        insecure_skip_verify,
-        local_address.map(|s| s.to_string()),
-    );
+impl CookieJarResource {
+    fn in_memory(jar: CookieStore) -> Self {
+        Self {
+            jar: Arc::new(jar),
+            file: None,
+        }
+    }
 
-    // Fast path: read lock
-    {
-        let cache = CLIENT_CACHE.read().unwrap_or_else(|e| e.into_inner());
-        if let Some(client) = cache.get(&key) {
-            return Ok(client.clone());
+    /// Writes the jar to its file, if it has one and changed since the last
+    /// save.
+    fn save(&self) -> Result<(), NativeError> {
+        match &self.file {
+            Some(file) => file.save(&self.jar),
+            None => Ok(()),
         }
     }
 
-    // Slow path: write lock, double-check
+    /// Saves after a change, on the blocking pool so that neither a scheduler
+    /// nor a runtime worker waits for the disk. Changes made while a save is
+    /// queued are written by that save. A failure does not fail the change:
+    /// it is kept for `nif_cookie_jar_save_error`, and the next change or
+    /// `CookieJar.save/1` retries.
+    fn save_in_background(&self) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        if !file.queue() {
+            return;
+        }
+
+        let file = file.clone();
+        let jar = self.jar.clone();
+        RUNTIME.spawn_blocking(move || file.save_queued(&jar));
+    }
+}
+
+/// Client built by `nif_create_client`, owned by the BEAM.
+///
+/// It lives outside `CLIENT_CACHE`: it is never evicted, its connections are
+/// not shared with any other client, and it is dropped once no Elixir term and
+/// no request in flight refers to it any more.
+struct ClientResource {
+    client: Arc<PooledClient>,
+}
+
+impl rustler::Resource for ClientResource {}
+
+/// Handle for a request started with `nif_start_request`, `nif_start_stream` or
+/// `nif_preconnect`.
+///
+/// The handle term tags every reply message. Cancelling it aborts the request
+/// task, which drops the connection. The calling process is monitored, so the
+/// request is also cancelled when that process exits.
+#[derive(Default)]
+struct RequestHandle {
+    abort: Mutex<Option<AbortHandle>>,
+    demand: Demand,
+    gate: Gate,
+}
+
+/// Body chunks the caller has asked for with `nif_demand_chunk`. A streaming
+/// task reads the next chunk only once it has been asked for, so a slow
+/// consumer holds back the transfer instead of filling its mailbox.
+#[derive(Clone, Default)]
+struct Demand(Arc<Semaphore>);
+
+impl Demand {
+    fn grant(&self) {
+        self.0.add_permits(1);
+    }
+
+    async fn wait(&self) {
+        // The semaphore is never closed, so acquiring only fails on a bug.
+        if let Ok(permit) = self.0.acquire().await {
+            permit.forget();
+        }
+    }
+}
+
+impl RequestHandle {
+    fn arm(&self, abort: AbortHandle) {
+        *self.abort.lock().unwrap_or_else(|e| e.into_inner()) = Some(abort);
+    }
+
+    /// Aborts the task. The task stops at its next await, so the gate is
+    /// closed first: no reply is sent once this returns.
+    fn cancel(&self) {
+        self.gate.close();
+        if let Some(abort) = self
+            .abort
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+        {
+            abort.abort();
+        }
+    }
+}
+
+impl rustler::Resource for RequestHandle {
+    const IMPLEMENTS_DOWN: bool = true;
+
+    fn down<'a>(&'a self, _env: Env<'a>, _pid: LocalPid, _monitor: Monitor) {
+        self.cancel();
+    }
+}
+
+/// The cached client for `key`, if there is one.
+fn cached_client(key: &ClientKey) -> Option<Arc<PooledClient>> {
+    CLIENT_CACHE
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(key)
+}
+
+fn get_or_build_client(request: &NativeRequest) -> Result<Arc<PooledClient>, NativeError> {
+    let key = ClientKey::new(request);
+    if let Some(client) = cached_client(&key) {
+        return Ok(client);
+    }
+
+    // Build without the lock: loading certificates and DNS configuration
+    // must not hold up requests that use other clients.
+    let client = Arc::new(build_client(request)?);
+
+    // Another request may have built the same client meanwhile. Keep the
+    // first one, so both share its connection pool.
     let mut cache = CLIENT_CACHE.write().unwrap_or_else(|e| e.into_inner());
-    if let Some(client) = cache.get(&key) {
-        return Ok(client.clone());
+    if let Some(existing) = cache.get(&key) {
+        return Ok(existing);
     }
+    cache.insert(key, client.clone());
+    Ok(client)
+}
 
+/// The client handle's client if there is one, the cached client for the
+/// request's options otherwise. A client missing from the cache is built on
+/// the blocking pool, so building it does not stall a runtime worker.
+async fn client_for(
+    request: &NativeRequest,
+    handle: Option<&ClientResource>,
+) -> Result<Arc<PooledClient>, NativeError> {
+    if let Some(handle) = handle {
+        return Ok(handle.client.clone());
+    }
+    if let Some(client) = cached_client(&ClientKey::new(request)) {
+        return Ok(client);
+    }
+
+    let request = request.clone();
+    match tokio::task::spawn_blocking(move || get_or_build_client(&request)).await {
+        Ok(result) => result,
+        Err(join_error) => std::panic::resume_unwind(join_error.into_panic()),
+    }
+}
+
+/// Builds a client from every request option that shapes one, see [`ClientKey`].
+fn build_client(request: &NativeRequest) -> Result<PooledClient, NativeError> {
     let mut builder = Client::builder()
-        .pool_max_idle_per_host(20)
-        .connect_timeout(Duration::from_secs(10));
+        .pool_idle_timeout(request.pool_idle_timeout_ms.map(Duration::from_millis))
+        .pool_max_idle_per_host(request.pool_max_idle_per_host)
+        .dns_resolver(dns::TimedResolver::new(
+            request.dns.as_ref(),
+            request.ip_family,
+            &request.resolve,
+        )?)
+        .tcp_happy_eyeballs_timeout(request.happy_eyeballs_ms.map(Duration::from_millis))
+        .connector_layer(dns::TargetLayer)
+        .connector_layer(timing::ConnectTimingLayer)
+        .tls_info(true);
+
+    if !request.pins.is_empty() {
+        builder = builder.connector_layer(tls::PinCheckLayer::new(&request.pins));
+    }
 
-    if let Some(profile_name) = emulation {
+    let origins = pool::Origins::new(request.max_connections_per_host);
+    let connect_timeout = Duration::from_millis(request.connect_timeout_ms);
+    builder = match request.max_connections_per_host {
+        // wreq's connect timeout wraps every connector layer, so it would also
+        // run while waiting for a free connection. It goes under the limit
+        // instead, which also means it is no longer split between addresses.
+        Some(_) => builder.connector_layer(TimeoutLayer::new(connect_timeout)),
+        None => builder.connect_timeout(connect_timeout),
+    }
+    .connector_layer(pool::ConnectionLayer::new(origins.clone()));
+
+    if let Some(profile_name) = request.emulation.as_deref() {
         let profile: Emulation = serde_json::from_value(Value::String(profile_name.to_string()))
             .map_err(|reason| {
                 NativeError::new(
@@ -88,10 +357,21 @@ fn get_or_build_client(
                 )
             })?;
 
-        builder = builder.emulation(profile);
+        let mut emulation = profile.emulation();
+        if let Some(keep_alive) = &request.http2_keep_alive {
+            let http2 = emulation
+                .http2_options_mut()
+                .get_or_insert_with(Http2Options::default);
+            apply_http2_keep_alive(http2, keep_alive);
+        }
+        builder = builder.emulation(emulation);
+    } else if let Some(keep_alive) = &request.http2_keep_alive {
+        let mut http2 = Http2Options::default();
+        apply_http2_keep_alive(&mut http2, keep_alive);
+        builder = builder.http2_options(http2);
     }
 
-    if let Some(addr_str) = local_address {
+    if let Some(addr_str) = request.local_address.as_deref() {
         let addr: std::net::IpAddr = addr_str.parse().map_err(|_| {
             NativeError::new(
                 "invalid_request",
@@ -102,10 +382,30 @@ fn get_or_build_client(
         builder = builder.local_address(addr);
     }
 
-    if insecure_skip_verify {
+    if let Some(name) = request.interface.as_deref() {
+        builder = bind_interface(builder, name)?;
+    }
+
+    if let Some(proxy) = &request.proxy {
+        builder = builder.proxy(build_proxy(proxy)?);
+    }
+
+    if let Some(certificate) = &request.client_certificate {
+        builder = builder.identity(tls::client_identity(certificate)?);
+    }
+
+    if let Some(ca_certificates) = &request.ca_certificates {
+        builder = builder.cert_store(tls::ca_cert_store(ca_certificates)?);
+    }
+
+    if request.insecure_skip_verify {
         builder = builder.cert_verification(false);
     }
 
+    if let Some(path) = request.tls_keylog.as_deref() {
+        builder = builder.keylog(KeyLog::from_file(path));
+    }
+
     let client = builder.build().map_err(|reason| {
         NativeError::new(
             "transport_error",
@@ -114,8 +414,102 @@ fn get_or_build_client(
         )
     })?;
 
-    cache.insert(key, client.clone());
-    Ok(client)
+    Ok(PooledClient::new(client, origins))
+}
+
+/// Turns on HTTP/2 PINGs in `http2`, which may carry an emulation profile's
+/// fingerprinted settings that must be kept as they are.
+fn apply_http2_keep_alive(http2: &mut Http2Options, keep_alive: &NativeHttp2KeepAlive) {
+    http2.keep_alive_interval = Some(Duration::from_millis(keep_alive.interval_ms));
+    http2.keep_alive_timeout = Duration::from_millis(keep_alive.timeout_ms);
+    http2.keep_alive_while_idle = keep_alive.while_idle;
+}
+
+/// Binds every socket of the client to the network interface `name`
+/// (`SO_BINDTODEVICE` on Linux, `IP_BOUND_IF` on Apple platforms).
+#[cfg(any(
+    target_os = "android",
+    target_os = "fuchsia",
+    target_os = "illumos",
+    target_os = "ios",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "solaris",
+    target_os = "tvos",
+    target_os = "visionos",
+    target_os = "watchos",
+))]
+fn bind_interface(builder: ClientBuilder, name: &str) -> Result<ClientBuilder, NativeError> {
+    // Same rules as the Linux kernel: at most 15 bytes, no `/`, `:` or whitespace.
+    let valid = !name.is_empty()
+        && name.len() < 16
+        && name != "."
+        && name != ".."
+        && !name
+            .chars()
+            .any(|c| c == '/' || c == ':' || c == '\0' || c.is_whitespace());
+    if !valid {
+        return Err(NativeError::new(
+            "invalid_request",
+            "invalid interface",
+            json!({"value": name}),
+        ));
+    }
+
+    Ok(builder.interface(name.to_string()))
+}
+
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "fuchsia",
+    target_os = "illumos",
+    target_os = "ios",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "solaris",
+    target_os = "tvos",
+    target_os = "visionos",
+    target_os = "watchos",
+)))]
+fn bind_interface(_builder: ClientBuilder, name: &str) -> Result<ClientBuilder, NativeError> {
+    Err(NativeError::new(
+        "invalid_request",
+        "interface is not supported on this platform",
+        json!({"value": name}),
+    ))
+}
+
+/// Builds a wreq `Proxy` that intercepts all traffic. Error details never
+/// include the proxy URL, since it may carry credentials.
+fn build_proxy(proxy: &NativeProxy) -> Result<Proxy, NativeError> {
+    let scheme = proxy
+        .url
+        .parse::<http::Uri>()
+        .ok()
+        .and_then(|uri| uri.scheme_str().map(|s| s.to_ascii_lowercase()))
+        .ok_or_else(|| NativeError::new("invalid_request", "invalid proxy url", json!({})))?;
+
+    if !PROXY_SCHEMES.contains(&scheme.as_str()) {
+        return Err(NativeError::new(
+            "invalid_request",
+            "unsupported proxy scheme",
+            json!({"scheme": scheme}),
+        ));
+    }
+
+    let mut built = Proxy::all(proxy.url.as_str()).map_err(|reason| {
+        NativeError::new(
+            "invalid_request",
+            "invalid proxy url",
+            json!({"reason": reason.to_string()}),
+        )
+    })?;
+
+    if let Some(username) = proxy.username.as_deref() {
+        built = built.basic_auth(username, proxy.password.as_deref().unwrap_or_default());
+    }
+
+    Ok(built)
 }
 
 fn run_with_panic_protection<T, F>(f: F) -> Result<T, NativeError>
@@ -124,87 +518,590 @@ where
 {
     match catch_unwind(AssertUnwindSafe(f)) {
         Ok(result) => result,
-        Err(panic_info) => {
-            let message = panic_info
-                .downcast_ref::<String>()
-                .map(|s| s.as_str())
-                .or_else(|| panic_info.downcast_ref::<&str>().copied())
-                .unwrap_or("unknown panic");
-            Err(NativeError::new("nif_panic", message, json!({})))
-        }
+        Err(panic_info) => Err(NativeError::new(
+            "nif_panic",
+            panic_message(panic_info.as_ref()),
+            json!({}),
+        )),
     }
 }
 
+fn panic_message(panic_info: &(dyn Any + Send)) -> &str {
+    panic_info
+        .downcast_ref::<String>()
+        .map(|s| s.as_str())
+        .or_else(|| panic_info.downcast_ref::<&str>().copied())
+        .unwrap_or("unknown panic")
+}
+
 /// Creates a new empty cookie jar.
 #[rustler::nif]
 fn nif_create_cookie_jar() -> ResourceArc<CookieJarResource> {
-    ResourceArc::new(CookieJarResource {
-        jar: wreq::cookie::Jar::default(),
-    })
+    ResourceArc::new(CookieJarResource::in_memory(CookieStore::default()))
+}
+
+/// Loads the jar saved at `path`, or starts an empty one if there is no file,
+/// and binds it to `path`. Returns `{:ok, jar}` or `{:error, error_map}`.
+#[rustler::nif(schedule = "DirtyIo")]
+fn nif_open_cookie_jar<'a>(env: Env<'a>, path: String, format: NativeCookieFormat) -> Term<'a> {
+    match CookieFile::open(&path, format) {
+        Ok((file, jar)) => {
+            let jar = ResourceArc::new(CookieJarResource {
+                jar: Arc::new(jar),
+                file: Some(Arc::new(file)),
+            });
+            (ok(), jar).encode(env)
+        }
+        Err(native_error) => encode_error(env, native_error),
+    }
+}
+
+/// Writes a file-backed jar to its file now. Returns `:ok` or
+/// `{:error, error_map}`.
+#[rustler::nif(schedule = "DirtyIo")]
+fn nif_save_cookie_jar<'a>(env: Env<'a>, jar: ResourceArc<CookieJarResource>) -> Term<'a> {
+    match jar.save() {
+        Ok(()) => ok().encode(env),
+        Err(native_error) => encode_error(env, native_error),
+    }
+}
+
+/// Returns `{:error, error_map}` with the reason the last save of a
+/// file-backed jar failed, or `:ok` when it succeeded or there was none.
+#[rustler::nif]
+fn nif_cookie_jar_save_error<'a>(env: Env<'a>, jar: ResourceArc<CookieJarResource>) -> Term<'a> {
+    match jar.file.as_ref().and_then(|file| file.last_error()) {
+        Some(native_error) => encode_error(env, native_error),
+        None => ok().encode(env),
+    }
+}
+
+/// Serializes every unexpired cookie in `jar` as JSON or Netscape cookies.txt.
+/// Runs on a dirty CPU scheduler, since a jar may hold many cookies.
+#[rustler::nif(schedule = "DirtyCpu")]
+fn nif_export_cookie_jar(
+    jar: ResourceArc<CookieJarResource>,
+    format: NativeCookieFormat,
+) -> String {
+    jar.jar.export(format)
+}
+
+/// Builds a new jar from an export and returns `{:ok, jar}`, or
+/// `{:error, error_map}` for malformed data. Runs on a dirty CPU scheduler,
+/// since exports can be large.
+#[rustler::nif(schedule = "DirtyCpu")]
+fn nif_import_cookie_jar<'a>(env: Env<'a>, data: String, format: NativeCookieFormat) -> Term<'a> {
+    match cookies::import(&data, format) {
+        Ok(imported) => {
+            let jar = ResourceArc::new(CookieJarResource::in_memory(CookieStore::from_cookies(
+                imported,
+            )));
+            (ok(), jar).encode(env)
+        }
+        Err(native_error) => encode_error(env, native_error),
+    }
+}
+
+/// Every unexpired cookie in `jar`, sorted by domain, path and name. Runs on
+/// a dirty CPU scheduler like the export, which walks the jar the same way.
+#[rustler::nif(schedule = "DirtyCpu")]
+fn nif_cookie_jar_cookies(jar: ResourceArc<CookieJarResource>) -> Vec<StoredCookie> {
+    jar.jar.cookies()
+}
+
+/// Returns `{:ok, cookies}` with the cookies `jar` sends to `url`, in header
+/// order, or `{:error, error_map}` for an invalid URL.
+///
+/// This and the NIFs changing one cookie stay on normal schedulers: they only
+/// look at the domains of one host. The jar's lock is held for in-memory work
+/// only, never across a request, an await or a file write; walks over the
+/// whole jar happen on dirty schedulers or the save thread.
+#[rustler::nif]
+fn nif_cookie_jar_cookies_for<'a>(
+    env: Env<'a>,
+    jar: ResourceArc<CookieJarResource>,
+    url: String,
+) -> Term<'a> {
+    match cookie_url(&url) {
+        Ok(url) => (ok(), jar.jar.cookies_for(&url)).encode(env),
+        Err(native_error) => encode_error(env, native_error),
+    }
+}
+
+/// Stores `set_cookie` as if `url` had sent it in a `set-cookie` header, with
+/// the same domain checks. Returns `:ok` or `{:error, error_map}`.
+#[rustler::nif]
+fn nif_put_cookie<'a>(
+    env: Env<'a>,
+    jar: ResourceArc<CookieJarResource>,
+    url: String,
+    set_cookie: String,
+) -> Term<'a> {
+    match put_cookie(&jar.jar, &url, &set_cookie) {
+        Ok(()) => {
+            jar.save_in_background();
+            ok().encode(env)
+        }
+        Err(native_error) => encode_error(env, native_error),
+    }
+}
+
+/// Removes one cookie and returns whether `jar` had it.
+#[rustler::nif]
+fn nif_delete_cookie(
+    jar: ResourceArc<CookieJarResource>,
+    name: String,
+    domain: String,
+    path: String,
+) -> bool {
+    let removed = jar.jar.remove(&name, &domain, &path);
+    if removed {
+        jar.save_in_background();
+    }
+    removed
+}
+
+/// Removes every cookie. Runs on a dirty CPU scheduler, since dropping a large
+/// jar frees each of its cookies.
+#[rustler::nif(schedule = "DirtyCpu")]
+fn nif_clear_cookie_jar(jar: ResourceArc<CookieJarResource>) -> Atom {
+    jar.jar.clear();
+    jar.save_in_background();
+    ok()
+}
+
+fn put_cookie(jar: &CookieStore, url: &str, set_cookie: &str) -> Result<(), NativeError> {
+    let url = cookie_url(url)?;
+    let host = url.host_str().unwrap_or_default();
+    if !is_cookie_domain_safe(set_cookie.as_bytes(), host) {
+        return Err(NativeError::new(
+            "invalid_request",
+            "cookie domain is a public suffix or does not match the url host",
+            json!({"host": host}),
+        ));
+    }
+    if !jar.set_cookie(set_cookie, &url) {
+        return Err(NativeError::new(
+            "invalid_request",
+            "invalid set-cookie value",
+            json!({}),
+        ));
+    }
+    Ok(())
+}
+
+/// Parses the URL a cookie is sent to or received from, which must be an
+/// `http` or `https` URL.
+fn cookie_url(url: &str) -> Result<Url, NativeError> {
+    let invalid = |reason: String| {
+        NativeError::new(
+            "invalid_request",
+            "url must be an http or https URL",
+            json!({"url": url, "reason": reason}),
+        )
+    };
+    let parsed = Url::parse(url).map_err(|reason| invalid(reason.to_string()))?;
+    if !matches!(parsed.scheme(), "http" | "https") || !parsed.has_host() {
+        return Err(invalid("unsupported scheme or missing host".to_string()));
+    }
+    Ok(parsed)
+}
+
+/// Builds a client from the client options of a request map and returns
+/// `{:ok, client}`, or `{:error, error_map}` for invalid options. Requests
+/// passed the handle use its client instead of a cached one.
+#[rustler::nif(schedule = "DirtyIo")]
+fn nif_create_client<'a>(env: Env<'a>, request: NativeRequest) -> Term<'a> {
+    let _runtime = RUNTIME.enter();
+
+    match run_with_panic_protection(|| build_client(&request)) {
+        Ok(client) => {
+            let handle = ResourceArc::new(ClientResource {
+                client: Arc::new(client),
+            });
+            (ok(), handle).encode(env)
+        }
+        Err(native_error) => encode_error(env, native_error),
+    }
 }
 
 /// NIF entry point. Receives a native Elixir map (decoded via NifMap) + optional raw body binary
-/// + optional cookie jar resource.
+/// + optional cookie jar resource + optional client handle.
 /// Returns `{:ok, response_meta_map, body_binary}` or `{:error, error_map}`.
+///
+/// Holds the dirty IO scheduler until the request completes. It cannot be
+/// cancelled; requests that must be go through `nif_start_request`.
 #[rustler::nif(schedule = "DirtyIo")]
 fn nif_perform_request<'a>(
     env: Env<'a>,
     request: NativeRequest,
     body: Option<Binary>,
     cookie_jar: Option<ResourceArc<CookieJarResource>>,
+    client: Option<ResourceArc<ClientResource>>,
 ) -> Term<'a> {
     let body_vec = body.map(|b| b.as_slice().to_vec());
-    let result = run_with_panic_protection(|| execute_request(request, body_vec, cookie_jar));
+    let result =
+        run_with_panic_protection(|| execute_request(request, body_vec, cookie_jar, client));
 
     match result {
-        Ok((meta, response_body)) => {
-            let mut new_bin = NewBinary::new(env, response_body.len());
-            new_bin.as_mut_slice().copy_from_slice(&response_body);
-            let body_binary = Binary::from(new_bin);
-            (ok(), meta, body_binary).encode(env)
+        Ok((meta, response_body)) => (ok(), meta, encode_binary(env, &response_body)).encode(env),
+        Err(native_error) => encode_error(env, native_error),
+    }
+}
+
+/// Starts a request on the shared runtime and returns `{:ok, handle}` right away,
+/// so no scheduler thread is held while the request is in flight.
+///
+/// The result is delivered to the calling process as `{handle, {:ok, meta, body}}`
+/// or `{handle, {:error, error_map}}`.
+///
+/// Runs on a dirty CPU scheduler only long enough to copy the request body,
+/// which may be large, out of the calling process.
+#[rustler::nif(schedule = "DirtyCpu")]
+fn nif_start_request<'a>(
+    env: Env<'a>,
+    request: NativeRequest,
+    body: Option<Binary>,
+    cookie_jar: Option<ResourceArc<CookieJarResource>>,
+    client: Option<ResourceArc<ClientResource>>,
+) -> Term<'a> {
+    let body_vec = body.map(|b| b.as_slice().to_vec());
+
+    start_task(env, |mut reply, _demand| async move {
+        let outcome = perform_request(request, body_vec, cookie_jar, client).await;
+        reply.send(|env| match outcome {
+            Ok((meta, response_body)) => {
+                (ok(), meta, encode_binary(env, &response_body)).encode(env)
+            }
+            Err(native_error) => encode_error(env, native_error),
+        });
+    })
+}
+
+/// Starts a streaming request on the shared runtime and returns `{:ok, handle}`
+/// right away.
+///
+/// The response is delivered to the calling process as `{handle, message}` tuples:
+/// `{:response, meta}` once headers arrive, then `{:data, chunk}` per body chunk,
+/// and finally `:done` or `{:error, error_map}`. After the headers, each message
+/// waits for a `nif_demand_chunk` call, so at most one is in the mailbox.
+///
+/// Like `nif_start_request`, copies the request body on a dirty CPU scheduler.
+#[rustler::nif(schedule = "DirtyCpu")]
+fn nif_start_stream<'a>(
+    env: Env<'a>,
+    request: NativeRequest,
+    body: Option<Binary>,
+    cookie_jar: Option<ResourceArc<CookieJarResource>>,
+    client: Option<ResourceArc<ClientResource>>,
+) -> Term<'a> {
+    let body_vec = body.map(|b| b.as_slice().to_vec());
+
+    start_task(env, |mut reply, demand| async move {
+        let outcome =
+            stream_request(request, body_vec, cookie_jar, client, &mut reply, &demand).await;
+        reply.send(|env| match outcome {
+            Ok(()) => done().encode(env),
+            Err(native_error) => encode_error(env, native_error),
+        });
+    })
+}
+
+/// Asks a request started with `nif_start_stream` for its next body message.
+#[rustler::nif]
+fn nif_demand_chunk(handle: ResourceArc<RequestHandle>) -> Atom {
+    handle.demand.grant();
+    ok()
+}
+
+/// Aborts a request started with `nif_start_request` or `nif_start_stream`.
+///
+/// No further messages are sent for the handle once this returns; a reply
+/// being sent meanwhile is delivered first, so the caller can flush it.
+/// Cancelling a finished request is a no-op.
+#[rustler::nif]
+fn nif_cancel_request(handle: ResourceArc<RequestHandle>) -> Atom {
+    handle.cancel();
+    ok()
+}
+
+/// Bounds the client cache to `max_clients` clients, each dropped after
+/// `idle_timeout_ms` without requests (`None` keeps idle clients).
+#[rustler::nif]
+fn nif_configure_client_cache(max_clients: usize, idle_timeout_ms: Option<u64>) -> Atom {
+    CLIENT_CACHE
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .configure(max_clients, idle_timeout_ms.map(Duration::from_millis));
+    ok()
+}
+
+/// Drops the cached client that `request` would use. Returns whether there
+/// was one.
+#[rustler::nif]
+fn nif_evict_client(request: NativeRequest) -> bool {
+    CLIENT_CACHE
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(&ClientKey::new(&request))
+}
+
+/// Drops every cached client and returns how many there were.
+#[rustler::nif]
+fn nif_clear_clients() -> usize {
+    CLIENT_CACHE
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .clear()
+}
+
+/// Opens a connection to each of `origins` with the cached client `request`
+/// would use, or with `client`, so later requests find one in the pool.
+///
+/// Starts on the shared runtime and returns `{:ok, handle}` right away, so no
+/// scheduler is held through DNS, TCP and TLS. The calling process receives
+/// `{handle, {:ok, [{origin, :ok | {:error, error_map}}]}}` in the order
+/// given, or `{handle, {:error, error_map}}` when no client can be built.
+#[rustler::nif]
+fn nif_preconnect<'a>(
+    env: Env<'a>,
+    request: NativeRequest,
+    origins: Vec<String>,
+    client: Option<ResourceArc<ClientResource>>,
+) -> Term<'a> {
+    start_task(env, |mut reply, _demand| async move {
+        let outcome = match client_for(&request, client.as_deref()).await {
+            Ok(client) => Ok(preconnect(client, request, origins).await),
+            Err(native_error) => Err(native_error),
+        };
+        reply.send(|env| match outcome {
+            Ok(outcomes) => {
+                let outcomes: Vec<Term> = outcomes
+                    .into_iter()
+                    .map(|(origin, outcome)| match outcome {
+                        Ok(()) => (origin, ok()).encode(env),
+                        Err(native_error) => (origin, encode_error(env, native_error)).encode(env),
+                    })
+                    .collect();
+                (ok(), outcomes).encode(env)
+            }
+            Err(native_error) => encode_error(env, native_error),
+        });
+    })
+}
+
+/// Describes every cached client, oldest first. Client handles are not
+/// cached and so not listed.
+#[rustler::nif]
+fn nif_client_stats() -> Vec<NativeClientStats> {
+    let clock = Clock::now();
+    let cache = CLIENT_CACHE.read().unwrap_or_else(|e| e.into_inner());
+    let mut entries: Vec<_> = cache.entries().collect();
+    entries.sort_by_key(|entry| entry.created);
+
+    entries
+        .into_iter()
+        .map(|entry| NativeClientStats {
+            emulation: entry.key.emulation.clone(),
+            insecure_skip_verify: entry.key.insecure_skip_verify,
+            local_address: entry.key.local_address.clone(),
+            interface: entry.key.interface.clone(),
+            proxy: entry.key.proxy.as_ref().map(stats::redact_proxy),
+            created_at_ms: clock.unix_ms(entry.created),
+            last_used_at_ms: clock.unix_ms(entry.last_used),
+            hosts: entry
+                .value
+                .host_stats()
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+        })
+        .collect()
+}
+
+/// Drops all cached DNS answers so the next lookups go to the nameservers.
+#[rustler::nif]
+fn nif_flush_dns_cache() -> Atom {
+    dns::flush_caches();
+    ok()
+}
+
+fn encode_binary<'a>(env: Env<'a>, bytes: &[u8]) -> Term<'a> {
+    let mut new_bin = NewBinary::new(env, bytes.len());
+    new_bin.as_mut_slice().copy_from_slice(bytes);
+    Binary::from(new_bin).encode(env)
+}
+
+fn encode_error<'a>(env: Env<'a>, native_error: NativeError) -> Term<'a> {
+    let error_value = serde_json::to_value(native_error).expect("NativeError must serialize");
+    (error(), SerdeTerm(error_value)).encode(env)
+}
+
+/// Spawns the task built by `make_task` and returns `{:ok, handle}`.
+///
+/// The task receives a `Reply` tagged with the handle and the handle's
+/// `Demand`. The calling process is monitored; if it is already gone the task
+/// is cancelled straight away.
+fn start_task<'a, F, T>(env: Env<'a>, make_task: F) -> Term<'a>
+where
+    F: FnOnce(Reply, Demand) -> T,
+    T: Future<Output = ()> + Send + 'static,
+{
+    let pid = env.pid();
+    let handle = ResourceArc::new(RequestHandle::default());
+    let tag = handle.encode(env);
+
+    let task = make_task(
+        Reply::new(pid, tag, handle.gate.clone()),
+        handle.demand.clone(),
+    );
+    let abort = spawn_replying(Reply::new(pid, tag, handle.gate.clone()), task);
+    handle.arm(abort);
+
+    if env.monitor(&handle, &pid).is_none() {
+        handle.cancel();
+    }
+
+    (ok(), tag).encode(env)
+}
+
+/// Spawns `task` on the shared runtime. If it panics, `panic_reply` reports a
+/// `nif_panic` error so the caller is never left waiting for a reply.
+fn spawn_replying<F>(mut panic_reply: Reply, task: F) -> AbortHandle
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let handle = RUNTIME.spawn(task);
+    let abort = handle.abort_handle();
+
+    RUNTIME.spawn(async move {
+        if let Err(join_error) = handle.await {
+            if join_error.is_panic() {
+                let panic_info = join_error.into_panic();
+                let message = panic_message(panic_info.as_ref()).to_string();
+                panic_reply.send(|env| {
+                    encode_error(env, NativeError::new("nif_panic", &message, json!({})))
+                });
+            }
+        }
+    });
+
+    abort
+}
+
+async fn stream_request(
+    request: NativeRequest,
+    body: Option<Vec<u8>>,
+    cookie_jar: Option<ResourceArc<CookieJarResource>>,
+    client: Option<ResourceArc<ClientResource>>,
+    reply: &mut Reply,
+    demand: &Demand,
+) -> Result<(), NativeError> {
+    let client = client_for(&request, client.as_deref()).await?;
+
+    timeouts::with_total(request.total_timeout_ms, async {
+        let (meta, mut response) =
+            send_request(&client, &request, body, cookie_jar.as_deref()).await?;
+
+        // Stop quietly if the caller exited; there is nobody left to report to.
+        if !reply.send(|env| (response_head(), meta).encode(env)) {
+            return Ok(());
+        }
+
+        // The final `:done` or error answers the demand for the read that
+        // found the end of the body or failed.
+        stream_body_with_limit(&mut response, &request, Some(demand), |chunk| {
+            reply.send(|env| (data(), encode_binary(env, chunk)).encode(env))
+        })
+        .await
+    })
+    .await
+}
+
+/// Reads the response body chunk by chunk, handing each chunk to `sink` as it
+/// arrives. `max_body_size_bytes` applies to the running total and `low_speed`
+/// to the transfer rate. Stops early, without an error, once `sink` returns
+/// `false`.
+///
+/// With `demand`, each chunk is read only once it has been asked for. Time
+/// spent waiting does not count against `low_speed`.
+async fn stream_body_with_limit<F>(
+    response: &mut wreq::Response,
+    request: &NativeRequest,
+    demand: Option<&Demand>,
+    mut sink: F,
+) -> Result<(), NativeError>
+where
+    F: FnMut(&[u8]) -> bool,
+{
+    let limit = request.max_body_size_bytes.unwrap_or(u64::MAX);
+    let mut received: u64 = 0;
+    let mut speed = timeouts::SpeedCheck::new(request.low_speed.as_ref());
+
+    loop {
+        if let Some(demand) = demand {
+            let waiting = Instant::now();
+            demand.wait().await;
+            speed.pause(waiting.elapsed());
+        }
+
+        let next = match speed.deadline() {
+            Some(deadline) => match tokio::time::timeout_at(deadline, response.chunk()).await {
+                Ok(next) => next,
+                Err(_) => {
+                    speed.check()?;
+                    continue;
+                }
+            },
+            None => response.chunk().await,
+        };
+        let next = next.map_err(|reason| {
+            timeouts::transport_error(reason, "failed to read response body", request)
+        })?;
+        let Some(chunk) = next else {
+            break;
+        };
+
+        speed.record(chunk.len());
+        speed.check()?;
+        received = received.saturating_add(chunk.len() as u64);
+        if received > limit {
+            return Err(NativeError::new(
+                "invalid_request",
+                "response body exceeds max_body_size",
+                json!({"limit": limit}),
+            ));
         }
-        Err(native_error) => {
-            let error_value =
-                serde_json::to_value(native_error).expect("NativeError must serialize");
-            (error(), SerdeTerm(error_value)).encode(env)
+        if !sink(&chunk[..]) {
+            break;
         }
     }
+
+    Ok(())
 }
 
 async fn read_body_with_limit(
     response: &mut wreq::Response,
-    max_size: Option<u64>,
+    request: &NativeRequest,
 ) -> Result<Vec<u8>, NativeError> {
-    let limit = max_size.unwrap_or(u64::MAX) as usize;
+    let limit = request.max_body_size_bytes.unwrap_or(u64::MAX);
 
     let content_length = response
         .headers()
         .get("content-length")
         .and_then(|v| v.to_str().ok())
-        .and_then(|v| v.parse::<usize>().ok());
+        .and_then(|v| v.parse::<u64>().ok());
 
     let mut body = match content_length {
-        Some(len) if len <= limit => Vec::with_capacity(len),
+        Some(len) if len <= limit => Vec::with_capacity(len as usize),
         _ => Vec::new(),
     };
 
-    while let Some(chunk) = response.chunk().await.map_err(|reason| {
-        // reason = Display (user-friendly message), debug = Debug (inner error chain for diagnostics)
-        NativeError::new(
-            "transport_error",
-            "failed to read response body",
-            json!({"reason": reason.to_string(), "debug": format!("{reason:?}")}),
-        )
-    })? {
-        if body.len() + chunk.len() > limit {
-            return Err(NativeError::new(
-                "invalid_request",
-                "response body exceeds max_body_size",
-                json!({"limit": limit}),
-            ));
-        }
-        body.extend_from_slice(&chunk);
-    }
+    stream_body_with_limit(response, request, None, |chunk| {
+        body.extend_from_slice(chunk);
+        true
+    })
+    .await?;
 
     Ok(body)
 }
@@ -213,101 +1110,340 @@ fn execute_request(
     request: NativeRequest,
     body: Option<Vec<u8>>,
     cookie_jar: Option<ResourceArc<CookieJarResource>>,
+    client: Option<ResourceArc<ClientResource>>,
 ) -> Result<(NativeResponseMeta, Vec<u8>), NativeError> {
-    let client = get_or_build_client(
-        request.emulation.as_deref(),
-        request.insecure_skip_verify,
-        request.local_address.as_deref(),
-    )?;
-
-    RUNTIME.block_on(async move {
-        let method = Method::from_bytes(request.method.as_bytes()).map_err(|reason| {
-            NativeError::new(
-                "invalid_request",
-                "invalid HTTP method",
-                json!({"reason": reason.to_string(), "value": request.method}),
-            )
-        })?;
+    RUNTIME.block_on(perform_request(request, body, cookie_jar, client))
+}
 
-        let mut builder = client
-            .request(method, request.url.as_str())
-            .timeout(Duration::from_millis(request.receive_timeout_ms));
+async fn perform_request(
+    request: NativeRequest,
+    body: Option<Vec<u8>>,
+    cookie_jar: Option<ResourceArc<CookieJarResource>>,
+    client: Option<ResourceArc<ClientResource>>,
+) -> Result<(NativeResponseMeta, Vec<u8>), NativeError> {
+    let started = Instant::now();
+    let client = client_for(&request, client.as_deref()).await?;
+
+    timeouts::with_total(request.total_timeout_ms, async {
+        let (mut meta, mut response) =
+            send_request(&client, &request, body, cookie_jar.as_deref()).await?;
+        let body_started = Instant::now();
+        let body_bytes = read_body_with_limit(&mut response, &request).await?;
+
+        meta.timing.body_us = Some(timing::micros(body_started.elapsed()));
+        meta.timing.total_us = Some(timing::micros(started.elapsed()));
+        Ok((meta, body_bytes))
+    })
+    .await
+}
 
-        // Iterate by reference so request.url remains accessible for cookie jar
-        for (name, value) in &request.headers {
-            builder = builder.header(name.as_str(), value.as_str());
-        }
+/// Warms up `origins` concurrently, see `nif_preconnect`.
+async fn preconnect(
+    client: Arc<PooledClient>,
+    request: NativeRequest,
+    origins: Vec<String>,
+) -> Vec<(String, Result<(), NativeError>)> {
+    let request = Arc::new(request);
+    let tasks: Vec<_> = origins
+        .into_iter()
+        .map(|origin| {
+            let client = client.clone();
+            let request = request.clone();
+            let task = RUNTIME.spawn(async move {
+                timeouts::with_total(
+                    request.total_timeout_ms,
+                    warm_up(&client, &request, &origin),
+                )
+                .await
+            });
+            (origin, task)
+        })
+        .collect();
 
-        // Add cookies from jar before sending
-        if let Some(ref jar) = cookie_jar {
-            if let Ok(parsed_uri) = request.url.parse::<http::Uri>() {
-                match jar.jar.cookies(&parsed_uri) {
-                    Cookies::Compressed(val) => {
-                        builder = builder.header("cookie", val);
-                    }
-                    Cookies::Uncompressed(vals) => {
-                        for val in vals {
-                            builder = builder.header("cookie", val);
-                        }
-                    }
-                    _ => {}
-                }
-            }
+    let mut outcomes = Vec::with_capacity(tasks.len());
+    for (origin, task) in tasks {
+        let outcome = task.await.unwrap_or_else(|join_error| {
+            let message = if join_error.is_panic() {
+                panic_message(join_error.into_panic().as_ref()).to_string()
+            } else {
+                "preconnect task was cancelled".to_string()
+            };
+            Err(NativeError::new("nif_panic", &message, json!({})))
+        });
+        outcomes.push((origin, outcome));
+    }
+    outcomes
+}
+
+/// Opens a connection to `origin` and leaves it in the client's pool, without
+/// sending a request. Over HTTP/2 the session stays open for later requests to
+/// share.
+async fn warm_up(
+    client: &PooledClient,
+    request: &NativeRequest,
+    origin: &str,
+) -> Result<(), NativeError> {
+    let url = origin_url(origin)?;
+    dns::check_literal_host(request.ip_family, &url)?;
+    client
+        .client
+        .get(url.as_str())
+        .connect_only()
+        .send()
+        .await
+        .map_err(|reason| timeouts::transport_error(reason, "preconnect failed", request))?;
+    Ok(())
+}
+
+/// The root URL of `origin`, which must be an `http` or `https` URL. Any path,
+/// query or fragment is dropped.
+fn origin_url(origin: &str) -> Result<Url, NativeError> {
+    let invalid = |reason: String| {
+        NativeError::new(
+            "invalid_request",
+            "origin must be an http or https URL",
+            json!({"origin": origin, "reason": reason}),
+        )
+    };
+    let mut url = Url::parse(origin).map_err(|reason| invalid(reason.to_string()))?;
+    if !matches!(url.scheme(), "http" | "https") || !url.has_host() {
+        return Err(invalid("unsupported scheme or missing host".to_string()));
+    }
+    url.set_path("/");
+    url.set_query(None);
+    url.set_fragment(None);
+    Ok(url)
+}
+
+/// Sends the request and returns the response metadata together with the
+/// response, whose body has not been read yet.
+///
+/// Redirects are followed here rather than by wreq, one hop at a time, so every
+/// hop gets cookies from the jar for its own URL and stores the cookies it sets.
+async fn send_request(
+    client: &PooledClient,
+    request: &NativeRequest,
+    body: Option<Vec<u8>>,
+    cookie_jar: Option<&CookieJarResource>,
+) -> Result<(NativeResponseMeta, wreq::Response), NativeError> {
+    let mut method = Method::from_bytes(request.method.as_bytes()).map_err(|reason| {
+        NativeError::new(
+            "invalid_request",
+            "invalid HTTP method",
+            json!({"reason": reason.to_string(), "value": request.method}),
+        )
+    })?;
+    let mut url = Url::parse(&request.url).map_err(|reason| {
+        NativeError::new(
+            "invalid_request",
+            "invalid url",
+            json!({"reason": reason.to_string()}),
+        )
+    })?;
+    let mut headers = request.headers.clone();
+    let mut body = body;
+    let follows_redirects = request.redirect.mode != NativeRedirectMode::None;
+    let mut redirects = Vec::new();
+
+    loop {
+        let hop_body = if follows_redirects {
+            body.clone()
+        } else {
+            body.take()
+        };
+        dns::check_literal_host(request.ip_family, &url)?;
+        let in_flight = client.track(&url);
+        let hop_started = Instant::now();
+        let (response, connect_times) = timing::record_hop(send_hop(
+            &client.client,
+            method.clone(),
+            &url,
+            &headers,
+            hop_body,
+            request,
+            cookie_jar,
+        ))
+        .await;
+        let mut response = response?;
+        let head_received = Instant::now();
+        pool::hold(&mut response, in_flight);
+
+        let (cookies_accepted, cookies_rejected) = match cookie_jar {
+            Some(jar) => store_response_cookies(jar, &response),
+            None => (Vec::new(), Vec::new()),
+        };
+
+        let location = response
+            .headers()
+            .get("location")
+            .and_then(|value| value.to_str().ok());
+        let next = redirect::next_hop(
+            &request.redirect,
+            &url,
+            &method,
+            response.status().as_u16(),
+            location,
+            redirects.len() as u32,
+        )?;
+
+        let Some(next) = next else {
+            let mut meta = response_meta(&response);
+            meta.redirects = redirects;
+            meta.timing = timing::hop_timing(
+                &connect_times,
+                url.scheme() == "https",
+                hop_started,
+                head_received,
+            );
+            return Ok((meta, response));
+        };
+
+        let meta = response_meta(&response);
+        redirects.push(NativeRedirectHop {
+            url: meta.url,
+            status: meta.status,
+            headers: meta.headers,
+            cookies_accepted,
+            cookies_rejected,
+        });
+
+        redirect::retain_headers(&mut headers, &next, &request.redirect);
+        if !next.keep_body {
+            body = None;
         }
+        method = next.method;
+        url = next.url;
+    }
+}
 
-        if let Some(body) = body {
-            builder = builder.body(body);
+/// Sends a single request without following redirects.
+async fn send_hop(
+    client: &Client,
+    method: Method,
+    url: &Url,
+    headers: &[(String, String)],
+    body: Option<Vec<u8>>,
+    request: &NativeRequest,
+    cookie_jar: Option<&CookieJarResource>,
+) -> Result<wreq::Response, NativeError> {
+    let mut builder = client
+        .request(method, url.as_str())
+        .read_timeout(Duration::from_millis(request.receive_timeout_ms));
+
+    for (name, value) in headers {
+        builder = builder.header(name.as_str(), value.as_str());
+    }
+
+    // Add cookies from jar before sending
+    if let Some(header) = cookie_jar.and_then(|jar| jar.jar.header_for(url)) {
+        builder = builder.header("cookie", header);
+    }
+
+    let progress = Arc::new(timeouts::WriteProgress::default());
+    let write_timeout_ms = request.write_timeout_ms.filter(|_| body.is_some());
+    match body {
+        Some(body) if write_timeout_ms.is_some() => {
+            builder = builder.body(wreq::Body::wrap(timeouts::ProgressBody::new(
+                body,
+                progress.clone(),
+            )));
         }
+        Some(body) => builder = builder.body(body),
+        None => {}
+    }
 
-        let mut response = builder.send().await.map_err(|reason| {
-            NativeError::new(
-                "transport_error",
-                "request execution failed",
-                json!({"reason": reason.to_string(), "debug": format!("{reason:?}")}),
-            )
-        })?;
+    let sent = match write_timeout_ms {
+        Some(timeout_ms) => tokio::select! {
+            sent = builder.send() => sent,
+            stalled = timeouts::write_stalled(&progress, timeout_ms) => return Err(stalled),
+        },
+        None => builder.send().await,
+    };
 
-        // Store cookies against the actual response URI so redirects use the
-        // final host for PSL validation and jar scoping.
-        if let Some(ref jar) = cookie_jar {
-            if let Ok(response_uri) = response.uri().to_string().parse::<http::Uri>() {
-                let host = response_uri.host().unwrap_or_default();
-                let set_cookies: Vec<_> = response
-                    .headers()
-                    .get_all("set-cookie")
-                    .iter()
-                    .filter(|hv| is_cookie_domain_safe(hv.as_bytes(), host))
-                    .collect();
-                if !set_cookies.is_empty() {
-                    let mut iter = set_cookies.into_iter();
-                    jar.jar.set_cookies(&mut iter, &response_uri);
-                }
-            }
+    sent.map_err(|reason| timeouts::transport_error(reason, "request execution failed", request))
+}
+
+/// Stores Set-Cookie headers against the URI that actually sent them, so PSL
+/// validation and jar scoping use that hop's host.
+///
+/// Returns the Set-Cookie values the jar stored and those it rejected, in that
+/// order. Values failing the domain check or that do not parse are rejected.
+fn store_response_cookies(
+    jar: &CookieJarResource,
+    response: &wreq::Response,
+) -> (Vec<String>, Vec<String>) {
+    let Ok(response_url) = Url::parse(&response.uri().to_string()) else {
+        return (Vec::new(), Vec::new());
+    };
+    let host = response_url.host_str().unwrap_or_default();
+
+    let mut accepted = Vec::new();
+    let mut rejected = Vec::new();
+    for hv in response.headers().get_all("set-cookie") {
+        let header = header_text(hv);
+        if is_cookie_domain_safe(hv.as_bytes(), host) && jar.jar.set_cookie(&header, &response_url)
+        {
+            accepted.push(header);
+        } else {
+            rejected.push(header);
         }
+    }
+    if !accepted.is_empty() {
+        jar.save_in_background();
+    }
 
-        let status = response.status().as_u16();
-        let url = response.uri().to_string();
-        let headers = response
-            .headers()
-            .iter()
-            .map(|(name, value)| {
-                (
-                    name.to_string(),
-                    String::from_utf8_lossy(value.as_bytes()).into_owned(),
-                )
-            })
-            .collect::<Vec<_>>();
+    (accepted, rejected)
+}
+
+fn header_text(value: &http::HeaderValue) -> String {
+    String::from_utf8_lossy(value.as_bytes()).into_owned()
+}
 
-        let body_bytes = read_body_with_limit(&mut response, request.max_body_size_bytes).await?;
+fn response_meta(response: &wreq::Response) -> NativeResponseMeta {
+    let headers = response
+        .headers()
+        .iter()
+        .map(|(name, value)| (name.to_string(), header_text(value)))
+        .collect::<Vec<_>>();
+
+    NativeResponseMeta {
+        status: response.status().as_u16(),
+        url: response.uri().to_string(),
+        headers,
+        redirects: Vec::new(),
+        timing: NativeTiming::default(),
+        http_version: http_version_name(response.version()).to_string(),
+        tls: tls_info(response),
+    }
+}
 
-        Ok((
-            NativeResponseMeta {
-                status,
-                url,
-                headers,
-            },
-            body_bytes,
-        ))
+fn http_version_name(version: wreq::Version) -> &'static str {
+    match version {
+        wreq::Version::HTTP_09 => "HTTP/0.9",
+        wreq::Version::HTTP_10 => "HTTP/1.0",
+        wreq::Version::HTTP_11 => "HTTP/1.1",
+        wreq::Version::HTTP_2 => "HTTP/2",
+        wreq::Version::HTTP_3 => "HTTP/3",
+        _ => "unknown",
+    }
+}
+
+/// TLS details recorded on the connection, if it was encrypted.
+fn tls_info(response: &wreq::Response) -> Option<NativeTlsInfo> {
+    let info = response.extensions().get::<TlsInfo>()?;
+    let peer_certificates = info
+        .peer_certificate_chain()
+        .map(|chain| chain.map(|der| DerBinary(der.to_vec())).collect())
+        .unwrap_or_default();
+
+    Some(NativeTlsInfo {
+        alpn: info
+            .alpn_protocol()
+            .map(|protocol| String::from_utf8_lossy(protocol).into_owned()),
+        version: info.version().map(str::to_string),
+        cipher: info.cipher().map(str::to_string),
+        session_resumed: info.session_reused(),
+        peer_certificates,
     })
 }
 
@@ -360,6 +1496,8 @@ fn extract_cookie_domain(header: &str) -> Option<&str> {
 
 fn on_load(env: Env, _info: Term) -> bool {
     env.register::<CookieJarResource>().is_ok()
+        && env.register::<ClientResource>().is_ok()
+        && env.register::<RequestHandle>().is_ok()
 }
 
 rustler::init!("Elixir.CloakedReq.Native", load = on_load);
@@ -367,6 +1505,7 @@ rustler::init!("Elixir.CloakedReq.Native", load = on_load);
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::request::NativeRedirect;
     use std::io::{Read, Write};
     use std::net::TcpListener;
     use std::sync::mpsc;
@@ -422,139 +1561,799 @@ mod tests {
             url: "http://example.com".to_string(),
             headers: vec![],
             receive_timeout_ms: 5_000,
+            connect_timeout_ms: 10_000,
+            write_timeout_ms: None,
+            total_timeout_ms: None,
+            low_speed: None,
             emulation: None,
             insecure_skip_verify: false,
             max_body_size_bytes: None,
             local_address: None,
+            interface: None,
+            proxy: None,
+            redirect: NativeRedirect::default(),
+            client_certificate: None,
+            ca_certificates: None,
+            pins: Vec::new(),
+            tls_keylog: None,
+            resolve: Vec::new(),
+            dns: None,
+            ip_family: NativeIpFamily::Any,
+            happy_eyeballs_ms: Some(300),
+            pool_idle_timeout_ms: Some(90_000),
+            pool_max_idle_per_host: 20,
+            max_connections_per_host: None,
+            http2_keep_alive: None,
+        }
+    }
+
+    #[test]
+    fn keylog_clients_are_cached_separately() {
+        let plain = base_request();
+        let mut logged = base_request();
+        logged.tls_keylog = Some("/tmp/keys.log".to_string());
+
+        assert_ne!(ClientKey::new(&plain), ClientKey::new(&logged));
+    }
+
+    #[test]
+    fn rejects_unknown_emulation_profile() {
+        let mut request = base_request();
+        request.emulation = Some("unknown_browser".to_string());
+
+        let result = execute_request(request, None, None, None);
+        assert!(result.is_err());
+
+        let err = result.err().expect("expected error");
+        assert_eq!(err.type_name, "invalid_request");
+        assert_eq!(err.message, "unknown emulation profile");
+    }
+
+    #[test]
+    fn rejects_invalid_http_method() {
+        let mut request = base_request();
+        request.method = "BAD METHOD".to_string();
+
+        let result = execute_request(request, None, None, None);
+        assert!(result.is_err());
+
+        let err = result.err().expect("expected error");
+        assert_eq!(err.type_name, "invalid_request");
+        assert_eq!(err.message, "invalid HTTP method");
+    }
+
+    #[test]
+    fn executes_local_http_request_successfully() {
+        let response_body = "ok";
+        let raw_response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+            response_body.len(),
+            response_body
+        )
+        .into_bytes();
+        let (url, received_request, server) = spawn_test_server(raw_response, 200);
+
+        let mut request = base_request();
+        request.url = url;
+        request.headers = vec![("x-demo".to_string(), "1".to_string())];
+
+        let (meta, body) =
+            execute_request(request, None, None, None).expect("request should succeed");
+        server.join().expect("server thread must join");
+
+        assert_eq!(meta.status, 200);
+        assert_eq!(body, b"ok");
+        assert_eq!(meta.http_version, "HTTP/1.1");
+        assert!(meta.tls.is_none());
+        assert!(meta
+            .headers
+            .iter()
+            .any(|header| header.0.eq_ignore_ascii_case("content-type")
+                && header.1.contains("text/plain")));
+
+        let raw_request = received_request
+            .recv_timeout(StdDuration::from_secs(1))
+            .expect("must capture request");
+        let request_text = String::from_utf8(raw_request).expect("request should be utf-8");
+        assert!(request_text.starts_with("GET / HTTP/1.1"));
+        assert!(request_text.contains("x-demo: 1"));
+    }
+
+    #[test]
+    fn reports_timing_for_new_connection() {
+        let raw_response =
+            b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok".to_vec();
+        let (url, _received_request, server) = spawn_test_server(raw_response, 200);
+
+        let mut request = base_request();
+        request.url = url;
+
+        let (meta, _body) =
+            execute_request(request, None, None, None).expect("request should succeed");
+        server.join().expect("server thread must join");
+
+        let timing = meta.timing;
+        assert_eq!(timing.dns_us, None, "IP literals need no lookup");
+        assert!(timing.connect_us.is_some());
+        assert_eq!(timing.tls_us, None, "plain HTTP has no handshake");
+        assert!(
+            timing.total_us.unwrap() >= timing.connect_us.unwrap() + timing.ttfb_us,
+            "time to first byte starts once the connection is set up"
+        );
+        assert!(timing.body_us.is_some());
+        assert!(timing.total_us.unwrap() >= timing.ttfb_us);
+    }
+
+    #[test]
+    fn resolve_override_connects_to_given_address() {
+        let raw_response =
+            b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok".to_vec();
+        let (url, received_request, server) = spawn_test_server(raw_response, 200);
+        let port = Url::parse(&url)
+            .unwrap()
+            .port()
+            .expect("test url has a port");
+
+        let mut request = base_request();
+        request.url = format!("http://api.cloaked-req.example:{port}/");
+        request.resolve = vec![NativeResolve {
+            host: "api.cloaked-req.example".to_string(),
+            port,
+            addresses: vec!["127.0.0.1".to_string()],
+        }];
+
+        let (meta, _body) =
+            execute_request(request, None, None, None).expect("request should succeed");
+        server.join().expect("server thread must join");
+
+        assert_eq!(meta.status, 200);
+        let raw_request = received_request
+            .recv_timeout(StdDuration::from_secs(1))
+            .expect("must capture request");
+        let request_text = String::from_utf8(raw_request).expect("request should be utf-8");
+        assert!(request_text.contains(&format!("host: api.cloaked-req.example:{port}")));
+    }
+
+    #[test]
+    fn ipv4_family_skips_ipv6_addresses() {
+        let raw_response =
+            b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok".to_vec();
+        let (url, _rx, server) = spawn_test_server(raw_response, 200);
+        let port = Url::parse(&url)
+            .unwrap()
+            .port()
+            .expect("test url has a port");
+
+        let mut request = base_request();
+        request.url = format!("http://api.cloaked-req.example:{port}/");
+        request.ip_family = NativeIpFamily::Ipv4;
+        request.happy_eyeballs_ms = None;
+        request.resolve = vec![NativeResolve {
+            host: "api.cloaked-req.example".to_string(),
+            port,
+            addresses: vec!["::1".to_string(), "127.0.0.1".to_string()],
+        }];
+
+        let (meta, _body) =
+            execute_request(request, None, None, None).expect("request should succeed");
+        server.join().expect("server thread must join");
+
+        assert_eq!(meta.status, 200);
+    }
+
+    #[test]
+    fn ipv6_family_rejects_ipv4_literal_host() {
+        let mut request = base_request();
+        request.url = "http://127.0.0.1:9/".to_string();
+        request.ip_family = NativeIpFamily::Ipv6;
+
+        let err = execute_request(request, None, None, None).expect_err("family should not match");
+        assert_eq!(err.type_name, "transport_error");
+        assert_eq!(err.message, "url host is outside the requested IP family");
+    }
+
+    #[test]
+    fn pin_mismatch_fails_before_the_request_is_sent() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener must bind");
+        let addr = listener.local_addr().expect("local addr must be available");
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener
+                .accept()
+                .expect("server must accept one connection");
+            stream
+                .set_read_timeout(Some(StdDuration::from_secs(2)))
+                .expect("read timeout should be set");
+            let mut received = Vec::new();
+            let _ = stream.read_to_end(&mut received);
+            received
+        });
+
+        let mut request = base_request();
+        request.url = format!("http://{addr}/");
+        request.method = "POST".to_string();
+        request.pins = vec![NativePin {
+            host: "127.0.0.1".to_string(),
+            sha256: vec!["AAAA".to_string()],
+        }];
+
+        let err = execute_request(request, Some(b"secret".to_vec()), None, None)
+            .expect_err("pinned host must fail");
+        let received = server.join().expect("server thread must join");
+
+        assert_eq!(err.type_name, "certificate_pin_error");
+        assert!(received.is_empty(), "server must not receive the request");
+    }
+
+    #[test]
+    fn pins_are_part_of_the_client_key() {
+        let plain = base_request();
+        let mut pinned = base_request();
+        pinned.pins = vec![NativePin {
+            host: "example.com".to_string(),
+            sha256: vec!["AAAA".to_string()],
+        }];
+
+        assert_ne!(ClientKey::new(&plain), ClientKey::new(&pinned));
+    }
+
+    fn redirect_response(location: &str) -> Vec<u8> {
+        format!(
+            "HTTP/1.1 302 Found\r\nlocation: {location}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn follows_cross_origin_redirect_without_credentials() {
+        let final_response =
+            b"HTTP/1.1 200 OK\r\ncontent-length: 4\r\nconnection: close\r\n\r\ndone".to_vec();
+        let (final_url, final_request, final_server) = spawn_test_server(final_response, 200);
+        let (first_url, _first_request, first_server) =
+            spawn_test_server(redirect_response(&final_url), 200);
+
+        let mut request = base_request();
+        request.url = first_url.clone();
+        request.headers = vec![
+            ("authorization".to_string(), "Bearer secret".to_string()),
+            ("x-demo".to_string(), "1".to_string()),
+        ];
+        request.redirect.mode = NativeRedirectMode::Follow;
+
+        let (meta, body) =
+            execute_request(request, None, None, None).expect("redirect should be followed");
+        first_server.join().expect("first server thread must join");
+        final_server.join().expect("final server thread must join");
+
+        assert_eq!(meta.status, 200);
+        assert_eq!(meta.url, final_url);
+        assert_eq!(body, b"done");
+        assert_eq!(meta.redirects.len(), 1);
+        assert_eq!(meta.redirects[0].status, 302);
+        assert_eq!(meta.redirects[0].url, first_url);
+
+        let raw_request = final_request
+            .recv_timeout(StdDuration::from_secs(1))
+            .expect("must capture redirected request");
+        let request_text = String::from_utf8(raw_request).expect("request should be utf-8");
+        assert!(request_text.contains("x-demo: 1"));
+        assert!(!request_text.to_lowercase().contains("authorization"));
+    }
+
+    #[test]
+    fn records_set_cookie_outcome_per_redirect_hop() {
+        let final_response =
+            b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n".to_vec();
+        let (final_url, _final_request, final_server) = spawn_test_server(final_response, 200);
+        let redirect = format!(
+            "HTTP/1.1 302 Found\r\nlocation: {final_url}\r\nset-cookie: session=1; Path=/\r\nset-cookie: evil=1; Domain=com\r\nset-cookie: garbage\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+        );
+        let (url, _first_request, first_server) = spawn_test_server(redirect.into_bytes(), 200);
+
+        let mut request = base_request();
+        request.url = url;
+        request.redirect.mode = NativeRedirectMode::Follow;
+        let jar = CookieJarResource::in_memory(CookieStore::default());
+        let client = get_or_build_client(&request).expect("client should build");
+
+        let (meta, _response) = RUNTIME
+            .block_on(send_request(&client, &request, None, Some(&jar)))
+            .expect("redirect should be followed");
+        first_server.join().expect("first server thread must join");
+        final_server.join().expect("final server thread must join");
+
+        let hop = &meta.redirects[0];
+        assert_eq!(hop.cookies_accepted, vec!["session=1; Path=/".to_string()]);
+        assert_eq!(
+            hop.cookies_rejected,
+            vec!["evil=1; Domain=com".to_string(), "garbage".to_string()]
+        );
+    }
+
+    #[test]
+    fn returns_redirect_response_when_redirects_are_disabled() {
+        let (url, _received_request, server) =
+            spawn_test_server(redirect_response("http://127.0.0.1:1/elsewhere"), 200);
+
+        let mut request = base_request();
+        request.url = url;
+
+        let (meta, _body) =
+            execute_request(request, None, None, None).expect("request should succeed");
+        server.join().expect("server thread must join");
+
+        assert_eq!(meta.status, 302);
+        assert!(meta
+            .headers
+            .iter()
+            .any(|header| header.0 == "location" && header.1 == "http://127.0.0.1:1/elsewhere"));
+    }
+
+    #[test]
+    fn reports_too_many_redirects() {
+        let (url, _received_request, server) = spawn_test_server(redirect_response("/again"), 200);
+
+        let mut request = base_request();
+        request.url = url;
+        request.redirect.mode = NativeRedirectMode::Follow;
+        request.redirect.max = 0;
+
+        let err = execute_request(request, None, None, None).expect_err("limit should be enforced");
+        server.join().expect("server thread must join");
+
+        assert_eq!(err.type_name, "redirect_error");
+        assert_eq!(err.message, "too many redirects");
+    }
+
+    #[test]
+    fn sends_body_to_local_http_server() {
+        let response_body = "created";
+        let raw_response = format!(
+            "HTTP/1.1 201 Created\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+            response_body.len(),
+            response_body
+        )
+        .into_bytes();
+        let (url, received_request, server) = spawn_test_server(raw_response, 300);
+
+        let mut request = base_request();
+        request.method = "POST".to_string();
+        request.url = url;
+
+        let (meta, _body) = execute_request(request, Some(b"hello".to_vec()), None, None)
+            .expect("request should succeed");
+        server.join().expect("server thread must join");
+
+        assert_eq!(meta.status, 201);
+        let raw_request = received_request
+            .recv_timeout(StdDuration::from_secs(1))
+            .expect("must capture request");
+        let request_text = String::from_utf8(raw_request).expect("request should be utf-8");
+        assert!(request_text.starts_with("POST / HTTP/1.1"));
+        assert!(request_text.contains("hello"));
+    }
+
+    /// Reads the request head, then waits `delay` before answering.
+    fn spawn_delayed_server(delay: StdDuration) -> (String, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener must bind");
+        let addr = listener.local_addr().expect("local addr");
+
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("server must accept");
+            stream
+                .set_read_timeout(Some(StdDuration::from_millis(100)))
+                .expect("read timeout should be set");
+            let mut buffer = [0_u8; 1024];
+            let _ = stream.read(&mut buffer);
+            thread::sleep(delay);
+            let _ = stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok");
+            let _ = stream.flush();
+        });
+
+        (format!("http://{addr}/"), handle)
+    }
+
+    #[test]
+    fn missing_clients_are_built_once_and_cached() {
+        let mut request = base_request();
+        request.pool_max_idle_per_host = 7;
+
+        let built = RUNTIME
+            .block_on(client_for(&request, None))
+            .expect("client should build");
+        let cached = get_or_build_client(&request).expect("client should be cached");
+
+        assert!(Arc::ptr_eq(&built, &cached));
+    }
+
+    #[test]
+    fn client_handles_bypass_the_cache() {
+        let mut request = base_request();
+        request.pool_max_idle_per_host = 4;
+        let handle = ClientResource {
+            client: Arc::new(build_client(&request).expect("client should build")),
+        };
+
+        let client = RUNTIME
+            .block_on(client_for(&request, Some(&handle)))
+            .expect("handle should be used");
+
+        assert!(Arc::ptr_eq(&client, &handle.client));
+        assert!(CLIENT_CACHE
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&ClientKey::new(&request))
+            .is_none());
+    }
+
+    #[test]
+    fn evicting_a_client_does_not_break_requests_in_flight() {
+        let (url, server) = spawn_delayed_server(StdDuration::from_millis(200));
+        let mut request = base_request();
+        request.url = url;
+        request.pool_max_idle_per_host = 3;
+        let key = ClientKey::new(&request);
+
+        let in_flight = thread::spawn(move || execute_request(request, None, None, None));
+        let cached = |key: &ClientKey| {
+            CLIENT_CACHE
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .get(key)
+                .is_some()
+        };
+        for _ in 0..100 {
+            if cached(&key) {
+                break;
+            }
+            thread::sleep(StdDuration::from_millis(5));
         }
+        assert!(CLIENT_CACHE
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&key));
+
+        let (meta, body) = in_flight
+            .join()
+            .expect("request thread must join")
+            .expect("request should finish after eviction");
+        server.join().expect("server thread must join");
+        assert_eq!(meta.status, 200);
+        assert_eq!(body, b"ok");
+        assert!(!cached(&key));
+    }
+
+    #[test]
+    fn preconnect_leaves_a_warm_connection_for_the_next_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener must bind");
+        let addr = listener.local_addr().expect("local addr");
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("server must accept once");
+            let mut head = Vec::new();
+            let mut byte = [0_u8; 1];
+            while !head.ends_with(b"\r\n\r\n") {
+                stream.read_exact(&mut byte).expect("request head");
+                head.push(byte[0]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok")
+                .expect("response should be written");
+            String::from_utf8_lossy(&head).into_owned()
+        });
+        let origin = format!("http://{addr}");
+        let pooled_request = || {
+            let mut request = base_request();
+            request.pool_max_idle_per_host = 4;
+            request
+        };
+
+        let request = pooled_request();
+        let client = RUNTIME
+            .block_on(client_for(&request, None))
+            .expect("client should build");
+        let outcomes = RUNTIME.block_on(preconnect(
+            client,
+            request,
+            vec![origin.clone(), "ftp://example.com".to_string()],
+        ));
+        assert_eq!(outcomes[0].0, origin);
+        assert!(outcomes[0].1.is_ok());
+        let err = outcomes[1]
+            .1
+            .as_ref()
+            .expect_err("ftp is not an HTTP origin");
+        assert_eq!(err.message, "origin must be an http or https URL");
+
+        // Give the pool a moment to take the connection back.
+        thread::sleep(StdDuration::from_millis(50));
+        let mut request = pooled_request();
+        request.url = format!("{origin}/page");
+        let (meta, body) =
+            execute_request(request, None, None, None).expect("request should succeed");
+
+        assert_eq!(body, b"ok");
+        assert!(
+            meta.timing.connect_us.is_none(),
+            "request reuses the warm connection"
+        );
+        let head = server.join().expect("server thread must join");
+        assert!(
+            head.starts_with("GET /page HTTP/1.1\r\n"),
+            "the warm connection carries no other request"
+        );
     }
 
     #[test]
-    fn rejects_unknown_emulation_profile() {
+    fn client_stats_count_open_connections_per_origin() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener must bind");
+        let addr = listener.local_addr().expect("local addr");
+        let (close, closed) = mpsc::channel::<()>();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("server must accept");
+            let mut head = Vec::new();
+            let mut byte = [0_u8; 1];
+            while !head.ends_with(b"\r\n\r\n") {
+                stream.read_exact(&mut byte).expect("request head");
+                head.push(byte[0]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok")
+                .expect("response should be written");
+            // Keep the connection open until the test has looked at it.
+            let _ = closed.recv();
+        });
         let mut request = base_request();
-        request.emulation = Some("unknown_browser".to_string());
+        request.url = format!("http://{addr}/");
+        request.pool_max_idle_per_host = 5;
+        let key = ClientKey::new(&request);
 
-        let result = execute_request(request, None, None);
-        assert!(result.is_err());
+        execute_request(request, None, None, None).expect("request should succeed");
 
-        let err = result.err().expect("expected error");
-        assert_eq!(err.type_name, "invalid_request");
-        assert_eq!(err.message, "unknown emulation profile");
+        let client = CLIENT_CACHE
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&key)
+            .expect("client should be cached");
+        assert_eq!(
+            client.host_stats(),
+            vec![pool::HostStats {
+                origin: format!("http://{addr}"),
+                connections: 1,
+                in_flight: 0,
+            }]
+        );
+
+        close.send(()).expect("server should be waiting");
+        server.join().expect("server thread must join");
+        let deadline = Instant::now() + StdDuration::from_secs(1);
+        while !client.host_stats().is_empty() && Instant::now() < deadline {
+            thread::sleep(StdDuration::from_millis(10));
+        }
+        assert!(
+            client.host_stats().is_empty(),
+            "connections closed by the server are no longer counted"
+        );
     }
 
     #[test]
-    fn rejects_invalid_http_method() {
-        let mut request = base_request();
-        request.method = "BAD METHOD".to_string();
+    fn returns_read_timeout_error_on_receive_timeout() {
+        let (url, server) = spawn_delayed_server(StdDuration::from_millis(350));
 
-        let result = execute_request(request, None, None);
-        assert!(result.is_err());
+        let mut request = base_request();
+        request.url = url;
+        request.receive_timeout_ms = 50;
 
-        let err = result.err().expect("expected error");
-        assert_eq!(err.type_name, "invalid_request");
-        assert_eq!(err.message, "invalid HTTP method");
+        let error = execute_request(request, None, None, None).expect_err("expected error");
+        server.join().expect("server thread must join");
+        assert_eq!(error.type_name, "read_timeout_error");
+        assert_eq!(error.message, "read timed out");
+        assert_eq!(error.details["timeout_ms"], 50);
     }
 
     #[test]
-    fn executes_local_http_request_successfully() {
-        let response_body = "ok";
-        let raw_response = format!(
-            "HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
-            response_body.len(),
-            response_body
-        )
-        .into_bytes();
-        let (url, received_request, server) = spawn_test_server(raw_response, 200);
+    fn returns_total_timeout_error_past_the_deadline() {
+        let (url, server) = spawn_delayed_server(StdDuration::from_millis(350));
 
         let mut request = base_request();
         request.url = url;
-        request.headers = vec![("x-demo".to_string(), "1".to_string())];
+        request.total_timeout_ms = Some(50);
 
-        let (meta, body) = execute_request(request, None, None).expect("request should succeed");
+        let error = execute_request(request, None, None, None).expect_err("expected error");
         server.join().expect("server thread must join");
+        assert_eq!(error.type_name, "total_timeout_error");
+    }
 
-        assert_eq!(meta.status, 200);
-        assert_eq!(body, b"ok");
-        assert!(meta
-            .headers
-            .iter()
-            .any(|header| header.0.eq_ignore_ascii_case("content-type")
-                && header.1.contains("text/plain")));
+    #[test]
+    fn returns_write_timeout_error_when_body_is_not_read() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener must bind");
+        let addr = listener.local_addr().expect("local addr");
+        let server = thread::spawn(move || {
+            let (_stream, _) = listener.accept().expect("server must accept");
+            thread::sleep(StdDuration::from_millis(500));
+        });
 
-        let raw_request = received_request
-            .recv_timeout(StdDuration::from_secs(1))
-            .expect("must capture request");
-        let request_text = String::from_utf8(raw_request).expect("request should be utf-8");
-        assert!(request_text.starts_with("GET / HTTP/1.1"));
-        assert!(request_text.contains("x-demo: 1"));
+        let mut request = base_request();
+        request.method = "POST".to_string();
+        request.url = format!("http://{addr}/");
+        request.write_timeout_ms = Some(100);
+
+        let error = execute_request(request, Some(vec![0; 32 * 1024 * 1024]), None, None)
+            .expect_err("expected error");
+        server.join().expect("server thread must join");
+        assert_eq!(error.type_name, "write_timeout_error");
     }
 
     #[test]
-    fn sends_body_to_local_http_server() {
-        let response_body = "created";
-        let raw_response = format!(
-            "HTTP/1.1 201 Created\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
-            response_body.len(),
-            response_body
-        )
-        .into_bytes();
-        let (url, received_request, server) = spawn_test_server(raw_response, 300);
+    fn flushed_small_body_disarms_write_timeout_when_server_never_reads() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener must bind");
+        let addr = listener.local_addr().expect("local addr");
+        let server = thread::spawn(move || {
+            let (_stream, _) = listener.accept().expect("server must accept");
+            thread::sleep(StdDuration::from_millis(800));
+        });
 
         let mut request = base_request();
         request.method = "POST".to_string();
-        request.url = url;
+        request.url = format!("http://{addr}/");
+        request.write_timeout_ms = Some(100);
+        request.receive_timeout_ms = 400;
 
-        let (meta, _body) = execute_request(request, Some(b"hello".to_vec()), None)
-            .expect("request should succeed");
+        let error =
+            execute_request(request, Some(vec![0; 1024]), None, None).expect_err("expected error");
         server.join().expect("server thread must join");
+        assert_eq!(error.type_name, "read_timeout_error");
+    }
 
-        assert_eq!(meta.status, 201);
-        let raw_request = received_request
-            .recv_timeout(StdDuration::from_secs(1))
-            .expect("must capture request");
-        let request_text = String::from_utf8(raw_request).expect("request should be utf-8");
-        assert!(request_text.starts_with("POST / HTTP/1.1"));
-        assert!(request_text.contains("hello"));
+    #[test]
+    fn connect_timeout_is_part_of_client_key() {
+        let plain = base_request();
+        let mut quick = base_request();
+        quick.connect_timeout_ms = 1_000;
+
+        assert_ne!(ClientKey::new(&plain), ClientKey::new(&quick));
     }
 
     #[test]
-    fn returns_transport_error_on_receive_timeout() {
-        let (url, _received_request, server) = {
-            let listener = TcpListener::bind("127.0.0.1:0").expect("listener must bind");
-            let addr = listener.local_addr().expect("local addr");
-            let (tx, rx) = mpsc::channel::<Vec<u8>>();
+    fn connection_limit_queues_requests_on_the_open_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener must bind");
+        let addr = listener.local_addr().expect("local addr");
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("server must accept once");
+            for _ in 0..3 {
+                let mut head = Vec::new();
+                let mut byte = [0_u8; 1];
+                while !head.ends_with(b"\r\n\r\n") {
+                    stream.read_exact(&mut byte).expect("request head");
+                    head.push(byte[0]);
+                }
+                thread::sleep(StdDuration::from_millis(50));
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok")
+                    .expect("response should be written");
+            }
+            listener
+                .set_nonblocking(true)
+                .expect("listener should turn nonblocking");
+            listener.accept().is_ok()
+        });
+
+        let requests: Vec<_> = (0..3)
+            .map(|_| {
+                let mut request = base_request();
+                request.url = format!("http://{addr}/");
+                request.pool_max_idle_per_host = 4;
+                request.max_connections_per_host = Some(1);
+                thread::spawn(move || execute_request(request, None, None, None))
+            })
+            .collect();
+
+        for request in requests {
+            let (meta, body) = request
+                .join()
+                .expect("request thread must join")
+                .expect("request should succeed");
+            assert_eq!((meta.status, body.as_slice()), (200, b"ok".as_slice()));
+        }
+        assert!(
+            !server.join().expect("server thread must join"),
+            "no second connection is opened"
+        );
+    }
 
-            let handle = thread::spawn(move || {
-                let (mut stream, _) = listener.accept().expect("server must accept");
+    #[test]
+    fn connects_left_to_the_pool_keep_the_resolve_override() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener must bind");
+        let port = listener.local_addr().expect("local addr").port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("server must accept once");
+            for served in 0..2 {
+                let mut head = Vec::new();
+                let mut byte = [0_u8; 1];
+                while !head.ends_with(b"\r\n\r\n") {
+                    stream.read_exact(&mut byte).expect("request head");
+                    head.push(byte[0]);
+                }
+                if served == 0 {
+                    // Both requests are waiting by now, one of them on a
+                    // connect queued behind the limit.
+                    thread::sleep(StdDuration::from_millis(200));
+                }
                 stream
-                    .set_read_timeout(Some(StdDuration::from_millis(100)))
-                    .expect("read timeout should be set");
-                let mut request = Vec::new();
-                let mut buffer = [0_u8; 1024];
-                let _ = stream.read(&mut buffer).map(|bytes| {
-                    request.extend_from_slice(&buffer[..bytes]);
-                });
-                tx.send(request).expect("request should be sent");
-                thread::sleep(StdDuration::from_millis(350));
-                let _ = stream.write_all(
-                    b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok",
-                );
-                let _ = stream.flush();
-            });
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok")
+                    .expect("response should be written");
+            }
+            // Closing the connection frees the permit for the queued connect,
+            // which the pool finishes in the background.
+            drop(stream);
+            listener
+                .set_nonblocking(true)
+                .expect("listener should turn nonblocking");
+            let deadline = Instant::now() + StdDuration::from_secs(3);
+            while Instant::now() < deadline {
+                if listener.accept().is_ok() {
+                    return true;
+                }
+                thread::sleep(StdDuration::from_millis(10));
+            }
+            false
+        });
 
-            (format!("http://{addr}/"), rx, handle)
-        };
+        let requests: Vec<_> = (0..2)
+            .map(|_| {
+                let mut request = base_request();
+                request.url = format!("http://limited.cloaked-req.example:{port}/");
+                request.max_connections_per_host = Some(1);
+                request.resolve = vec![NativeResolve {
+                    host: "limited.cloaked-req.example".to_string(),
+                    port,
+                    addresses: vec!["127.0.0.1".to_string()],
+                }];
+                thread::spawn(move || execute_request(request, None, None, None))
+            })
+            .collect();
+
+        for request in requests {
+            let (meta, _body) = request
+                .join()
+                .expect("request thread must join")
+                .expect("request should succeed");
+            assert_eq!(meta.status, 200);
+        }
+        assert!(
+            server.join().expect("server thread must join"),
+            "the background connect resolves through the override"
+        );
+    }
 
+    #[test]
+    fn pool_settings_are_part_of_client_key() {
+        let plain = base_request();
+        let mut limited = base_request();
+        limited.max_connections_per_host = Some(6);
+        let mut pinged = base_request();
+        pinged.http2_keep_alive = Some(NativeHttp2KeepAlive {
+            interval_ms: 30_000,
+            timeout_ms: 5_000,
+            while_idle: false,
+        });
+
+        assert_ne!(ClientKey::new(&plain), ClientKey::new(&limited));
+        assert_ne!(ClientKey::new(&plain), ClientKey::new(&pinged));
+    }
+
+    #[test]
+    fn http2_keep_alive_builds_with_and_without_emulation() {
         let mut request = base_request();
-        request.url = url;
-        request.receive_timeout_ms = 50;
+        request.pool_idle_timeout_ms = None;
+        request.http2_keep_alive = Some(NativeHttp2KeepAlive {
+            interval_ms: 15_000,
+            timeout_ms: 5_000,
+            while_idle: true,
+        });
+        assert!(get_or_build_client(&request).is_ok());
 
-        let result = execute_request(request, None, None);
-        server.join().expect("server thread must join");
-        assert!(result.is_err());
-        let error = result.err().expect("expected error");
-        assert_eq!(error.type_name, "transport_error");
-        assert_eq!(error.message, "request execution failed");
+        request.emulation = Some("chrome_136".to_string());
+        assert!(get_or_build_client(&request).is_ok());
     }
 
     #[test]
@@ -564,14 +2363,33 @@ mod tests {
             url: "https://tlsinfo.me/json".to_string(),
             headers: vec![],
             receive_timeout_ms: 20_000,
+            connect_timeout_ms: 10_000,
+            write_timeout_ms: None,
+            total_timeout_ms: None,
+            low_speed: None,
             emulation: Some("chrome_136".to_string()),
             insecure_skip_verify: false,
             max_body_size_bytes: None,
             local_address: None,
+            interface: None,
+            proxy: None,
+            redirect: NativeRedirect::default(),
+            client_certificate: None,
+            ca_certificates: None,
+            pins: Vec::new(),
+            tls_keylog: None,
+            resolve: Vec::new(),
+            dns: None,
+            ip_family: NativeIpFamily::Any,
+            happy_eyeballs_ms: Some(300),
+            pool_idle_timeout_ms: Some(90_000),
+            pool_max_idle_per_host: 20,
+            max_connections_per_host: None,
+            http2_keep_alive: None,
         };
 
         let (meta, body) =
-            execute_request(request, None, None).expect("fingerprint request should succeed");
+            execute_request(request, None, None, None).expect("fingerprint request should succeed");
         assert!(meta.status >= 200 && meta.status < 300);
 
         let payload: serde_json::Value =
@@ -596,7 +2414,7 @@ mod tests {
         request.url = url;
         request.max_body_size_bytes = Some(100);
 
-        let result = execute_request(request, None, None);
+        let result = execute_request(request, None, None, None);
         server.join().expect("server thread must join");
 
         assert!(result.is_err());
@@ -621,7 +2439,7 @@ mod tests {
         request.max_body_size_bytes = Some(1024);
 
         let (meta, response_body) =
-            execute_request(request, None, None).expect("request should succeed");
+            execute_request(request, None, None, None).expect("request should succeed");
         server.join().expect("server thread must join");
 
         assert_eq!(meta.status, 200);
@@ -637,7 +2455,8 @@ mod tests {
         let mut request = base_request();
         request.url = url;
 
-        let (meta, body) = execute_request(request, None, None).expect("request should succeed");
+        let (meta, body) =
+            execute_request(request, None, None, None).expect("request should succeed");
         server.join().expect("server thread must join");
 
         assert_eq!(meta.status, 204);
@@ -659,8 +2478,8 @@ mod tests {
         request.url = url;
         request.max_body_size_bytes = Some(100);
 
-        let (meta, response_body) =
-            execute_request(request, None, None).expect("request at exact limit should succeed");
+        let (meta, response_body) = execute_request(request, None, None, None)
+            .expect("request at exact limit should succeed");
         server.join().expect("server thread must join");
 
         assert_eq!(meta.status, 200);
@@ -680,7 +2499,7 @@ mod tests {
         let mut request = base_request();
         request.url = url;
 
-        let result = execute_request(request, None, None);
+        let result = execute_request(request, None, None, None);
         server.join().expect("server thread must join");
 
         // wreq may reject invalid header bytes at the HTTP parsing level.
@@ -739,6 +2558,112 @@ mod tests {
         assert_eq!(err.type_name, "transport_error");
     }
 
+    // --- streaming tests ---
+
+    fn chunked_response(chunks: &[&str]) -> Vec<u8> {
+        let mut raw =
+            b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\nconnection: close\r\n\r\n".to_vec();
+        for chunk in chunks {
+            raw.extend_from_slice(format!("{:x}\r\n{chunk}\r\n", chunk.len()).as_bytes());
+        }
+        raw.extend_from_slice(b"0\r\n\r\n");
+        raw
+    }
+
+    fn stream_chunks(
+        request: NativeRequest,
+        stop_after: usize,
+    ) -> Result<(NativeResponseMeta, Vec<Vec<u8>>), NativeError> {
+        let client = get_or_build_client(&request)?;
+
+        RUNTIME.block_on(async move {
+            let (meta, mut response) = send_request(&client, &request, None, None).await?;
+            let mut chunks = Vec::new();
+            stream_body_with_limit(&mut response, &request, None, |chunk| {
+                chunks.push(chunk.to_vec());
+                chunks.len() < stop_after
+            })
+            .await?;
+            Ok((meta, chunks))
+        })
+    }
+
+    #[test]
+    fn streams_chunked_body_to_sink() {
+        let (url, _rx, server) =
+            spawn_test_server(chunked_response(&["hello ", "streamed ", "world"]), 200);
+
+        let mut request = base_request();
+        request.url = url;
+
+        let (meta, chunks) = stream_chunks(request, usize::MAX).expect("stream should succeed");
+        server.join().expect("server thread must join");
+
+        assert_eq!(meta.status, 200);
+        assert_eq!(chunks.concat(), b"hello streamed world");
+    }
+
+    #[test]
+    fn stream_applies_max_body_size_to_running_total() {
+        let chunk = "x".repeat(60);
+        let (url, _rx, server) = spawn_test_server(chunked_response(&[&chunk, &chunk]), 200);
+
+        let mut request = base_request();
+        request.url = url;
+        request.max_body_size_bytes = Some(100);
+
+        let err = stream_chunks(request, usize::MAX).expect_err("expected error");
+        server.join().expect("server thread must join");
+
+        assert_eq!(err.type_name, "invalid_request");
+        assert_eq!(err.message, "response body exceeds max_body_size");
+    }
+
+    #[test]
+    fn stream_stops_when_sink_declines_more_chunks() {
+        let (url, _rx, server) = spawn_test_server(chunked_response(&["first", "second"]), 200);
+
+        let mut request = base_request();
+        request.url = url;
+
+        let (_meta, chunks) = stream_chunks(request, 1).expect("stream should succeed");
+        server.join().expect("server thread must join");
+
+        assert_eq!(chunks.len(), 1);
+    }
+
+    #[test]
+    fn stream_reads_only_the_chunks_asked_for() {
+        let (url, _rx, server) =
+            spawn_test_server(chunked_response(&["first", "second", "third"]), 200);
+
+        let mut request = base_request();
+        request.url = url;
+        let client = get_or_build_client(&request).expect("client should build");
+        let demand = Demand::default();
+        let mut chunks = Vec::new();
+
+        RUNTIME.block_on(async {
+            let (_meta, mut response) = send_request(&client, &request, None, None)
+                .await
+                .expect("request should succeed");
+
+            demand.grant();
+            let streamed = tokio::time::timeout(
+                Duration::from_millis(200),
+                stream_body_with_limit(&mut response, &request, Some(&demand), |chunk| {
+                    chunks.push(chunk.to_vec());
+                    true
+                }),
+            )
+            .await;
+            assert!(streamed.is_err(), "the stream waits for more demand");
+        });
+        server.join().expect("server thread must join");
+
+        assert_eq!(chunks, vec![b"first".to_vec()]);
+    }
+
     // --- local_address tests ---
 
     #[test]
@@ -746,7 +2671,7 @@ mod tests {
         let mut request = base_request();
         request.local_address = Some("not-an-ip".to_string());
 
-        let result = execute_request(request, None, None);
+        let result = execute_request(request, None, None, None);
         assert!(result.is_err());
 
         let err = result.err().expect("expected error");
@@ -754,6 +2679,27 @@ mod tests {
         assert_eq!(err.message, "invalid local_address");
     }
 
+    #[test]
+    fn rejects_invalid_interface() {
+        for name in ["", "a-name-longer-than-15", "eth0:1", "../lo"] {
+            let mut request = base_request();
+            request.interface = Some(name.to_string());
+
+            let err = execute_request(request, None, None, None).expect_err("expected error");
+            assert_eq!(err.type_name, "invalid_request");
+            assert_eq!(err.message, "invalid interface");
+        }
+    }
+
+    #[test]
+    fn interface_clients_are_cached_separately() {
+        let plain = base_request();
+        let mut bound = base_request();
+        bound.interface = Some("lo".to_string());
+
+        assert_ne!(ClientKey::new(&plain), ClientKey::new(&bound));
+    }
+
     #[test]
     fn accepts_loopback_local_address() {
         let response_body = "ok";
@@ -769,11 +2715,174 @@ mod tests {
         request.url = url;
         request.local_address = Some("127.0.0.1".to_string());
 
-        let (meta, body) = execute_request(request, None, None).expect("request should succeed");
+        let (meta, body) =
+            execute_request(request, None, None, None).expect("request should succeed");
+        server.join().expect("server thread must join");
+
+        assert_eq!(meta.status, 200);
+        assert_eq!(body, b"ok");
+    }
+
+    // --- proxy tests ---
+
+    /// Minimal SOCKS5 stand-in: negotiates username/password auth, records the
+    /// requested target, then answers the tunneled HTTP request itself.
+    fn spawn_socks5_server(
+        response_bytes: Vec<u8>,
+    ) -> (
+        String,
+        mpsc::Receiver<(String, String, String, u16)>,
+        thread::JoinHandle<()>,
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener must bind");
+        let addr = listener.local_addr().expect("local addr must be available");
+        let (tx, rx) = mpsc::channel();
+
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("proxy must accept");
+
+            let mut header = [0_u8; 2];
+            stream.read_exact(&mut header).expect("greeting");
+            let mut methods = vec![0_u8; header[1] as usize];
+            stream.read_exact(&mut methods).expect("greeting methods");
+            assert!(methods.contains(&0x02), "client must offer password auth");
+            stream.write_all(&[0x05, 0x02]).expect("method selection");
+
+            let mut len = [0_u8; 2];
+            stream.read_exact(&mut len).expect("auth header");
+            let mut username = vec![0_u8; len[1] as usize];
+            stream.read_exact(&mut username).expect("username");
+            stream.read_exact(&mut len[..1]).expect("password length");
+            let mut password = vec![0_u8; len[0] as usize];
+            stream.read_exact(&mut password).expect("password");
+            stream.write_all(&[0x01, 0x00]).expect("auth reply");
+
+            let mut connect = [0_u8; 4];
+            stream.read_exact(&mut connect).expect("connect header");
+            assert_eq!(connect[3], 0x03, "socks5h must send the domain name");
+            stream.read_exact(&mut len[..1]).expect("domain length");
+            let mut domain = vec![0_u8; len[0] as usize];
+            stream.read_exact(&mut domain).expect("domain");
+            let mut port = [0_u8; 2];
+            stream.read_exact(&mut port).expect("port");
+            stream
+                .write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+                .expect("connect reply");
+
+            let mut request = Vec::new();
+            let mut buffer = [0_u8; 2048];
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                let bytes = stream.read(&mut buffer).expect("tunneled request");
+                if bytes == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buffer[..bytes]);
+            }
+
+            tx.send((
+                String::from_utf8_lossy(&domain).into_owned(),
+                String::from_utf8_lossy(&username).into_owned(),
+                String::from_utf8_lossy(&password).into_owned(),
+                u16::from_be_bytes(port),
+            ))
+            .expect("handshake should be sent");
+            stream
+                .write_all(&response_bytes)
+                .expect("response should be written");
+            stream.flush().expect("response should flush");
+        });
+
+        (format!("socks5h://{addr}"), rx, handle)
+    }
+
+    #[test]
+    fn rejects_unsupported_proxy_scheme() {
+        let mut request = base_request();
+        request.proxy = Some(NativeProxy {
+            url: "ftp://127.0.0.1:2121".to_string(),
+            username: None,
+            password: None,
+        });
+
+        let err = execute_request(request, None, None, None).expect_err("expected error");
+        assert_eq!(err.type_name, "invalid_request");
+        assert_eq!(err.message, "unsupported proxy scheme");
+    }
+
+    #[test]
+    fn rejects_proxy_url_without_scheme() {
+        let mut request = base_request();
+        request.proxy = Some(NativeProxy {
+            url: "not a url".to_string(),
+            username: None,
+            password: None,
+        });
+
+        let err = execute_request(request, None, None, None).expect_err("expected error");
+        assert_eq!(err.type_name, "invalid_request");
+        assert_eq!(err.message, "invalid proxy url");
+    }
+
+    #[test]
+    fn forwards_plain_http_through_http_proxy_with_credentials() {
+        let raw_response =
+            b"HTTP/1.1 200 OK\r\ncontent-length: 7\r\nconnection: close\r\n\r\nproxied".to_vec();
+        let (proxy_url, received_request, server) = spawn_test_server(raw_response, 200);
+
+        let mut request = base_request();
+        request.url = "http://example.com/path".to_string();
+        request.proxy = Some(NativeProxy {
+            url: proxy_url,
+            username: Some("user".to_string()),
+            password: Some("secret".to_string()),
+        });
+
+        let (meta, body) =
+            execute_request(request, None, None, None).expect("request should succeed");
         server.join().expect("server thread must join");
 
+        assert_eq!(meta.status, 200);
+        assert_eq!(body, b"proxied");
+
+        let raw_request = received_request
+            .recv_timeout(StdDuration::from_secs(1))
+            .expect("must capture request");
+        let request_text = String::from_utf8(raw_request)
+            .expect("request should be utf-8")
+            .to_lowercase();
+        assert!(request_text.starts_with("get http://example.com/path http/1.1"));
+        // base64("user:secret")
+        assert!(request_text.contains("proxy-authorization: basic dxnlcjpzzwnyzxq="));
+    }
+
+    #[test]
+    fn tunnels_through_socks5h_proxy_with_remote_dns() {
+        let raw_response =
+            b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok".to_vec();
+        let (proxy_url, handshake, server) = spawn_socks5_server(raw_response);
+
+        let mut request = base_request();
+        request.url = "http://socks-only.invalid:8080/".to_string();
+        request.proxy = Some(NativeProxy {
+            url: proxy_url,
+            username: Some("user".to_string()),
+            password: Some("secret".to_string()),
+        });
+
+        let (meta, body) =
+            execute_request(request, None, None, None).expect("request should succeed");
+        server.join().expect("proxy thread must join");
+
         assert_eq!(meta.status, 200);
         assert_eq!(body, b"ok");
+
+        let (domain, username, password, port) = handshake
+            .recv_timeout(StdDuration::from_secs(1))
+            .expect("must capture handshake");
+        assert_eq!(domain, "socks-only.invalid");
+        assert_eq!(port, 8080);
+        assert_eq!(username, "user");
+        assert_eq!(password, "secret");
     }
 
     // --- Cookie domain safety tests ---
@@ -838,4 +2947,27 @@ mod tests {
     fn psl_rejects_non_utf8_header() {
         assert!(!is_cookie_domain_safe(&[0xff, 0xfe], "example.com"));
     }
+
+    #[test]
+    fn put_cookie_applies_set_cookie_domain_checks() {
+        let jar = CookieStore::default();
+
+        put_cookie(&jar, "https://www.example.com/", "a=1; Domain=example.com")
+            .expect("parent domain should be accepted");
+        let rejected = [
+            ("https://www.example.com/", "b=2; Domain=com"),
+            ("https://www.example.com/", "b=2; Domain=other.com"),
+            ("ftp://example.com/", "b=2"),
+            ("https://example.com/", "missing-value"),
+        ];
+        for (url, set_cookie) in rejected {
+            let err = put_cookie(&jar, url, set_cookie).expect_err("cookie should be rejected");
+            assert_eq!(err.type_name, "invalid_request", "{set_cookie} from {url}");
+        }
+
+        assert_eq!(
+            jar.header_for(&Url::parse("https://api.example.com/").expect("url should parse")),
+            Some("a=1".to_string())
+        );
+    }
 }
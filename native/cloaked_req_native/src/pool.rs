@@ -0,0 +1,363 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use http::Uri;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tower::{BoxError, Layer, Service};
+use url::Url;
+use wreq::connect::{Conn, Unnameable};
+use wreq::Client;
+
+/// A client together with the per-origin counts reported by
+/// [`PooledClient::host_stats`].
+///
+/// wreq does not expose its pool, so connections are counted by the
+/// client's [`ConnectionLayer`] instead: from the moment one is established
+/// until wreq drops it, whether it is busy or idle in the pool.
+pub struct PooledClient {
+    pub client: Client,
+    origins: Arc<Origins>,
+}
+
+/// Counts for one origin, see [`PooledClient::host_stats`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostStats {
+    pub origin: String,
+    /// Open connections, busy or idle.
+    pub connections: usize,
+    /// Requests sent and not yet done with their response. HTTP/2 requests
+    /// share a connection.
+    pub in_flight: usize,
+}
+
+/// Per-origin state shared by a client's connector and its requests.
+pub struct Origins {
+    /// `:max_connections_per_host`.
+    limit: Option<usize>,
+    origins: Mutex<HashMap<String, Arc<Origin>>>,
+}
+
+#[derive(Default)]
+struct Origin {
+    permits: Option<Arc<Semaphore>>,
+    connections: AtomicUsize,
+    in_flight: AtomicUsize,
+}
+
+impl Origins {
+    pub fn new(limit: Option<u32>) -> Arc<Self> {
+        Arc::new(Self {
+            limit: limit.map(|limit| limit as usize),
+            origins: Mutex::default(),
+        })
+    }
+
+    fn get(&self, origin: String) -> Arc<Origin> {
+        let mut origins = self.origins.lock().unwrap_or_else(|e| e.into_inner());
+        if !origins.contains_key(&origin) {
+            // Forget origins without connections or requests before tracking
+            // another one. Each of them holds a reference to its origin.
+            origins.retain(|_, origin| Arc::strong_count(origin) > 1);
+        }
+        origins
+            .entry(origin)
+            .or_insert_with(|| {
+                Arc::new(Origin {
+                    permits: self.limit.map(|limit| Arc::new(Semaphore::new(limit))),
+                    ..Origin::default()
+                })
+            })
+            .clone()
+    }
+}
+
+impl PooledClient {
+    pub fn new(client: Client, origins: Arc<Origins>) -> Self {
+        Self { client, origins }
+    }
+
+    /// Registers a request to the origin of `url`.
+    pub fn track(&self, url: &Url) -> InFlight {
+        let origin = self.origins.get(url.origin().ascii_serialization());
+        origin.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(origin)
+    }
+
+    /// Open connections and requests in flight per origin, sorted by origin.
+    /// Origins with neither are left out.
+    pub fn host_stats(&self) -> Vec<HostStats> {
+        let origins = self
+            .origins
+            .origins
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let mut stats: Vec<HostStats> = origins
+            .iter()
+            .map(|(name, origin)| HostStats {
+                origin: name.clone(),
+                connections: origin.connections.load(Ordering::Relaxed),
+                in_flight: origin.in_flight.load(Ordering::Relaxed),
+            })
+            .filter(|stats| stats.connections > 0 || stats.in_flight > 0)
+            .collect();
+        stats.sort_by(|a, b| a.origin.cmp(&b.origin));
+        stats
+    }
+}
+
+/// A request in flight to one origin. Dropping it ends the request.
+pub struct InFlight(Arc<Origin>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Request whose response has arrived, kept in the response's extensions so
+/// it ends together with the response.
+#[derive(Clone)]
+struct HeldRequest {
+    _request: Arc<InFlight>,
+}
+
+/// Ties `request` to `response`.
+pub fn hold(response: &mut wreq::Response, request: InFlight) {
+    response.extensions_mut().insert(HeldRequest {
+        _request: Arc::new(request),
+    });
+}
+
+/// An open connection, attached to wreq's [`Conn`] so it is counted until
+/// the connection is closed. Holds the permit of a limited origin.
+struct OpenConnection {
+    origin: Arc<Origin>,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl OpenConnection {
+    fn new(origin: Arc<Origin>, permit: Option<OwnedSemaphorePermit>) -> Self {
+        origin.connections.fetch_add(1, Ordering::Relaxed);
+        Self {
+            origin,
+            _permit: permit,
+        }
+    }
+}
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.origin.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Connector layer that counts open connections per origin and enforces
+/// `:max_connections_per_host`.
+///
+/// With a limit, every new connection takes a permit for its origin and keeps
+/// it until the connection is closed, so at most that many connections per
+/// origin are open at once. A request that finds none idle waits until one is
+/// returned to the pool or closed.
+#[derive(Clone)]
+pub struct ConnectionLayer {
+    origins: Arc<Origins>,
+}
+
+impl ConnectionLayer {
+    pub fn new(origins: Arc<Origins>) -> Self {
+        Self { origins }
+    }
+}
+
+impl<S> Layer<S> for ConnectionLayer {
+    type Service = Connections<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Connections {
+            inner,
+            origins: self.origins.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Connections<S> {
+    inner: S,
+    origins: Arc<Origins>,
+}
+
+impl<S> Service<Unnameable> for Connections<S>
+where
+    S: Service<Unnameable, Response = Conn, Error = BoxError> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Conn;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Conn, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Unnameable) -> Self::Future {
+        let origin = self.origins.get(origin(request.uri()));
+        // Connect with the service that was polled ready, once a permit is
+        // free, and leave a fresh clone in its place.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let permit = match &origin.permits {
+                Some(permits) => Some(permits.clone().acquire_owned().await?),
+                None => None,
+            };
+            let mut conn = inner.call(request).await?;
+            conn.attach(OpenConnection::new(origin, permit));
+            Ok(conn)
+        })
+    }
+}
+
+/// The origin of a connect target, serialized like [`url::Origin`].
+fn origin(uri: &Uri) -> String {
+    let scheme = uri.scheme_str().unwrap_or("http");
+    let host = uri.host().unwrap_or_default();
+    let default_port = match scheme {
+        "https" => 443,
+        _ => 80,
+    };
+    match uri.port_u16() {
+        Some(port) if port != default_port => format!("{scheme}://{host}:{port}"),
+        _ => format!("{scheme}://{host}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .expect("runtime should build")
+    }
+
+    fn url(value: &str) -> Url {
+        Url::parse(value).expect("url should parse")
+    }
+
+    fn origin_named(origins: &Origins, name: &str) -> Arc<Origin> {
+        origins.get(name.to_string())
+    }
+
+    #[test]
+    fn limits_connections_per_origin() {
+        let origins = Origins::new(Some(2));
+        let permits = origin_named(&origins, "http://example.com")
+            .permits
+            .clone()
+            .expect("a limit hands out permits");
+
+        runtime().block_on(async {
+            let first = permits.clone().acquire_owned().await;
+            let _second = permits.clone().acquire_owned().await;
+            let third =
+                tokio::time::timeout(Duration::from_millis(50), permits.clone().acquire_owned())
+                    .await;
+            assert!(third.is_err(), "a third connection waits for a permit");
+
+            let other = origin_named(&origins, "http://example.com:8080");
+            assert_eq!(
+                other
+                    .permits
+                    .as_ref()
+                    .map(|permits| permits.available_permits()),
+                Some(2),
+                "other origins have their own permits"
+            );
+
+            drop(first);
+            assert_eq!(permits.available_permits(), 1);
+        });
+    }
+
+    #[test]
+    fn hands_out_no_permits_without_a_limit() {
+        let origins = Origins::new(None);
+
+        assert!(origin_named(&origins, "https://example.com")
+            .permits
+            .is_none());
+    }
+
+    #[test]
+    fn forgets_origins_without_connections_or_requests() {
+        let origins = Origins::new(Some(6));
+        let _held = origin_named(&origins, "https://b.example");
+        drop(origin_named(&origins, "https://a.example"));
+
+        origin_named(&origins, "https://c.example");
+
+        let tracked = origins.origins.lock().expect("lock should not be poisoned");
+        let mut names: Vec<_> = tracked.keys().collect();
+        names.sort();
+        assert_eq!(names, vec!["https://b.example", "https://c.example"]);
+    }
+
+    #[test]
+    fn counts_open_connections_and_requests_in_flight() {
+        let pooled = PooledClient::new(Client::new(), Origins::new(None));
+        let page = url("http://example.com/");
+
+        let first = pooled.track(&page);
+        let second = pooled.track(&page);
+        let connection =
+            OpenConnection::new(origin_named(&pooled.origins, "http://example.com"), None);
+        let other = pooled.track(&url("https://other.example/"));
+
+        assert_eq!(
+            pooled.host_stats(),
+            vec![
+                HostStats {
+                    origin: "http://example.com".to_string(),
+                    connections: 1,
+                    in_flight: 2,
+                },
+                HostStats {
+                    origin: "https://other.example".to_string(),
+                    connections: 0,
+                    in_flight: 1,
+                },
+            ]
+        );
+
+        drop((first, second, other));
+        assert_eq!(
+            pooled.host_stats(),
+            vec![HostStats {
+                origin: "http://example.com".to_string(),
+                connections: 1,
+                in_flight: 0,
+            }]
+        );
+
+        drop(connection);
+        assert!(pooled.host_stats().is_empty());
+    }
+
+    #[test]
+    fn origins_leave_out_default_ports() {
+        let origin = |uri: &str| origin(&uri.parse().expect("uri should parse"));
+
+        assert_eq!(origin("http://example.com:80/a"), "http://example.com");
+        assert_eq!(origin("https://example.com"), "https://example.com");
+        assert_eq!(origin("https://example.com:80"), "https://example.com:80");
+        assert_eq!(origin("http://[::1]:8080/"), "http://[::1]:8080");
+    }
+}
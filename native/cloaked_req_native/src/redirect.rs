@@ -0,0 +1,332 @@
+use serde_json::json;
+use url::Url;
+use wreq::Method;
+
+use crate::error::NativeError;
+use crate::request::{NativeRedirect, NativeRedirectMode};
+
+/// Request headers dropped on cross-origin hops unless the redirect is trusted.
+const SENSITIVE_HEADERS: [&str; 3] = ["authorization", "cookie", "proxy-authorization"];
+
+/// Request headers dropped together with the body when a redirect switches to `GET`.
+const BODY_HEADERS: [&str; 3] = ["content-type", "content-length", "transfer-encoding"];
+
+/// The follow-up request for a redirect response.
+#[derive(Debug, PartialEq, Eq)]
+pub struct NextHop {
+    pub url: Url,
+    pub method: Method,
+    /// `false` when the redirect turns the request into a body-less `GET`.
+    pub keep_body: bool,
+    pub cross_origin: bool,
+}
+
+/// Decides whether the response at `current` is followed under `policy`.
+///
+/// Returns `Ok(None)` when the response is final: redirects are disabled, the
+/// status is not a redirect, there is no `Location`, or the target leaves the
+/// origin in same-origin mode. `hops` is the number of redirects already followed.
+pub fn next_hop(
+    policy: &NativeRedirect,
+    current: &Url,
+    method: &Method,
+    status: u16,
+    location: Option<&str>,
+    hops: u32,
+) -> Result<Option<NextHop>, NativeError> {
+    if policy.mode == NativeRedirectMode::None || !matches!(status, 301 | 302 | 303 | 307 | 308) {
+        return Ok(None);
+    }
+
+    let Some(location) = location else {
+        return Ok(None);
+    };
+
+    let url = current.join(location).map_err(|reason| {
+        NativeError::new(
+            "redirect_error",
+            "invalid redirect location",
+            json!({"reason": reason.to_string(), "location": location}),
+        )
+    })?;
+
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(NativeError::new(
+            "redirect_error",
+            "unsupported redirect scheme",
+            json!({"scheme": url.scheme()}),
+        ));
+    }
+
+    let cross_origin = url.origin() != current.origin();
+    if cross_origin && policy.mode == NativeRedirectMode::SameOrigin {
+        return Ok(None);
+    }
+
+    if current.scheme() == "https" && url.scheme() == "http" && !policy.allow_downgrade {
+        return Err(NativeError::new(
+            "redirect_error",
+            "refusing to follow redirect from https to http",
+            json!({"location": url.as_str()}),
+        ));
+    }
+
+    if hops >= policy.max {
+        return Err(NativeError::new(
+            "redirect_error",
+            "too many redirects",
+            json!({"max_redirects": policy.max}),
+        ));
+    }
+
+    // Like browsers and Req, 301/302/303 turn anything but GET/HEAD into a GET.
+    let switch_to_get =
+        matches!(status, 301..=303) && *method != Method::GET && *method != Method::HEAD;
+    let method = if switch_to_get {
+        Method::GET
+    } else {
+        method.clone()
+    };
+
+    Ok(Some(NextHop {
+        url,
+        method,
+        keep_body: !switch_to_get,
+        cross_origin,
+    }))
+}
+
+/// Removes the request headers that must not be carried over to `hop`.
+pub fn retain_headers(headers: &mut Vec<(String, String)>, hop: &NextHop, policy: &NativeRedirect) {
+    headers.retain(|(name, _)| {
+        let sensitive = hop.cross_origin && !policy.trusted && is_one_of(name, &SENSITIVE_HEADERS);
+        let body_header = !hop.keep_body && is_one_of(name, &BODY_HEADERS);
+        !(sensitive || body_header)
+    });
+}
+
+fn is_one_of(name: &str, candidates: &[&str]) -> bool {
+    candidates.iter().any(|c| name.eq_ignore_ascii_case(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(mode: NativeRedirectMode) -> NativeRedirect {
+        NativeRedirect {
+            mode,
+            ..NativeRedirect::default()
+        }
+    }
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).expect("test url must parse")
+    }
+
+    fn hop(
+        policy: &NativeRedirect,
+        from: &str,
+        method: Method,
+        status: u16,
+        location: &str,
+        hops: u32,
+    ) -> Result<Option<NextHop>, NativeError> {
+        next_hop(policy, &url(from), &method, status, Some(location), hops)
+    }
+
+    #[test]
+    fn none_mode_never_follows() {
+        let policy = policy(NativeRedirectMode::None);
+        let next = hop(&policy, "https://a.test/", Method::GET, 302, "/next", 0).unwrap();
+        assert!(next.is_none());
+    }
+
+    #[test]
+    fn ignores_non_redirect_status_and_missing_location() {
+        let policy = policy(NativeRedirectMode::Follow);
+        assert!(
+            hop(&policy, "https://a.test/", Method::GET, 200, "/next", 0)
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            next_hop(&policy, &url("https://a.test/"), &Method::GET, 302, None, 0)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn resolves_relative_location_against_current_url() {
+        let policy = policy(NativeRedirectMode::Follow);
+        let next = hop(
+            &policy,
+            "https://a.test/dir/page",
+            Method::GET,
+            302,
+            "other?x=1",
+            0,
+        )
+        .unwrap()
+        .expect("should follow");
+
+        assert_eq!(next.url.as_str(), "https://a.test/dir/other?x=1");
+        assert!(!next.cross_origin);
+    }
+
+    #[test]
+    fn see_other_switches_post_to_get_and_drops_body() {
+        let policy = policy(NativeRedirectMode::Follow);
+        let next = hop(&policy, "https://a.test/", Method::POST, 303, "/done", 0)
+            .unwrap()
+            .expect("should follow");
+
+        assert_eq!(next.method, Method::GET);
+        assert!(!next.keep_body);
+    }
+
+    #[test]
+    fn temporary_redirect_preserves_method_and_body() {
+        let policy = policy(NativeRedirectMode::Follow);
+        let next = hop(&policy, "https://a.test/", Method::POST, 307, "/retry", 0)
+            .unwrap()
+            .expect("should follow");
+
+        assert_eq!(next.method, Method::POST);
+        assert!(next.keep_body);
+    }
+
+    #[test]
+    fn same_origin_mode_stops_at_cross_origin_hop() {
+        let policy = policy(NativeRedirectMode::SameOrigin);
+
+        assert!(
+            hop(&policy, "https://a.test/", Method::GET, 302, "/local", 0)
+                .unwrap()
+                .is_some()
+        );
+        assert!(hop(
+            &policy,
+            "https://a.test/",
+            Method::GET,
+            302,
+            "https://b.test/",
+            0
+        )
+        .unwrap()
+        .is_none());
+        assert!(hop(
+            &policy,
+            "https://a.test/",
+            Method::GET,
+            302,
+            "https://a.test:8443/",
+            0
+        )
+        .unwrap()
+        .is_none());
+    }
+
+    #[test]
+    fn refuses_https_to_http_downgrade_unless_allowed() {
+        let mut policy = policy(NativeRedirectMode::Follow);
+        let error = hop(
+            &policy,
+            "https://a.test/",
+            Method::GET,
+            302,
+            "http://a.test/",
+            0,
+        )
+        .expect_err("downgrade should be refused");
+        assert_eq!(error.type_name, "redirect_error");
+
+        policy.allow_downgrade = true;
+        assert!(hop(
+            &policy,
+            "https://a.test/",
+            Method::GET,
+            302,
+            "http://a.test/",
+            0
+        )
+        .unwrap()
+        .is_some());
+    }
+
+    #[test]
+    fn enforces_max_redirects() {
+        let mut policy = policy(NativeRedirectMode::Follow);
+        policy.max = 2;
+
+        assert!(hop(&policy, "https://a.test/", Method::GET, 302, "/x", 1)
+            .unwrap()
+            .is_some());
+        let error = hop(&policy, "https://a.test/", Method::GET, 302, "/x", 2)
+            .expect_err("limit should be enforced");
+        assert_eq!(error.message, "too many redirects");
+    }
+
+    #[test]
+    fn rejects_non_http_location() {
+        let policy = policy(NativeRedirectMode::Follow);
+        let error = hop(
+            &policy,
+            "https://a.test/",
+            Method::GET,
+            302,
+            "ftp://a.test/file",
+            0,
+        )
+        .expect_err("ftp should be rejected");
+        assert_eq!(error.message, "unsupported redirect scheme");
+    }
+
+    #[test]
+    fn strips_credentials_on_cross_origin_hop_unless_trusted() {
+        let mut policy = policy(NativeRedirectMode::Follow);
+        let next = hop(
+            &policy,
+            "https://a.test/",
+            Method::GET,
+            302,
+            "https://b.test/",
+            0,
+        )
+        .unwrap()
+        .expect("should follow");
+        let original = vec![
+            ("Authorization".to_string(), "Bearer t".to_string()),
+            ("cookie".to_string(), "a=1".to_string()),
+            ("x-demo".to_string(), "1".to_string()),
+        ];
+
+        let mut headers = original.clone();
+        retain_headers(&mut headers, &next, &policy);
+        assert_eq!(headers, vec![("x-demo".to_string(), "1".to_string())]);
+
+        policy.trusted = true;
+        let mut headers = original.clone();
+        retain_headers(&mut headers, &next, &policy);
+        assert_eq!(headers, original);
+    }
+
+    #[test]
+    fn drops_body_headers_when_switching_to_get() {
+        let policy = policy(NativeRedirectMode::Follow);
+        let next = hop(&policy, "https://a.test/", Method::POST, 302, "/done", 0)
+            .unwrap()
+            .expect("should follow");
+        let mut headers = vec![
+            ("content-type".to_string(), "application/json".to_string()),
+            ("authorization".to_string(), "Bearer t".to_string()),
+        ];
+
+        retain_headers(&mut headers, &next, &policy);
+        assert_eq!(
+            headers,
+            vec![("authorization".to_string(), "Bearer t".to_string())]
+        );
+    }
+}
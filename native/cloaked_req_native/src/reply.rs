@@ -0,0 +1,74 @@
+use std::sync::{Arc, Mutex};
+
+use rustler::env::SavedTerm;
+use rustler::{Env, LocalPid, OwnedEnv, Term};
+
+/// Delivers `{ref, message}` tuples to a BEAM process from runtime threads.
+///
+/// The caller's reference is copied once into a private environment and
+/// re-copied into every outgoing message, because `send_and_clear` wipes the
+/// environment a message was built in.
+pub struct Reply {
+    pid: LocalPid,
+    ref_env: OwnedEnv,
+    reference: SavedTerm,
+    msg_env: OwnedEnv,
+    gate: Gate,
+}
+
+/// Stops the replies of a cancelled request.
+///
+/// Messages are sent with the gate held, so once [`Gate::close`] returns no
+/// message is on its way: it has either been delivered, and the caller can
+/// flush it, or it will never be sent.
+#[derive(Clone, Default)]
+pub struct Gate(Arc<Mutex<bool>>);
+
+impl Gate {
+    pub fn close(&self) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = true;
+    }
+}
+
+impl Reply {
+    pub fn new(pid: LocalPid, reference: Term, gate: Gate) -> Self {
+        let ref_env = OwnedEnv::new();
+        let reference = ref_env.save(reference);
+
+        Self {
+            pid,
+            ref_env,
+            reference,
+            msg_env: OwnedEnv::new(),
+            gate,
+        }
+    }
+
+    /// Sends `{ref, build(env)}`. Returns `false` when the recipient is gone
+    /// or the gate is closed.
+    ///
+    /// Must not be called from a BEAM scheduler thread.
+    pub fn send<F>(&mut self, build: F) -> bool
+    where
+        F: for<'a> FnOnce(Env<'a>) -> Term<'a>,
+    {
+        let Self {
+            pid,
+            ref_env,
+            reference,
+            msg_env,
+            gate,
+        } = self;
+
+        let closed = gate.0.lock().unwrap_or_else(|e| e.into_inner());
+        if *closed {
+            return false;
+        }
+        msg_env
+            .send_and_clear(pid, |env| {
+                let reference = ref_env.run(|ref_env| reference.load(ref_env).in_env(env));
+                (reference, build(env))
+            })
+            .is_ok()
+    }
+}
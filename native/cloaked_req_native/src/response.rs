@@ -1,16 +1,92 @@
-use rustler::NifMap;
-use serde::Serialize;
+use rustler::types::binary::{Binary, NewBinary};
+use rustler::{Decoder, Encoder, Env, NifMap, NifResult, Term};
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, NifMap)]
 pub struct NativeResponseMeta {
     pub status: u16,
     pub url: String,
     pub headers: Vec<(String, String)>,
+    /// Redirect responses that led to this one, oldest first.
+    pub redirects: Vec<NativeRedirectHop>,
+    pub timing: NativeTiming,
+    /// HTTP version used for the response, e.g. `"HTTP/2"`.
+    pub http_version: String,
+    /// TLS details of the connection; `None` for plain HTTP.
+    pub tls: Option<NativeTlsInfo>,
+}
+
+/// TLS details of the connection that served a response, read from the TLS
+/// session once the handshake is done.
+///
+/// `peer_certificates` is the DER encoded chain as sent by the server, leaf
+/// first.
+#[derive(Debug, Serialize, NifMap)]
+pub struct NativeTlsInfo {
+    /// Negotiated ALPN protocol, e.g. `"h2"`; `None` when the server chose none.
+    pub alpn: Option<String>,
+    /// Protocol version, e.g. `"TLSv1.3"`.
+    pub version: Option<String>,
+    /// Cipher suite name, e.g. `"TLS_AES_128_GCM_SHA256"`.
+    pub cipher: Option<String>,
+    /// Whether an earlier session was resumed.
+    pub session_resumed: bool,
+    pub peer_certificates: Vec<DerBinary>,
+}
+
+/// DER bytes, passed as an Erlang binary rather than a list of integers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DerBinary(pub Vec<u8>);
+
+impl Encoder for DerBinary {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        let mut binary = NewBinary::new(env, self.0.len());
+        binary.as_mut_slice().copy_from_slice(&self.0);
+        binary.into()
+    }
+}
+
+impl<'a> Decoder<'a> for DerBinary {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        let binary: Binary = term.decode()?;
+        Ok(Self(binary.as_slice().to_vec()))
+    }
+}
+
+/// A redirect response that was followed.
+///
+/// `cookies_accepted` and `cookies_rejected` split the hop's Set-Cookie values
+/// by the cookie jar's domain check; both are empty without a jar.
+#[derive(Debug, Serialize, NifMap)]
+pub struct NativeRedirectHop {
+    pub url: String,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub cookies_accepted: Vec<String>,
+    pub cookies_rejected: Vec<String>,
+}
+
+/// Request timing in microseconds.
+///
+/// `dns_us`, `connect_us` and `tls_us` describe the final hop's connection and
+/// are `None` when it reused a pooled connection (or, for DNS, connected to an
+/// IP literal). `ttfb_us` runs from sending the final hop, once its connection
+/// is set up, to its response headers. `body_us` and `total_us`, which
+/// includes redirects, are `None` for streamed responses, whose metadata is
+/// sent before the body; the adapter leaves them out for those.
+#[derive(Debug, Default, Serialize, NifMap)]
+pub struct NativeTiming {
+    pub dns_us: Option<u64>,
+    pub connect_us: Option<u64>,
+    pub tls_us: Option<u64>,
+    pub ttfb_us: u64,
+    pub body_us: Option<u64>,
+    pub total_us: Option<u64>,
 }
 
 #[cfg(test)]
 mod tests {
-    use super::NativeResponseMeta;
+    use super::{DerBinary, NativeRedirectHop, NativeResponseMeta, NativeTiming, NativeTlsInfo};
 
     #[test]
     fn serializes_expected_shape() {
@@ -21,6 +97,10 @@ mod tests {
                 ("content-type".to_string(), "text/plain".to_string()),
                 ("x-request-id".to_string(), "abc-123".to_string()),
             ],
+            redirects: vec![],
+            timing: NativeTiming::default(),
+            http_version: "HTTP/1.1".to_string(),
+            tls: None,
         };
 
         let json = serde_json::to_string(&meta).expect("should serialize");
@@ -40,6 +120,10 @@ mod tests {
             status: 204,
             url: "https://example.com".to_string(),
             headers: vec![],
+            redirects: vec![],
+            timing: NativeTiming::default(),
+            http_version: "HTTP/1.1".to_string(),
+            tls: None,
         };
 
         let json = serde_json::to_string(&meta).expect("should serialize");
@@ -59,6 +143,10 @@ mod tests {
             status: 200,
             url: "https://example.com".to_string(),
             headers,
+            redirects: vec![],
+            timing: NativeTiming::default(),
+            http_version: "HTTP/1.1".to_string(),
+            tls: None,
         };
 
         let json = serde_json::to_string(&meta).expect("should serialize");
@@ -70,4 +158,91 @@ mod tests {
         assert_eq!(parsed_headers[49][0], "x-header-49");
         assert_eq!(parsed_headers[49][1], "value-49");
     }
+
+    #[test]
+    fn serializes_redirect_history() {
+        let meta = NativeResponseMeta {
+            status: 200,
+            url: "https://b.example/".to_string(),
+            headers: vec![],
+            redirects: vec![NativeRedirectHop {
+                url: "https://a.example/login".to_string(),
+                status: 302,
+                headers: vec![("location".to_string(), "https://b.example/".to_string())],
+                cookies_accepted: vec!["session=1; Path=/".to_string()],
+                cookies_rejected: vec!["evil=1; Domain=com".to_string()],
+            }],
+            timing: NativeTiming::default(),
+            http_version: "HTTP/1.1".to_string(),
+            tls: None,
+        };
+
+        let json = serde_json::to_string(&meta).expect("should serialize");
+        let decoded: serde_json::Value = serde_json::from_str(&json).expect("should parse back");
+
+        let hop = &decoded["redirects"][0];
+        assert_eq!(hop["url"], "https://a.example/login");
+        assert_eq!(hop["status"], 302);
+        assert_eq!(hop["headers"][0][0], "location");
+        assert_eq!(hop["cookies_accepted"][0], "session=1; Path=/");
+        assert_eq!(hop["cookies_rejected"][0], "evil=1; Domain=com");
+    }
+
+    #[test]
+    fn serializes_timing() {
+        let meta = NativeResponseMeta {
+            status: 200,
+            url: "https://example.com".to_string(),
+            headers: vec![],
+            redirects: vec![],
+            timing: NativeTiming {
+                dns_us: None,
+                connect_us: Some(1_200),
+                tls_us: Some(8_500),
+                ttfb_us: 20_000,
+                body_us: Some(300),
+                total_us: Some(20_400),
+            },
+            http_version: "HTTP/1.1".to_string(),
+            tls: None,
+        };
+
+        let json = serde_json::to_string(&meta).expect("should serialize");
+        let decoded: serde_json::Value = serde_json::from_str(&json).expect("should parse back");
+
+        assert!(decoded["timing"]["dns_us"].is_null());
+        assert_eq!(decoded["timing"]["connect_us"], 1_200);
+        assert_eq!(decoded["timing"]["tls_us"], 8_500);
+        assert_eq!(decoded["timing"]["ttfb_us"], 20_000);
+        assert_eq!(decoded["timing"]["total_us"], 20_400);
+    }
+
+    #[test]
+    fn serializes_protocol_details() {
+        let meta = NativeResponseMeta {
+            status: 200,
+            url: "https://example.com".to_string(),
+            headers: vec![],
+            redirects: vec![],
+            timing: NativeTiming::default(),
+            http_version: "HTTP/2".to_string(),
+            tls: Some(NativeTlsInfo {
+                alpn: Some("h2".to_string()),
+                version: Some("TLSv1.3".to_string()),
+                cipher: Some("TLS_AES_128_GCM_SHA256".to_string()),
+                session_resumed: true,
+                peer_certificates: vec![DerBinary(vec![0x30, 0x82])],
+            }),
+        };
+
+        let json = serde_json::to_string(&meta).expect("should serialize");
+        let decoded: serde_json::Value = serde_json::from_str(&json).expect("should parse back");
+
+        assert_eq!(decoded["http_version"], "HTTP/2");
+        assert_eq!(decoded["tls"]["alpn"], "h2");
+        assert_eq!(decoded["tls"]["version"], "TLSv1.3");
+        assert_eq!(decoded["tls"]["cipher"], "TLS_AES_128_GCM_SHA256");
+        assert_eq!(decoded["tls"]["session_resumed"], true);
+        assert_eq!(decoded["tls"]["peer_certificates"][0][0], 0x30);
+    }
 }
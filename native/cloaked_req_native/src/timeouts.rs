@@ -0,0 +1,381 @@
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use http_body::{Body, Frame, SizeHint};
+use serde_json::json;
+use tokio::time::Instant;
+
+use crate::error::NativeError;
+use crate::request::{NativeLowSpeed, NativeRequest};
+
+/// Size of the pieces a request body is handed to the connection in, so a
+/// stalled upload shows up as a gap between pieces.
+const WRITE_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Runs the whole request, redirects and body download included, under
+/// `total_timeout_ms`.
+pub async fn with_total<T, F>(total_timeout_ms: Option<u64>, future: F) -> Result<T, NativeError>
+where
+    F: Future<Output = Result<T, NativeError>>,
+{
+    let Some(timeout_ms) = total_timeout_ms else {
+        return future.await;
+    };
+
+    tokio::time::timeout(Duration::from_millis(timeout_ms), future)
+        .await
+        .unwrap_or_else(|_| {
+            Err(NativeError::new(
+                "total_timeout_error",
+                "request exceeded total_timeout",
+                json!({"timeout_ms": timeout_ms}),
+            ))
+        })
+}
+
+/// Maps a wreq error to the timeout that caused it, or to a `transport_error`
+/// with `message`.
+pub fn transport_error(reason: wreq::Error, message: &str, request: &NativeRequest) -> NativeError {
+    if let Some(error) = layer_error(&reason) {
+        return error.clone();
+    }
+
+    // reason = Display (user-friendly message), debug = Debug (inner error chain for diagnostics)
+    let reason_text = reason.to_string();
+    let debug = format!("{reason:?}");
+
+    if reason.is_timeout() && reason.is_connect() {
+        NativeError::new(
+            "connect_timeout_error",
+            "connect timed out",
+            json!({"reason": reason_text, "debug": debug, "timeout_ms": request.connect_timeout_ms}),
+        )
+    } else if reason.is_timeout() {
+        NativeError::new(
+            "read_timeout_error",
+            "read timed out",
+            json!({"reason": reason_text, "debug": debug, "timeout_ms": request.receive_timeout_ms}),
+        )
+    } else {
+        NativeError::new(
+            "transport_error",
+            message,
+            json!({"reason": reason_text, "debug": debug}),
+        )
+    }
+}
+
+/// The error one of our connector layers failed the connection with, if any.
+fn layer_error(reason: &wreq::Error) -> Option<&NativeError> {
+    let mut source = std::error::Error::source(reason);
+    while let Some(error) = source {
+        if let Some(native) = error.downcast_ref::<NativeError>() {
+            return Some(native);
+        }
+        source = error.source();
+    }
+    None
+}
+
+/// How far a request body has been handed to the connection.
+#[derive(Debug, Default)]
+pub struct WriteProgress {
+    state: Mutex<WriteState>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct WriteState {
+    last_write: Option<Instant>,
+    done: bool,
+}
+
+impl WriteProgress {
+    fn state(&self) -> WriteState {
+        *self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn update(&self, f: impl FnOnce(&mut WriteState)) {
+        f(&mut self.state.lock().unwrap_or_else(|e| e.into_inner()));
+    }
+}
+
+/// Resolves with a `write_timeout_error` once the connection has not taken a
+/// piece of the request body for `timeout_ms`.
+///
+/// The clock starts when the connection asks for the first piece, so connect
+/// time is not counted, and stops once the connection is done with the body,
+/// see [`ProgressBody`].
+pub async fn write_stalled(progress: &WriteProgress, timeout_ms: u64) -> NativeError {
+    let timeout = Duration::from_millis(timeout_ms);
+
+    loop {
+        let state = progress.state();
+        if state.done {
+            return std::future::pending().await;
+        }
+
+        match state.last_write {
+            Some(last_write) if last_write.elapsed() >= timeout => {
+                return NativeError::new(
+                    "write_timeout_error",
+                    "write timed out",
+                    json!({"timeout_ms": timeout_ms}),
+                );
+            }
+            Some(last_write) => tokio::time::sleep_until(last_write + timeout).await,
+            None => tokio::time::sleep(timeout).await,
+        }
+    }
+}
+
+/// Request body that is handed over in pieces, recording each one in a
+/// [`WriteProgress`]. The exact size hint keeps the `content-length` header.
+///
+/// Handing over the last piece does not finish the write: it still has to
+/// leave the connection's buffer. The body counts as written once it is
+/// polled past its end or dropped. HTTP/1 connections drop it after flushing
+/// its last bytes to the socket (see `vendor/wreq/PATCHES.md`); HTTP/2 ones
+/// once its data is queued on the stream, which flow control already paces.
+pub struct ProgressBody {
+    data: Bytes,
+    progress: Arc<WriteProgress>,
+}
+
+impl ProgressBody {
+    pub fn new(data: Vec<u8>, progress: Arc<WriteProgress>) -> Self {
+        Self {
+            data: Bytes::from(data),
+            progress,
+        }
+    }
+}
+
+impl Body for ProgressBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+        if self.data.is_empty() {
+            self.progress.update(|state| state.done = true);
+            return Poll::Ready(None);
+        }
+
+        let size = self.data.len().min(WRITE_CHUNK_SIZE);
+        let piece = self.data.split_to(size);
+        self.progress
+            .update(|state| state.last_write = Some(Instant::now()));
+
+        Poll::Ready(Some(Ok(Frame::data(piece))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.data.len() as u64)
+    }
+}
+
+impl Drop for ProgressBody {
+    fn drop(&mut self) {
+        self.progress.update(|state| state.done = true);
+    }
+}
+
+/// Tracks the response body download against `:low_speed`: fewer than
+/// `bytes_per_second` on average over a whole window of `seconds` aborts it.
+#[derive(Debug)]
+pub struct SpeedCheck {
+    limit: Option<NativeLowSpeed>,
+    window_start: Instant,
+    received: u64,
+}
+
+impl SpeedCheck {
+    pub fn new(limit: Option<&NativeLowSpeed>) -> Self {
+        Self {
+            limit: limit.cloned(),
+            window_start: Instant::now(),
+            received: 0,
+        }
+    }
+
+    /// When the current window ends, if a limit is set.
+    pub fn deadline(&self) -> Option<Instant> {
+        let limit = self.limit.as_ref()?;
+        Some(self.window_start + Duration::from_secs(limit.seconds))
+    }
+
+    /// Leaves `paused` out of the current window, for time spent not reading.
+    pub fn pause(&mut self, paused: Duration) {
+        self.window_start += paused;
+    }
+
+    pub fn record(&mut self, bytes: usize) {
+        self.received = self.received.saturating_add(bytes as u64);
+    }
+
+    /// Fails once a window has passed below the limit, otherwise starts a new
+    /// window when the current one is over.
+    pub fn check(&mut self) -> Result<(), NativeError> {
+        self.check_at(Instant::now())
+    }
+
+    fn check_at(&mut self, now: Instant) -> Result<(), NativeError> {
+        let (Some(limit), Some(deadline)) = (self.limit.as_ref(), self.deadline()) else {
+            return Ok(());
+        };
+        if now < deadline {
+            return Ok(());
+        }
+
+        let required = limit.bytes_per_second.saturating_mul(limit.seconds);
+        if self.received < required {
+            return Err(NativeError::new(
+                "low_speed_error",
+                "response body transfer was too slow",
+                json!({
+                    "bytes_per_second": limit.bytes_per_second,
+                    "seconds": limit.seconds,
+                    "received": self.received
+                }),
+            ));
+        }
+
+        self.window_start = now;
+        self.received = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .expect("runtime should build")
+    }
+
+    #[test]
+    fn total_deadline_maps_to_its_own_error() {
+        let error = runtime()
+            .block_on(with_total(Some(20), async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Ok::<_, NativeError>(())
+            }))
+            .expect_err("deadline should pass");
+
+        assert_eq!(error.type_name, "total_timeout_error");
+        assert_eq!(error.details["timeout_ms"], 20);
+    }
+
+    #[test]
+    fn progress_body_hands_over_pieces_with_exact_size() {
+        let progress = Arc::new(WriteProgress::default());
+        let mut body = ProgressBody::new(vec![0; WRITE_CHUNK_SIZE + 10], progress.clone());
+        assert_eq!(body.size_hint().exact(), Some(WRITE_CHUNK_SIZE as u64 + 10));
+
+        let mut cx = Context::from_waker(std::task::Waker::noop());
+        let mut sizes = Vec::new();
+        while let Poll::Ready(Some(Ok(frame))) = Pin::new(&mut body).poll_frame(&mut cx) {
+            sizes.push(frame.into_data().expect("data frame").len());
+        }
+
+        assert_eq!(sizes, vec![WRITE_CHUNK_SIZE, 10]);
+        assert!(progress.state().done);
+    }
+
+    #[test]
+    fn write_stays_armed_after_the_last_piece_until_the_body_is_released() {
+        let progress = Arc::new(WriteProgress::default());
+        let mut body = ProgressBody::new(vec![0; 10], progress.clone());
+        let mut cx = Context::from_waker(std::task::Waker::noop());
+
+        assert!(matches!(
+            Pin::new(&mut body).poll_frame(&mut cx),
+            Poll::Ready(Some(Ok(_)))
+        ));
+        assert!(body.is_end_stream());
+        assert!(!progress.state().done, "the piece may still be buffered");
+        let error = runtime().block_on(write_stalled(&progress, 20));
+        assert_eq!(error.type_name, "write_timeout_error");
+
+        drop(body);
+        assert!(progress.state().done);
+    }
+
+    #[test]
+    fn write_stall_fires_only_after_writing_started() {
+        let progress = WriteProgress::default();
+
+        runtime().block_on(async {
+            let waited =
+                tokio::time::timeout(Duration::from_millis(100), write_stalled(&progress, 20))
+                    .await;
+            assert!(waited.is_err(), "nothing was written yet");
+
+            progress.update(|state| state.last_write = Some(Instant::now()));
+            let error = write_stalled(&progress, 20).await;
+            assert_eq!(error.type_name, "write_timeout_error");
+        });
+    }
+
+    #[test]
+    fn speed_check_aborts_slow_windows() {
+        let limit = NativeLowSpeed {
+            bytes_per_second: 100,
+            seconds: 2,
+        };
+        let mut check = SpeedCheck::new(Some(&limit));
+        let window_end = check.deadline().expect("limit is set");
+
+        check.record(150);
+        assert!(check.check_at(window_end - Duration::from_secs(1)).is_ok());
+
+        check.record(100);
+        assert!(
+            check.check_at(window_end).is_ok(),
+            "250 bytes in 2s is enough"
+        );
+
+        check.record(150);
+        let error = check
+            .check_at(window_end + Duration::from_secs(2))
+            .expect_err("150 bytes in 2s is too slow");
+        assert_eq!(error.type_name, "low_speed_error");
+    }
+
+    #[test]
+    fn speed_check_without_limit_never_fails() {
+        let mut check = SpeedCheck::new(None);
+
+        assert!(check.deadline().is_none());
+        assert!(check.check().is_ok());
+    }
+
+    #[test]
+    fn paused_time_moves_the_window_end() {
+        let limit = NativeLowSpeed {
+            bytes_per_second: 100,
+            seconds: 2,
+        };
+        let mut check = SpeedCheck::new(Some(&limit));
+        let window_end = check.deadline().expect("limit is set");
+
+        check.pause(Duration::from_secs(5));
+
+        assert_eq!(check.deadline(), Some(window_end + Duration::from_secs(5)));
+    }
+}
@@ -0,0 +1,261 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use tower::{Layer, Service};
+
+use crate::response::NativeTiming;
+
+tokio::task_local! {
+    static CONNECT_TIMES: Arc<Mutex<ConnectTimes>>;
+}
+
+/// Connection setup milestones of one request hop.
+///
+/// The connector and resolver record them through a task-local, which only
+/// works because the pool drives a new connection on the requesting task. A
+/// hop that reuses a pooled connection records nothing.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ConnectTimes {
+    dns_start: Option<Instant>,
+    dns_end: Option<Instant>,
+    connect_start: Option<Instant>,
+    tcp_started: bool,
+    tcp_end: Option<Instant>,
+    connect_end: Option<Instant>,
+}
+
+impl ConnectTimes {
+    fn dns(&self) -> Option<Duration> {
+        Some(self.dns_end?.duration_since(self.dns_start?))
+    }
+
+    /// TCP connect time, counted from the end of DNS resolution. A connect that
+    /// finished within a single poll ends together with the whole connect.
+    fn tcp(&self) -> Option<Duration> {
+        let start = self.dns_end.or(self.connect_start)?;
+        let end = self.tcp_end.or(self.connect_end)?;
+        Some(end.saturating_duration_since(start))
+    }
+
+    fn tls(&self) -> Option<Duration> {
+        Some(self.connect_end?.saturating_duration_since(self.tcp_end?))
+    }
+
+    /// Whether the hop opened a new connection rather than reusing one.
+    pub fn connected(&self) -> bool {
+        self.connect_end.is_some()
+    }
+}
+
+/// Runs one request hop, returning its output with the connection setup it
+/// recorded.
+pub async fn record_hop<F: Future>(future: F) -> (F::Output, ConnectTimes) {
+    let times = Arc::new(Mutex::new(ConnectTimes::default()));
+    let output = CONNECT_TIMES.scope(times.clone(), future).await;
+    let times = *times.lock().unwrap_or_else(|e| e.into_inner());
+    (output, times)
+}
+
+/// Records a DNS lookup for the hop running on the current task, if any.
+pub fn record_dns(start: Instant, end: Instant) {
+    update(|times| {
+        times.dns_start.get_or_insert(start);
+        times.dns_end = Some(end);
+    });
+}
+
+fn update(f: impl FnOnce(&mut ConnectTimes)) {
+    let _ = CONNECT_TIMES.try_with(|times| f(&mut times.lock().unwrap_or_else(|e| e.into_inner())));
+}
+
+/// Builds the timing of the final hop, which started at `started` and got
+/// its response head at `head_received`. `https` decides whether the time
+/// after the TCP connect is reported as the TLS handshake.
+///
+/// Time to first byte is counted from the end of connection setup on a new
+/// connection, so it is the wait for the server rather than the whole hop.
+/// Body and total time are left for the caller, once the body is read.
+pub fn hop_timing(
+    times: &ConnectTimes,
+    https: bool,
+    started: Instant,
+    head_received: Instant,
+) -> NativeTiming {
+    let sent = times.connect_end.map_or(started, |end| end.max(started));
+    NativeTiming {
+        dns_us: times.dns().map(micros),
+        connect_us: times.tcp().map(micros),
+        tls_us: times.tls().filter(|_| https).map(micros),
+        ttfb_us: micros(head_received.saturating_duration_since(sent)),
+        body_us: None,
+        total_us: None,
+    }
+}
+
+pub fn micros(duration: Duration) -> u64 {
+    u64::try_from(duration.as_micros()).unwrap_or(u64::MAX)
+}
+
+/// Connector layer that timestamps the TCP connect and TLS handshake.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConnectTimingLayer;
+
+impl<S> Layer<S> for ConnectTimingLayer {
+    type Service = ConnectTiming<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ConnectTiming { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConnectTiming<S> {
+    inner: S,
+}
+
+impl<S, R> Service<R> for ConnectTiming<S>
+where
+    S: Service<R>,
+    S::Future: Unpin,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = TimedConnect<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        TimedConnect {
+            inner: self.inner.call(request),
+        }
+    }
+}
+
+/// Connect future that records milestones around each poll.
+///
+/// TCP connect starts once DNS is done (or immediately for IP literals) and is
+/// taken to end at the next wake-up, which is the socket becoming writable.
+/// Whatever follows until the connection is ready is the TLS handshake, plus
+/// tunnel setup when a proxy is used.
+pub struct TimedConnect<F> {
+    inner: F,
+}
+
+impl<F: Future + Unpin> Future for TimedConnect<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let now = Instant::now();
+        update(|times| {
+            times.connect_start.get_or_insert(now);
+            if times.tcp_started && times.tcp_end.is_none() {
+                times.tcp_end = Some(now);
+            }
+        });
+
+        let poll = Pin::new(&mut self.inner).poll(cx);
+
+        let now = Instant::now();
+        update(|times| {
+            if poll.is_ready() {
+                times.connect_end = Some(now);
+            }
+            if times.dns_start.is_none() || times.dns_end.is_some() {
+                times.tcp_started = true;
+            }
+        });
+
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(base: Instant, ms: u64) -> Option<Instant> {
+        Some(base + Duration::from_millis(ms))
+    }
+
+    #[test]
+    fn derives_phases_from_milestones() {
+        let base = Instant::now();
+        let times = ConnectTimes {
+            dns_start: at(base, 0),
+            dns_end: at(base, 5),
+            connect_start: at(base, 0),
+            tcp_started: true,
+            tcp_end: at(base, 15),
+            connect_end: at(base, 40),
+        };
+
+        let timing = hop_timing(&times, true, base, base + Duration::from_millis(60));
+
+        assert_eq!(timing.dns_us, Some(5_000));
+        assert_eq!(timing.connect_us, Some(10_000));
+        assert_eq!(timing.tls_us, Some(25_000));
+        assert_eq!(
+            timing.ttfb_us, 20_000,
+            "counted from the end of the handshake"
+        );
+        assert_eq!(timing.body_us, None);
+    }
+
+    #[test]
+    fn plain_http_reports_no_tls() {
+        let base = Instant::now();
+        let times = ConnectTimes {
+            connect_start: at(base, 0),
+            tcp_started: true,
+            connect_end: at(base, 3),
+            ..ConnectTimes::default()
+        };
+
+        let timing = hop_timing(&times, false, base, base + Duration::from_millis(10));
+
+        assert_eq!(timing.dns_us, None);
+        assert_eq!(timing.connect_us, Some(3_000));
+        assert_eq!(timing.tls_us, None);
+    }
+
+    #[test]
+    fn reused_connection_reports_no_setup() {
+        let base = Instant::now();
+        let timing = hop_timing(
+            &ConnectTimes::default(),
+            true,
+            base,
+            base + Duration::from_millis(10),
+        );
+
+        assert_eq!(timing.dns_us, None);
+        assert_eq!(timing.connect_us, None);
+        assert_eq!(timing.tls_us, None);
+        assert_eq!(timing.ttfb_us, 10_000);
+    }
+
+    #[test]
+    fn connect_future_records_milestones_inside_hop() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .expect("runtime should build");
+
+        let ((), times) = runtime.block_on(record_hop(async {
+            let connect = Box::pin(async {
+                tokio::task::yield_now().await;
+                tokio::task::yield_now().await;
+            });
+            TimedConnect { inner: connect }.await;
+        }));
+
+        assert!(times.connect_start.is_some());
+        assert!(times.tcp_end.is_some());
+        assert!(times.connect_end >= times.tcp_end);
+    }
+}
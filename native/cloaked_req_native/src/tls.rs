@@ -0,0 +1,417 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use boring2::base64;
+use boring2::pkey::PKey;
+use boring2::sha::{sha256, Sha256};
+use boring2::x509::X509;
+use serde_json::json;
+use tower::{BoxError, Layer, Service};
+use wreq::connect::{Conn, Unnameable};
+use wreq::tls::{CertStore, Identity, TlsInfo};
+
+use crate::error::NativeError;
+use crate::request::{NativeCaCertificates, NativeClientCertificate, NativePin};
+
+/// Builds the mutual TLS identity for `:client_certificate`.
+///
+/// PEM keys are accepted in any format BoringSSL reads (PKCS#8, PKCS#1 RSA or
+/// SEC1 EC) and re-encoded as PKCS#8, which is what wreq expects.
+pub fn client_identity(certificate: &NativeClientCertificate) -> Result<Identity, NativeError> {
+    let identity = match certificate {
+        NativeClientCertificate {
+            cert_pem: Some(cert),
+            key_pem: Some(key),
+            ..
+        } => {
+            let key = PKey::private_key_from_pem(key.as_bytes())
+                .and_then(|key| key.private_key_to_pem_pkcs8())
+                .map_err(|reason| invalid_client_certificate(reason.to_string()))?;
+            Identity::from_pkcs8_pem(cert.as_bytes(), &key)
+        }
+        NativeClientCertificate {
+            pkcs12: Some(bundle),
+            ..
+        } => Identity::from_pkcs12_der(&bundle.0, certificate.password.as_deref().unwrap_or("")),
+        _ => {
+            return Err(invalid_client_certificate(
+                "expected a PEM cert and key or a PKCS#12 bundle".to_string(),
+            ))
+        }
+    };
+
+    identity.map_err(|reason| invalid_client_certificate(reason.to_string()))
+}
+
+/// SHA-256 over the identity material, hex encoded. Used in the client cache
+/// key so distinct identities never share a client, without keeping key
+/// material in the key itself.
+pub fn client_certificate_fingerprint(certificate: &NativeClientCertificate) -> String {
+    fingerprint([
+        certificate.cert_pem.as_deref().map(str::as_bytes),
+        certificate.key_pem.as_deref().map(str::as_bytes),
+        certificate
+            .pkcs12
+            .as_ref()
+            .map(|bundle| bundle.0.as_slice()),
+        certificate.password.as_deref().map(str::as_bytes),
+    ])
+}
+
+/// Builds the trust store for `:ca_certificates`: the given PEM certificates
+/// on top of wreq's bundled Mozilla roots, or on their own with `only`.
+pub fn ca_cert_store(ca_certificates: &NativeCaCertificates) -> Result<CertStore, NativeError> {
+    let mut builder = CertStore::builder();
+    if !ca_certificates.only {
+        builder = builder.add_der_certs(webpki_root_certs::TLS_SERVER_ROOT_CERTS);
+    }
+    for pem in &ca_certificates.pems {
+        builder = builder.add_stack_pem_certs(pem);
+    }
+
+    builder.build().map_err(|reason| {
+        NativeError::new(
+            "invalid_request",
+            "invalid ca_certificates",
+            json!({"reason": reason.to_string()}),
+        )
+    })
+}
+
+/// Cache key fingerprint of a custom trust store, like
+/// [`client_certificate_fingerprint`].
+pub fn ca_certificates_fingerprint(ca_certificates: &NativeCaCertificates) -> String {
+    let mode: &[u8] = if ca_certificates.only {
+        b"only"
+    } else {
+        b"add"
+    };
+    fingerprint(
+        std::iter::once(Some(mode))
+            .chain(ca_certificates.pems.iter().map(|pem| Some(pem.as_bytes()))),
+    )
+}
+
+/// Enforces the public-key pins that match `host`.
+///
+/// Passes when no pin matches the host, or when any certificate in the peer
+/// chain has a pinned SPKI hash. Pins are checked whether or not chain
+/// verification is enabled.
+pub fn check_pins(
+    pins: &[NativePin],
+    host: &str,
+    tls_info: Option<&TlsInfo>,
+) -> Result<(), NativeError> {
+    let host = host.to_ascii_lowercase();
+    let allowed: Vec<&str> = pins
+        .iter()
+        .filter(|pin| host_matches(&pin.host, &host))
+        .flat_map(|pin| pin.sha256.iter().map(String::as_str))
+        .collect();
+
+    if allowed.is_empty() {
+        return Ok(());
+    }
+
+    let presented: Vec<String> = tls_info
+        .and_then(TlsInfo::peer_certificate_chain)
+        .map(|chain| chain.filter_map(spki_sha256).collect())
+        .unwrap_or_default();
+
+    if presented
+        .iter()
+        .any(|hash| allowed.contains(&hash.as_str()))
+    {
+        return Ok(());
+    }
+
+    Err(NativeError::new(
+        "certificate_pin_error",
+        "server public key does not match pinned keys",
+        json!({"host": host, "presented": presented}),
+    ))
+}
+
+/// Connector layer that checks the pins of every new connection right after
+/// the TLS handshake, so a mismatched server never receives the request.
+#[derive(Debug, Clone)]
+pub struct PinCheckLayer {
+    pins: Arc<[NativePin]>,
+}
+
+impl PinCheckLayer {
+    pub fn new(pins: &[NativePin]) -> Self {
+        Self { pins: pins.into() }
+    }
+}
+
+impl<S> Layer<S> for PinCheckLayer {
+    type Service = PinCheck<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        PinCheck {
+            inner,
+            pins: self.pins.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PinCheck<S> {
+    inner: S,
+    pins: Arc<[NativePin]>,
+}
+
+impl<S> Service<Unnameable> for PinCheck<S>
+where
+    S: Service<Unnameable, Response = Conn, Error = BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = Conn;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Conn, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Unnameable) -> Self::Future {
+        let pins = self.pins.clone();
+        let host = request.uri().host().unwrap_or_default().to_string();
+        let connect = self.inner.call(request);
+
+        Box::pin(async move {
+            let conn = connect.await?;
+            check_pins(&pins, &host, conn.tls_info().as_ref())?;
+            Ok(conn)
+        })
+    }
+}
+
+/// Matches `host` against an exact pattern or a `*.` subdomain wildcard.
+fn host_matches(pattern: &str, host: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    match pattern.strip_prefix("*.") {
+        Some(parent) => host
+            .strip_suffix(parent)
+            .is_some_and(|prefix| prefix.len() > 1 && prefix.ends_with('.')),
+        None => pattern == host,
+    }
+}
+
+/// Base64 SHA-256 of a DER certificate's SubjectPublicKeyInfo.
+fn spki_sha256(der: &[u8]) -> Option<String> {
+    let spki = X509::from_der(der)
+        .ok()?
+        .public_key()
+        .ok()?
+        .public_key_to_der()
+        .ok()?;
+    Some(base64::encode_block(&sha256(&spki)))
+}
+
+/// SHA-256 over `parts`, hex encoded. Every part is length-prefixed, so
+/// different splits of the same bytes, or a missing part, hash differently.
+fn fingerprint<'a>(parts: impl IntoIterator<Item = Option<&'a [u8]>>) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        match part {
+            Some(bytes) => {
+                hasher.update(&(bytes.len() as u64 + 1).to_be_bytes());
+                hasher.update(bytes);
+            }
+            None => hasher.update(&0_u64.to_be_bytes()),
+        }
+    }
+
+    hex(&hasher.finish())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn invalid_client_certificate(reason: String) -> NativeError {
+    NativeError::new(
+        "invalid_request",
+        "invalid client_certificate",
+        json!({"reason": reason}),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::DerBinary;
+    use boring2::asn1::Asn1Time;
+    use boring2::ec::{EcGroup, EcKey};
+    use boring2::hash::MessageDigest;
+    use boring2::nid::Nid;
+    use boring2::pkcs12::Pkcs12;
+    use boring2::pkey::Private;
+    use boring2::x509::{X509NameBuilder, X509};
+
+    fn self_signed() -> (PKey<Private>, X509) {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "client").unwrap();
+        let name = name.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+
+        (key, builder.build())
+    }
+
+    fn pem_certificate(key_pem: Vec<u8>, cert: &X509) -> NativeClientCertificate {
+        NativeClientCertificate {
+            cert_pem: Some(String::from_utf8(cert.to_pem().unwrap()).unwrap()),
+            key_pem: Some(String::from_utf8(key_pem).unwrap()),
+            pkcs12: None,
+            password: None,
+        }
+    }
+
+    #[test]
+    fn loads_pem_identity_with_sec1_key() {
+        let (key, cert) = self_signed();
+        let sec1 = key.ec_key().unwrap().private_key_to_pem().unwrap();
+
+        assert!(client_identity(&pem_certificate(sec1, &cert)).is_ok());
+    }
+
+    #[test]
+    fn loads_pkcs12_identity() {
+        let (key, cert) = self_signed();
+        let bundle = Pkcs12::builder()
+            .build("secret", "client", &key, &cert)
+            .unwrap()
+            .to_der()
+            .unwrap();
+        let certificate = NativeClientCertificate {
+            cert_pem: None,
+            key_pem: None,
+            pkcs12: Some(DerBinary(bundle)),
+            password: Some("secret".to_string()),
+        };
+
+        assert!(client_identity(&certificate).is_ok());
+
+        let wrong_password = NativeClientCertificate {
+            password: Some("wrong".to_string()),
+            ..certificate
+        };
+        let error = client_identity(&wrong_password).expect_err("password must be checked");
+        assert_eq!(error.message, "invalid client_certificate");
+    }
+
+    #[test]
+    fn rejects_certificate_without_key() {
+        let (_key, cert) = self_signed();
+        let certificate = NativeClientCertificate {
+            key_pem: None,
+            ..pem_certificate(Vec::new(), &cert)
+        };
+
+        let error = client_identity(&certificate).expect_err("key is required");
+        assert_eq!(error.type_name, "invalid_request");
+    }
+
+    #[test]
+    fn fingerprint_depends_on_all_parts() {
+        let (key, cert) = self_signed();
+        let certificate = pem_certificate(key.private_key_to_pem_pkcs8().unwrap(), &cert);
+        let fingerprint = client_certificate_fingerprint(&certificate);
+
+        assert_eq!(fingerprint.len(), 64);
+        assert_eq!(fingerprint, client_certificate_fingerprint(&certificate));
+
+        let (other_key, _cert) = self_signed();
+        let other = pem_certificate(other_key.private_key_to_pem_pkcs8().unwrap(), &cert);
+        assert_ne!(fingerprint, client_certificate_fingerprint(&other));
+    }
+
+    fn ca(pems: Vec<String>, only: bool) -> NativeCaCertificates {
+        NativeCaCertificates { pems, only }
+    }
+
+    #[test]
+    fn builds_ca_store_from_pem_certificates() {
+        let (_key, cert) = self_signed();
+        let pem = String::from_utf8(cert.to_pem().unwrap()).unwrap();
+
+        assert!(ca_cert_store(&ca(vec![pem.clone()], true)).is_ok());
+        assert!(ca_cert_store(&ca(vec![pem], false)).is_ok());
+    }
+
+    #[test]
+    fn rejects_malformed_ca_certificate() {
+        let pem = "-----BEGIN CERTIFICATE-----\nnot base64\n-----END CERTIFICATE-----\n";
+        let error = ca_cert_store(&ca(vec![pem.to_string()], true)).expect_err("must not parse");
+
+        assert_eq!(error.message, "invalid ca_certificates");
+    }
+
+    #[test]
+    fn ca_fingerprint_distinguishes_mode_and_certificates() {
+        let (_key, cert) = self_signed();
+        let pem = String::from_utf8(cert.to_pem().unwrap()).unwrap();
+
+        let added = ca_certificates_fingerprint(&ca(vec![pem.clone()], false));
+        let only = ca_certificates_fingerprint(&ca(vec![pem.clone()], true));
+        let twice = ca_certificates_fingerprint(&ca(vec![pem.clone(), pem], false));
+
+        assert_ne!(added, only);
+        assert_ne!(added, twice);
+    }
+
+    #[test]
+    fn matches_exact_and_wildcard_hosts() {
+        assert!(host_matches("api.example.com", "api.example.com"));
+        assert!(host_matches("API.example.com", "api.example.com"));
+        assert!(!host_matches("api.example.com", "example.com"));
+
+        assert!(host_matches("*.example.com", "api.example.com"));
+        assert!(host_matches("*.example.com", "a.b.example.com"));
+        assert!(!host_matches("*.example.com", "example.com"));
+        assert!(!host_matches("*.example.com", "badexample.com"));
+    }
+
+    #[test]
+    fn hashes_subject_public_key_info() {
+        let (key, cert) = self_signed();
+        let expected = base64::encode_block(&sha256(&key.public_key_to_der().unwrap()));
+
+        assert_eq!(spki_sha256(&cert.to_der().unwrap()), Some(expected));
+        assert_eq!(spki_sha256(b"not a certificate"), None);
+    }
+
+    #[test]
+    fn pins_only_apply_to_matching_hosts() {
+        let pins = vec![NativePin {
+            host: "*.pinned.test".to_string(),
+            sha256: vec!["AAAA".to_string()],
+        }];
+
+        assert!(check_pins(&pins, "other.test", None).is_ok());
+
+        let error =
+            check_pins(&pins, "API.pinned.test", None).expect_err("no certificate to match");
+        assert_eq!(error.type_name, "certificate_pin_error");
+        assert_eq!(error.details["host"], "api.pinned.test");
+    }
+}
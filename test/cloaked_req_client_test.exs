@@ -0,0 +1,93 @@
+defmodule CloakedReq.ClientTest do
+  @moduledoc """
+  Verifies client handle creation, validation and use by requests through the
+  full Elixir -> NIF -> Rust wreq pipeline.
+  """
+
+  use ExUnit.Case, async: true
+
+  alias CloakedReq.AdapterError
+  alias CloakedReq.Client
+  alias CloakedReq.Error
+  alias CloakedReq.TestServer
+
+  doctest Client, import: false
+
+  # -------------------------------------------------------------------
+  # Lifecycle
+  # -------------------------------------------------------------------
+
+  test "new/1 returns a Client struct with an opaque ref and its options" do
+    assert {:ok, %Client{} = client} = Client.new(impersonate: :chrome_136, max_connections_per_host: 6)
+    assert is_reference(client.ref)
+    assert client.options == [impersonate: :chrome_136, max_connections_per_host: 6]
+  end
+
+  test "two clients with the same options have different references" do
+    assert {:ok, client1} = Client.new()
+    assert {:ok, client2} = Client.new()
+    refute client1.ref == client2.ref
+  end
+
+  test "new/1 rejects options that do not shape a client" do
+    assert {:error, %Error{type: :invalid_request, message: "not client options", details: details}} =
+             Client.new(impersonate: :chrome_136, nonblocking: true, unknown: 1)
+
+    assert details == %{options: [:nonblocking, :unknown]}
+  end
+
+  test "new/1 validates option values" do
+    assert {:error, %Error{type: :invalid_request, message: "impersonate must be a profile atom"}} =
+             Client.new(impersonate: "chrome_136")
+
+    assert {:error, %Error{type: :invalid_request, message: "unknown emulation profile"}} =
+             Client.new(impersonate: :netscape_4)
+  end
+
+  test "non-Client client value returns error" do
+    request = [url: "https://example.com"] |> Req.new() |> CloakedReq.attach(client: %{ref: make_ref()})
+
+    assert {^request, %AdapterError{} = exception} = CloakedReq.run(request)
+    assert exception.message == "invalid_request: client must be a %CloakedReq.Client{}"
+  end
+
+  # -------------------------------------------------------------------
+  # Requests (e2e)
+  # -------------------------------------------------------------------
+
+  test "requests use the client and its options" do
+    response = TestServer.build_response(200, [{"content-type", "text/plain"}], "from client")
+    {url, server} = TestServer.start(response: response)
+    %URI{port: port} = URI.parse(url)
+    {:ok, client} = Client.new(resolve: %{"tenant.cloaked-req.example:#{port}" => "127.0.0.1"})
+
+    req =
+      [url: "http://tenant.cloaked-req.example:#{port}/", retry: false]
+      |> Req.new()
+      |> CloakedReq.attach(client: client)
+
+    assert {:ok, %Req.Response{status: 200, body: "from client"}} = Req.request(req)
+    assert TestServer.get_request(server) =~ ~r/host: tenant\.cloaked-req\.example:#{port}/i
+  end
+
+  test "client options override the request's" do
+    {:ok, client} = Client.new(ip_family: :ipv6)
+    req = [url: "http://127.0.0.1:9/", retry: false] |> Req.new() |> CloakedReq.attach(client: client, ip_family: :any)
+
+    assert {:error, %AdapterError{} = error} = Req.request(req)
+    assert error.error.message == "url host is outside the requested IP family"
+  end
+
+  test "non-blocking and streamed requests use the client" do
+    {:ok, client} = Client.new(pool_max_idle_per_host: 0)
+
+    for options <- [[nonblocking: true], [into: []]] do
+      response = TestServer.build_response(200, [{"content-type", "text/plain"}], "ok")
+      {url, _server} = TestServer.start(response: response)
+
+      req = [url: url, retry: false] |> Req.new(options) |> CloakedReq.attach(client: client)
+
+      assert {:ok, %Req.Response{status: 200}} = Req.request(req)
+    end
+  end
+end
@@ -7,6 +7,7 @@ defmodule CloakedReq.CookieJarTest do
   use ExUnit.Case, async: true
 
   alias CloakedReq.CookieJar
+  alias CloakedReq.Error
   alias CloakedReq.TestServer
 
   doctest CookieJar, import: false
@@ -203,4 +204,270 @@ defmodule CloakedReq.CookieJarTest do
     raw = TestServer.get_request(verify_server)
     assert raw =~ "redirect_token=abc"
   end
+
+  # -------------------------------------------------------------------
+  # Inspection and manipulation
+  # -------------------------------------------------------------------
+
+  test "cookies/1 and cookies_for/2 list cookies set by the server" do
+    jar = CookieJar.new()
+
+    set_response =
+      TestServer.build_response(
+        200,
+        [{"set-cookie", "sid=abc; Path=/; HttpOnly"}, {"set-cookie", "cart=1; Path=/shop; Max-Age=60"}],
+        "ok"
+      )
+
+    {set_url, _set_server} = TestServer.start(response: set_response)
+    req = [url: set_url, retry: false] |> Req.new() |> CloakedReq.attach(cookie_jar: jar)
+    assert {:ok, _} = Req.request(req)
+
+    assert [%{name: "sid", path: "/", expires: nil, http_only: true}, %{name: "cart", expires: %DateTime{}} = cart] =
+             CookieJar.cookies(jar)
+
+    assert DateTime.diff(cart.expires, DateTime.utc_now()) in 55..60
+    assert %{domain: "127.0.0.1", host_only: true, secure: false, same_site: nil} = cart
+
+    assert {:ok, [%{name: "cart"}, %{name: "sid"}]} = CookieJar.cookies_for(jar, set_url <> "shop/basket")
+    assert {:ok, [%{name: "sid"}]} = CookieJar.cookies_for(jar, URI.parse(set_url))
+    assert {:ok, []} = CookieJar.cookies_for(jar, "http://localhost/")
+  end
+
+  test "put/3 stores cookies that are sent with later requests" do
+    jar = CookieJar.new()
+    verify_response = TestServer.build_response(200, [], "ok")
+    {verify_url, verify_server} = TestServer.start(response: verify_response)
+
+    assert :ok = CookieJar.put(jar, verify_url, "token=manual; Path=/")
+
+    req = [url: verify_url, retry: false] |> Req.new() |> CloakedReq.attach(cookie_jar: jar)
+    assert {:ok, _} = Req.request(req)
+
+    assert TestServer.get_request(verify_server) =~ ~r/cookie: token=manual/i
+  end
+
+  test "put/3 applies the set-cookie domain checks" do
+    jar = CookieJar.new()
+
+    for set_cookie <- ["evil=1; Domain=com", "evil=1; Domain=other.example"] do
+      assert {:error, %Error{type: :invalid_request, message: message}} =
+               CookieJar.put(jar, "https://www.example.com/", set_cookie)
+
+      assert message == "cookie domain is a public suffix or does not match the url host"
+    end
+
+    assert {:error, %Error{message: "url must be an http or https URL"}} =
+             CookieJar.put(jar, "ftp://example.com/", "a=1")
+
+    assert {:error, %Error{message: "invalid set-cookie value"}} = CookieJar.put(jar, "https://example.com/", "a")
+    assert {:error, %Error{message: "url must be a string or URI"}} = CookieJar.cookies_for(jar, :example)
+    assert CookieJar.cookies(jar) == []
+  end
+
+  test "delete/4 and clear/1 remove cookies" do
+    jar = CookieJar.new()
+    :ok = CookieJar.put(jar, "https://example.com/", "a=1; Domain=example.com")
+    :ok = CookieJar.put(jar, "https://example.com/", "a=2; Path=/app")
+    :ok = CookieJar.put(jar, "https://example.com/", "b=3")
+
+    assert CookieJar.delete(jar, "a", ".example.com")
+    refute CookieJar.delete(jar, "a", ".example.com")
+    refute CookieJar.delete(jar, "a", "example.com", "/missing")
+    assert [%{name: "b"}, %{name: "a", path: "/app"}] = CookieJar.cookies(jar)
+
+    assert :ok = CookieJar.clear(jar)
+    assert CookieJar.cookies(jar) == []
+  end
+
+  # -------------------------------------------------------------------
+  # Export and import
+  # -------------------------------------------------------------------
+
+  test "export/2 writes every cookie attribute as JSON" do
+    jar = CookieJar.new()
+
+    set_response =
+      TestServer.build_response(
+        200,
+        [
+          {"set-cookie", "session=abc; Path=/app; HttpOnly; SameSite=Lax"},
+          {"set-cookie", "theme=dark; Domain=127.0.0.1; Path=/; Secure; Max-Age=3600"}
+        ],
+        "ok"
+      )
+
+    {set_url, _set_server} = TestServer.start(response: set_response)
+    req = [url: set_url <> "app/login", retry: false] |> Req.new() |> CloakedReq.attach(cookie_jar: jar)
+    assert {:ok, _} = Req.request(req)
+
+    assert %{"version" => 1, "cookies" => [theme, session]} = jar |> CookieJar.export() |> Jason.decode!()
+
+    assert session == %{
+             "name" => "session",
+             "value" => "abc",
+             "domain" => "127.0.0.1",
+             "host_only" => true,
+             "path" => "/app",
+             "expires" => nil,
+             "secure" => false,
+             "http_only" => true,
+             "same_site" => "lax"
+           }
+
+    assert %{"host_only" => false, "path" => "/", "secure" => true, "same_site" => nil} = theme
+    assert is_integer(theme["expires"])
+  end
+
+  test "exported jars round-trip through both formats" do
+    jar = CookieJar.new()
+    set_response = TestServer.build_response(200, [{"set-cookie", "sid=xyz; Path=/; HttpOnly"}], "ok")
+    {set_url, _set_server} = TestServer.start(response: set_response)
+    req = [url: set_url, retry: false] |> Req.new() |> CloakedReq.attach(cookie_jar: jar)
+    assert {:ok, _} = Req.request(req)
+
+    for format <- [:json, :netscape] do
+      exported = CookieJar.export(jar, format)
+      assert {:ok, %CookieJar{} = imported} = CookieJar.import(exported, format)
+      refute imported.ref == jar.ref
+      assert CookieJar.export(imported, format) == exported
+
+      verify_response = TestServer.build_response(200, [], "ok")
+      {verify_url, verify_server} = TestServer.start(response: verify_response)
+      req = [url: verify_url, retry: false] |> Req.new() |> CloakedReq.attach(cookie_jar: imported)
+      assert {:ok, _} = Req.request(req)
+
+      assert TestServer.get_request(verify_server) =~ ~r/cookie: sid=xyz/i
+    end
+  end
+
+  test "export/2 writes Netscape cookies.txt lines" do
+    {:ok, jar} =
+      CookieJar.import("""
+      # Netscape HTTP Cookie File
+      .example.com	TRUE	/	TRUE	4102444800	prefs	compact
+      #HttpOnly_example.com	FALSE	/account	FALSE	0	session	abc
+      """, :netscape)
+
+    assert CookieJar.export(jar, :netscape) == """
+           # Netscape HTTP Cookie File
+           .example.com	TRUE	/	TRUE	4102444800	prefs	compact
+           #HttpOnly_example.com	FALSE	/account	FALSE	0	session	abc
+           """
+  end
+
+  test "import/2 rejects malformed data" do
+    assert {:error, %Error{type: :invalid_request, message: "invalid cookie JSON"}} = CookieJar.import("[]")
+
+    assert {:error, %Error{message: "unsupported cookie JSON version", details: %{"version" => 2}}} =
+             CookieJar.import(~s({"version":2,"cookies":[]}))
+
+    assert {:error, %Error{message: "invalid cookies.txt line", details: %{"line" => 2}}} =
+             CookieJar.import("# Netscape HTTP Cookie File
+example.com	MAYBE	/	FALSE	0	a	b
+", :netscape)
+
+    assert {:error, %Error{message: "cookie domain is a public suffix"}} =
+             CookieJar.import(".com	TRUE	/	FALSE	0	evil	1
+", :netscape)
+
+    assert {:error, %Error{message: "format must be :json or :netscape"}} = CookieJar.import("", :yaml)
+    assert {:error, %Error{message: "cookie data must be valid UTF-8"}} = CookieJar.import(<<0xFF>>)
+  end
+
+  # -------------------------------------------------------------------
+  # File-backed jars
+  # -------------------------------------------------------------------
+
+  @tag :tmp_dir
+  test "open/2 saves cookies set by responses and loads them again", %{tmp_dir: tmp_dir} do
+    path = Path.join(tmp_dir, "cookies.json")
+    assert {:ok, %CookieJar{path: ^path} = jar} = CookieJar.open(path)
+    refute File.exists?(path)
+
+    set_response = TestServer.build_response(200, [{"set-cookie", "session=abc123; Path=/"}], "ok")
+    {set_url, _set_server} = TestServer.start(response: set_response)
+    req = [url: set_url, retry: false] |> Req.new() |> CloakedReq.attach(cookie_jar: jar)
+    assert {:ok, _} = Req.request(req)
+
+    export = CookieJar.export(jar)
+    assert eventually(fn -> File.read(path) == {:ok, export} end)
+    assert File.ls!(tmp_dir) == ["cookies.json"]
+    assert %File.Stat{mode: mode} = File.stat!(path)
+    assert Bitwise.band(mode, 0o077) == 0
+
+    assert {:ok, reopened} = CookieJar.open(path)
+    verify_response = TestServer.build_response(200, [], "ok")
+    {verify_url, verify_server} = TestServer.start(response: verify_response)
+    req = [url: verify_url, retry: false] |> Req.new() |> CloakedReq.attach(cookie_jar: reopened)
+    assert {:ok, _} = Req.request(req)
+
+    assert TestServer.get_request(verify_server) =~ ~r/cookie: session=abc123/i
+  end
+
+  @tag :tmp_dir
+  test "file-backed jars save manual changes in their format", %{tmp_dir: tmp_dir} do
+    path = Path.join(tmp_dir, "cookies.txt")
+    assert {:ok, jar} = CookieJar.open(path, format: :netscape)
+
+    :ok = CookieJar.put(jar, "https://example.com/", "a=1; Path=/")
+    :ok = CookieJar.put(jar, "https://example.com/", "b=2; Path=/")
+    assert eventually(fn -> contents(path) =~ "example.com\tFALSE\t/\tFALSE\t0\tb\t2" end)
+
+    true = CookieJar.delete(jar, "b", "example.com")
+    assert eventually(fn -> not (contents(path) =~ "\tb\t2") end)
+
+    :ok = CookieJar.clear(jar)
+    assert :ok = CookieJar.save(jar)
+    assert File.read!(path) == "# Netscape HTTP Cookie File\n"
+    assert CookieJar.last_save_error(jar) == nil
+  end
+
+  @tag :tmp_dir
+  test "open/2 and save/1 report errors", %{tmp_dir: tmp_dir} do
+    path = Path.join(tmp_dir, "cookies.json")
+    File.write!(path, "not json")
+
+    assert {:error, %Error{type: :invalid_request, message: "invalid cookie JSON"}} = CookieJar.open(path)
+    assert {:error, %Error{message: "cookie file could not be read"}} = CookieJar.open(tmp_dir)
+    assert {:error, %Error{message: "format must be :json or :netscape"}} = CookieJar.open(path, format: :yaml)
+
+    assert {:error, %Error{message: "unknown cookie jar options", details: %{options: [:sync]}}} =
+             CookieJar.open(path, sync: true)
+
+    assert {:error, %Error{message: "cookie jar is not bound to a file"}} = CookieJar.save(CookieJar.new())
+
+    assert CookieJar.last_save_error(CookieJar.new()) == nil
+
+    assert {:ok, jar} = CookieJar.open(Path.join([tmp_dir, "missing", "cookies.json"]))
+    :ok = CookieJar.put(jar, "https://example.com/", "a=1")
+    assert eventually(fn -> match?(%Error{type: :runtime_error}, CookieJar.last_save_error(jar)) end)
+    assert {:error, %Error{type: :runtime_error, message: "cookie file could not be written"}} = CookieJar.save(jar)
+
+    File.mkdir!(Path.join(tmp_dir, "missing"))
+    assert :ok = CookieJar.save(jar)
+    assert CookieJar.last_save_error(jar) == nil
+  end
+
+  # File-backed jars are saved on a background thread.
+  defp eventually(fun, attempts \\ 50) do
+    cond do
+      fun.() ->
+        true
+
+      attempts > 0 ->
+        Process.sleep(20)
+        eventually(fun, attempts - 1)
+
+      true ->
+        false
+    end
+  end
+
+  defp contents(path) do
+    case File.read(path) do
+      {:ok, contents} -> contents
+      {:error, _reason} -> ""
+    end
+  end
 end
@@ -6,6 +6,9 @@ defmodule CloakedReq.E2ETest do
   use ExUnit.Case, async: true
 
   alias CloakedReq.AdapterError
+  alias CloakedReq.Error
+  alias CloakedReq.Native
+  alias CloakedReq.Request
   alias CloakedReq.TestServer
 
   test "GET returns 200 with body and response headers" do
@@ -117,14 +120,170 @@ defmodule CloakedReq.E2ETest do
     assert error.error.message == "response body exceeds max_body_size"
   end
 
-  test "server delay past receive_timeout returns transport error" do
+  test "server delay past receive_timeout returns read timeout error" do
     response = TestServer.build_response(200, [{"content-type", "text/plain"}], "late")
     {url, _server} = TestServer.start(response: response, delay_ms: 500)
 
     req = [url: url, receive_timeout: 100, retry: false] |> Req.new() |> CloakedReq.attach()
 
     assert {:error, %AdapterError{} = error} = Req.request(req)
-    assert error.error.type == :transport_error
+    assert error.error.type == :read_timeout_error
+    assert error.error.details["timeout_ms"] == 100
+  end
+
+  test "server delay past total_timeout returns total timeout error" do
+    response = TestServer.build_response(200, [{"content-type", "text/plain"}], "late")
+    {url, _server} = TestServer.start(response: response, delay_ms: 500)
+
+    req = [url: url, retry: false] |> Req.new() |> CloakedReq.attach(total_timeout: 100)
+
+    assert {:error, %AdapterError{} = error} = Req.request(req)
+    assert error.error.type == :total_timeout_error
+  end
+
+  test "nonblocking request returns the response by message" do
+    response = TestServer.build_response(200, [{"content-type", "text/plain"}], "hello")
+    {url, _server} = TestServer.start(response: response)
+
+    req = [url: url, retry: false] |> Req.new() |> CloakedReq.attach(nonblocking: true)
+
+    assert {:ok, %Req.Response{} = resp} = Req.request(req)
+    assert resp.status == 200
+    assert resp.body == "hello"
+  end
+
+  test "nonblocking requests run concurrently" do
+    response = TestServer.build_response(200, [{"content-type", "text/plain"}], "slow")
+
+    urls =
+      for _ <- 1..20 do
+        {url, _server} = TestServer.start(response: response, delay_ms: 300)
+        url
+      end
+
+    {elapsed_us, results} =
+      :timer.tc(fn ->
+        urls
+        |> Task.async_stream(
+          fn url -> [url: url, retry: false] |> Req.new() |> CloakedReq.attach(nonblocking: true) |> Req.request() end,
+          max_concurrency: 20
+        )
+        |> Enum.map(fn {:ok, result} -> result end)
+      end)
+
+    assert Enum.all?(results, &match?({:ok, %Req.Response{status: 200, body: "slow"}}, &1))
+    assert elapsed_us < 3_000_000
+  end
+
+  test "nonblocking request past receive_timeout returns read timeout error" do
+    response = TestServer.build_response(200, [{"content-type", "text/plain"}], "late")
+    {url, _server} = TestServer.start(response: response, delay_ms: 500)
+
+    req = [url: url, receive_timeout: 100, retry: false] |> Req.new() |> CloakedReq.attach(nonblocking: true)
+
+    assert {:error, %AdapterError{} = error} = Req.request(req)
+    assert error.error.type == :read_timeout_error
+  end
+
+  test "cancel_request aborts a nonblocking request and closes its connection" do
+    {url, server} = TestServer.start_hanging()
+    {:ok, {payload, body}} = [url: url] |> Req.new() |> CloakedReq.attach() |> Request.to_native_payload()
+
+    assert {:ok, handle} = Native.start_request(payload, body, nil)
+    _ = TestServer.get_request(server)
+
+    assert :ok = Native.cancel_request(handle)
+    assert :ok = TestServer.await_closed(server)
+    refute_received {^handle, _}
+  end
+
+  test "cancel_request leaves no reply behind for requests that already completed" do
+    response = TestServer.build_response(200, [{"content-type", "text/plain"}], "done")
+    {url, server} = TestServer.start(response: response)
+    {:ok, {payload, body}} = [url: url] |> Req.new() |> CloakedReq.attach() |> Request.to_native_payload()
+
+    assert {:ok, handle} = Native.start_request(payload, body, nil)
+    _ = TestServer.get_request(server)
+    Process.sleep(100)
+
+    assert :ok = Native.cancel_request(handle)
+    refute_receive {^handle, _}, 100
+
+    # Cancelling right as replies are sent must not leave one behind either.
+    handles =
+      for _ <- 1..50 do
+        {:ok, handle} = Native.start_request(payload, body, nil)
+        Process.sleep(:rand.uniform(3) - 1)
+        :ok = Native.cancel_request(handle)
+        handle
+      end
+
+    Process.sleep(200)
+
+    for handle <- handles do
+      refute_received {^handle, _}
+    end
+  end
+
+  test "nonblocking request is cancelled when the owning process exits" do
+    {url, server} = TestServer.start_hanging()
+    {:ok, {payload, body}} = [url: url] |> Req.new() |> CloakedReq.attach() |> Request.to_native_payload()
+
+    owner =
+      spawn(fn ->
+        {:ok, _handle} = Native.start_request(payload, body, nil)
+        Process.sleep(:infinity)
+      end)
+
+    _ = TestServer.get_request(server)
+    Process.exit(owner, :kill)
+
+    assert :ok = TestServer.await_closed(server)
+  end
+
+  test "request with default options is cancelled when the owning process exits" do
+    {url, server} = TestServer.start_hanging()
+    req = [url: url, retry: false] |> Req.new() |> CloakedReq.attach()
+
+    owner = spawn(fn -> Req.request(req) end)
+
+    _ = TestServer.get_request(server)
+    Process.exit(owner, :kill)
+
+    assert :ok = TestServer.await_closed(server)
+  end
+
+  test "streamed request is cancelled when the owning process exits" do
+    {url, server} = TestServer.start_hanging()
+    {:ok, {payload, body}} = [url: url] |> Req.new() |> CloakedReq.attach() |> Request.to_native_payload()
+
+    owner =
+      spawn(fn ->
+        {:ok, _handle} = Native.start_stream(payload, body, nil)
+        Process.sleep(:infinity)
+      end)
+
+    _ = TestServer.get_request(server)
+    Process.exit(owner, :kill)
+
+    assert :ok = TestServer.await_closed(server)
+  end
+
+  test "streamed body is read only as chunks are demanded" do
+    response = TestServer.build_response(200, [{"content-type", "text/plain"}], "streamed body")
+    {url, _server} = TestServer.start(response: response)
+    {:ok, {payload, body}} = [url: url] |> Req.new() |> CloakedReq.attach() |> Request.to_native_payload()
+
+    {:ok, handle} = Native.start_stream(payload, body, nil)
+    assert_receive {^handle, {:response, _meta}}, 5_000
+    refute_receive {^handle, _message}, 200
+
+    :ok = Native.demand_chunk(handle)
+    assert_receive {^handle, {:data, "streamed body"}}, 5_000
+    refute_receive {^handle, _message}, 200
+
+    :ok = Native.demand_chunk(handle)
+    assert_receive {^handle, :done}, 5_000
   end
 
   test "binary non-UTF8 body is preserved through round-trip" do
@@ -139,6 +298,58 @@ defmodule CloakedReq.E2ETest do
     assert resp.body == binary_body
   end
 
+  test "into: fun receives body chunks" do
+    body = String.duplicate("chunk", 100)
+    response = TestServer.build_response(200, [{"content-type", "text/plain"}], body)
+    {url, _server} = TestServer.start(response: response)
+
+    into = fn {:data, data}, {req, resp} ->
+      {:cont, {req, Req.Response.update_private(resp, :chunks, [data], &[data | &1])}}
+    end
+
+    req = [url: url, retry: false, into: into] |> Req.new() |> CloakedReq.attach()
+
+    assert {:ok, %Req.Response{} = resp} = Req.request(req)
+    assert resp.status == 200
+    assert resp.body == ""
+    assert resp.private.chunks |> Enum.reverse() |> IO.iodata_to_binary() == body
+  end
+
+  test "into: collectable collects the body" do
+    response = TestServer.build_response(200, [{"content-type", "application/octet-stream"}], "collected")
+    {url, _server} = TestServer.start(response: response)
+
+    req = [url: url, retry: false, into: []] |> Req.new() |> CloakedReq.attach()
+
+    assert {:ok, %Req.Response{} = resp} = Req.request(req)
+    assert IO.iodata_to_binary(resp.body) == "collected"
+    assert %{ttfb_us: ttfb_us} = timing = resp.private[:cloaked_req_timing]
+    assert is_integer(ttfb_us)
+    refute Map.has_key?(timing, :body_us) or Map.has_key?(timing, :total_us)
+  end
+
+  test "into: :self streams the body to the caller mailbox" do
+    response = TestServer.build_response(200, [{"content-type", "text/plain"}], "async body")
+    {url, _server} = TestServer.start(response: response)
+
+    req = [url: url, retry: false, into: :self] |> Req.new() |> CloakedReq.attach()
+
+    assert {:ok, %Req.Response{status: 200} = resp} = Req.request(req)
+    assert %Req.Response.Async{} = resp.body
+    assert Enum.join(resp.body) == "async body"
+  end
+
+  test "streamed response exceeding max_body_size returns adapter error" do
+    response = TestServer.build_response(200, [{"content-type", "text/plain"}], String.duplicate("x", 600))
+    {url, _server} = TestServer.start(response: response)
+
+    req = [url: url, retry: false, into: []] |> Req.new() |> CloakedReq.attach(max_body_size: 500)
+
+    assert {:error, %AdapterError{} = error} = Req.request(req)
+    assert error.error.type == :invalid_request
+    assert error.error.message == "response body exceeds max_body_size"
+  end
+
   test "redirect from server A to server B returns final response" do
     # Server B (destination) returns 200
     final_response = TestServer.build_response(200, [{"content-type", "text/plain"}], "arrived")
@@ -155,6 +366,94 @@ defmodule CloakedReq.E2ETest do
     assert resp.body == "arrived"
   end
 
+  test "cross-origin redirect drops the authorization header" do
+    final_response = TestServer.build_response(200, [{"content-type", "text/plain"}], "arrived")
+    {dest_url, dest_server} = TestServer.start(response: final_response)
+
+    redirect_response = TestServer.build_response(302, [{"location", dest_url}], "")
+    {origin_url, origin_server} = TestServer.start(response: redirect_response)
+
+    req =
+      [url: origin_url, headers: [{"authorization", "Bearer secret"}], retry: false]
+      |> Req.new()
+      |> CloakedReq.attach()
+
+    assert {:ok, %Req.Response{status: 200}} = Req.request(req)
+    assert TestServer.get_request(origin_server) =~ ~r/authorization: Bearer secret/i
+    refute TestServer.get_request(dest_server) =~ ~r/authorization/i
+  end
+
+  test "redirect history is exposed in response private" do
+    final_response = TestServer.build_response(200, [{"content-type", "text/plain"}], "arrived")
+    {dest_url, _dest_server} = TestServer.start(response: final_response)
+
+    redirect_response =
+      TestServer.build_response(302, [{"location", dest_url}, {"set-cookie", "session=1; Path=/"}], "")
+
+    {origin_url, _origin_server} = TestServer.start(response: redirect_response)
+
+    req =
+      [url: origin_url, retry: false]
+      |> Req.new()
+      |> CloakedReq.attach(cookie_jar: CloakedReq.CookieJar.new())
+
+    assert {:ok, %Req.Response{status: 200} = resp} = Req.request(req)
+    assert resp.private[:cloaked_req_url] == dest_url
+
+    assert [hop] = resp.private[:cloaked_req_redirects]
+    assert hop.url == origin_url
+    assert hop.status == 302
+    assert hop.headers["location"] == [dest_url]
+    assert hop.cookies_accepted == ["session=1; Path=/"]
+    assert hop.cookies_rejected == []
+  end
+
+  test "redirect_trusted keeps the authorization header across origins" do
+    final_response = TestServer.build_response(200, [{"content-type", "text/plain"}], "arrived")
+    {dest_url, dest_server} = TestServer.start(response: final_response)
+
+    redirect_response = TestServer.build_response(302, [{"location", dest_url}], "")
+    {origin_url, _origin_server} = TestServer.start(response: redirect_response)
+
+    req =
+      [url: origin_url, headers: [{"authorization", "Bearer secret"}], redirect_trusted: true, retry: false]
+      |> Req.new()
+      |> CloakedReq.attach()
+
+    assert {:ok, %Req.Response{status: 200}} = Req.request(req)
+    assert TestServer.get_request(dest_server) =~ ~r/authorization: Bearer secret/i
+  end
+
+  test "redirect: false returns the redirect response" do
+    redirect_response = TestServer.build_response(302, [{"location", "http://127.0.0.1:1/"}], "")
+    {origin_url, _origin_server} = TestServer.start(response: redirect_response)
+
+    req = [url: origin_url, redirect: false, retry: false] |> Req.new() |> CloakedReq.attach()
+
+    assert {:ok, %Req.Response{status: 302} = resp} = Req.request(req)
+    assert resp.headers["location"] == ["http://127.0.0.1:1/"]
+  end
+
+  test "redirect: :same_origin stops at a cross-origin redirect" do
+    redirect_response = TestServer.build_response(302, [{"location", "http://127.0.0.1:1/"}], "")
+    {origin_url, _origin_server} = TestServer.start(response: redirect_response)
+
+    req = [url: origin_url, redirect: :same_origin, retry: false] |> Req.new() |> CloakedReq.attach()
+
+    assert {:ok, %Req.Response{status: 302}} = Req.request(req)
+  end
+
+  test "exceeding max_redirects returns a redirect error" do
+    redirect_response = TestServer.build_response(302, [{"location", "/again"}], "")
+    {origin_url, _origin_server} = TestServer.start(response: redirect_response)
+
+    req = [url: origin_url, max_redirects: 0, retry: false] |> Req.new() |> CloakedReq.attach()
+
+    assert {:error, %AdapterError{} = error} = Req.request(req)
+    assert error.error.type == :redirect_error
+    assert error.error.message == "too many redirects"
+  end
+
   test "local_address option binds to specified source IP" do
     response = TestServer.build_response(200, [{"content-type", "text/plain"}], "bound")
     {url, server} = TestServer.start(response: response)
@@ -171,6 +470,131 @@ defmodule CloakedReq.E2ETest do
     assert peer_ip == {127, 0, 0, 1}
   end
 
+  @tag skip: if(match?({:unix, :linux}, :os.type()), do: false, else: "interface binding is tested on Linux only")
+  test "interface option binds connections to the loopback device" do
+    response = TestServer.build_response(200, [{"content-type", "text/plain"}], "bound")
+    {url, _server} = TestServer.start(response: response)
+
+    req = [url: url, retry: false] |> Req.new() |> CloakedReq.attach(interface: "lo")
+
+    assert {:ok, %Req.Response{status: 200, body: "bound"}} = Req.request(req)
+  end
+
+  test "resolve option sends a public hostname to the local server" do
+    response = TestServer.build_response(200, [{"content-type", "text/plain"}], "overridden")
+    {url, server} = TestServer.start(response: response)
+    %URI{port: port} = URI.parse(url)
+
+    req =
+      [url: "http://api.cloaked-req.example:#{port}/", retry: false]
+      |> Req.new()
+      |> CloakedReq.attach(resolve: %{"api.cloaked-req.example:#{port}" => "127.0.0.1"})
+
+    assert {:ok, %Req.Response{status: 200, body: "overridden"} = resp} = Req.request(req)
+    assert resp.private[:cloaked_req_url] == "http://api.cloaked-req.example:#{port}/"
+    assert TestServer.get_request(server) =~ ~r/host: api\.cloaked-req\.example:#{port}/i
+  end
+
+  test "dns option builds a client with its own resolver settings" do
+    response = TestServer.build_response(200, [{"content-type", "text/plain"}], "resolved")
+    {url, _server} = TestServer.start(response: response)
+
+    req = [url: url, retry: false] |> Req.new() |> CloakedReq.attach(dns: [cache_size: 0, max_ttl: 5])
+
+    assert {:ok, %Req.Response{status: 200, body: "resolved"}} = Req.request(req)
+    assert CloakedReq.flush_dns_cache() == :ok
+  end
+
+  test "ip_family :ipv4 connects to the IPv4 address of an overridden host" do
+    response = TestServer.build_response(200, [{"content-type", "text/plain"}], "ipv4")
+    {url, _server} = TestServer.start(response: response)
+    %URI{port: port} = URI.parse(url)
+
+    req =
+      [url: "http://api.cloaked-req.example:#{port}/", retry: false]
+      |> Req.new()
+      |> CloakedReq.attach(
+        resolve: %{"api.cloaked-req.example:#{port}" => ["::1", "127.0.0.1"]},
+        ip_family: :ipv4,
+        happy_eyeballs_timeout: false
+      )
+
+    assert {:ok, %Req.Response{status: 200, body: "ipv4"}} = Req.request(req)
+  end
+
+  test "ip_family :ipv6 refuses an IPv4 literal host" do
+    req = [url: "http://127.0.0.1:9/", retry: false] |> Req.new() |> CloakedReq.attach(ip_family: :ipv6)
+
+    assert {:error, %AdapterError{} = error} = Req.request(req)
+    assert error.error.type == :transport_error
+    assert error.error.message == "url host is outside the requested IP family"
+  end
+
+  test "pool options build a client with its own pool and connection limit" do
+    response = TestServer.build_response(200, [{"content-type", "text/plain"}], "pooled")
+    {url, _server} = TestServer.start(response: response)
+
+    req =
+      [url: url, retry: false]
+      |> Req.new()
+      |> CloakedReq.attach(
+        max_connections_per_host: 1,
+        pool_idle_timeout: 5_000,
+        pool_max_idle_per_host: 0,
+        http2_keep_alive: [interval: 10_000, while_idle: true]
+      )
+
+    assert {:ok, %Req.Response{status: 200, body: "pooled"}} = Req.request(req)
+  end
+
+  test "evicted clients are rebuilt by the next request" do
+    for text <- ["first", "second"] do
+      response = TestServer.build_response(200, [{"content-type", "text/plain"}], text)
+      {url, _server} = TestServer.start(response: response)
+      req = [url: url, retry: false] |> Req.new() |> CloakedReq.attach(pool_max_idle_per_host: 7)
+
+      assert {:ok, %Req.Response{status: 200, body: ^text}} = Req.request(req)
+      assert CloakedReq.evict_client(req) == :ok
+    end
+  end
+
+  test "preconnect opens a connection to each origin without sending a request" do
+    response = TestServer.build_response(200, [{"content-type", "text/plain"}], "warm")
+    {url, server} = TestServer.start(response: response)
+    origin = String.trim_trailing(url, "/")
+    options = [pool_max_idle_per_host: 7]
+
+    assert {:ok, results} = CloakedReq.preconnect([origin, "ftp://example.com"], options)
+    assert results[origin] == :ok
+    assert {:error, %Error{type: :invalid_request, message: message}} = results["ftp://example.com"]
+    assert message == "origin must be an http or https URL"
+    refute_received {handle, _reply} when is_reference(handle)
+    assert_receive {:test_server_peer, ^server, _peer_ip}, 1_000
+    refute_receive {:test_server_request, ^server, _request}, 100
+
+    # The server accepts a single connection, so the request must reuse it.
+    req = [url: url, retry: false] |> Req.new() |> CloakedReq.attach(options)
+    assert {:ok, %Req.Response{status: 200, body: "warm"}} = Req.request(req)
+    assert TestServer.get_request(server) =~ ~r/^GET \/ HTTP\/1\.1\r\n/
+  end
+
+  test "proxy option forwards plain http requests through the proxy" do
+    response = TestServer.build_response(200, [{"content-type", "text/plain"}], "proxied")
+    {proxy_url, proxy} = TestServer.start(response: response)
+
+    req =
+      [url: "http://example.com/path", retry: false]
+      |> Req.new()
+      |> CloakedReq.attach(proxy: [url: proxy_url, username: "user", password: "secret"])
+
+    assert {:ok, %Req.Response{} = resp} = Req.request(req)
+    assert resp.body == "proxied"
+
+    raw = TestServer.get_request(proxy)
+    assert raw =~ ~r/^GET http:\/\/example\.com\/path HTTP\/1\.1/
+    assert raw =~ ~r/proxy-authorization:\s*Basic #{Base.encode64("user:secret")}/i
+  end
+
   test "response includes url in private metadata" do
     response = TestServer.build_response(200, [{"content-type", "text/plain"}], "ok")
     {url, _server} = TestServer.start(response: response)
@@ -181,4 +605,158 @@ defmodule CloakedReq.E2ETest do
     assert is_binary(resp.private[:cloaked_req_url])
     assert resp.private[:cloaked_req_url] =~ "127.0.0.1"
   end
+
+  test "response includes timing breakdown in private metadata" do
+    response = TestServer.build_response(200, [{"content-type", "text/plain"}], "ok")
+    {url, _server} = TestServer.start(response: response)
+
+    req = [url: url, retry: false] |> Req.new() |> CloakedReq.attach()
+
+    assert {:ok, %Req.Response{} = resp} = Req.request(req)
+    assert %{dns_us: nil, tls_us: nil} = timing = resp.private[:cloaked_req_timing]
+    assert is_integer(timing.connect_us)
+    assert is_integer(timing.body_us)
+    assert timing.total_us >= timing.ttfb_us
+  end
+
+  test "plain http response reports HTTP/1.1 without TLS details" do
+    response = TestServer.build_response(200, [{"content-type", "text/plain"}], "ok")
+    {url, _server} = TestServer.start(response: response)
+
+    req = [url: url, retry: false] |> Req.new() |> CloakedReq.attach()
+
+    assert {:ok, %Req.Response{} = resp} = Req.request(req)
+    assert resp.private[:cloaked_req_http_version] == "HTTP/1.1"
+    assert Map.has_key?(resp.private, :cloaked_req_tls)
+    assert resp.private[:cloaked_req_tls] == nil
+  end
+
+  test "client_certificate authenticates against a server that requires mTLS" do
+    fixture = TestServer.tls_fixture()
+    response = TestServer.build_response(200, [{"content-type", "text/plain"}], "authenticated")
+    ssl = fixture.server_ssl ++ TestServer.mtls_ssl_opts()
+    {url, server} = TestServer.start_tls(response: response, ssl: ssl)
+
+    req =
+      [url: url, retry: false]
+      |> Req.new()
+      |> CloakedReq.attach(
+        insecure_skip_verify: true,
+        client_certificate: [cert: fixture.client_cert, key: fixture.client_key]
+      )
+
+    assert {:ok, %Req.Response{status: 200, body: "authenticated"} = resp} = Req.request(req)
+    assert {:ok, client_der} = TestServer.get_peer_cert(server)
+    assert [{:Certificate, ^client_der, :not_encrypted}] = :public_key.pem_decode(fixture.client_cert)
+    assert %{alpn: nil, version: version, cipher: cipher, session_resumed: false, peer_certificates: [server_der | _]} =
+             resp.private[:cloaked_req_tls]
+
+    assert version in ["TLSv1.2", "TLSv1.3"]
+    assert is_binary(cipher)
+    assert server_der == fixture.server_cert
+  end
+
+  test "server requiring mTLS rejects requests without client_certificate" do
+    fixture = TestServer.tls_fixture()
+    response = TestServer.build_response(200, [], "unreachable")
+    ssl = fixture.server_ssl ++ TestServer.mtls_ssl_opts()
+    {url, server} = TestServer.start_tls(response: response, ssl: ssl)
+
+    req = [url: url, retry: false] |> Req.new() |> CloakedReq.attach(insecure_skip_verify: true)
+
+    assert {:error, %AdapterError{}} = Req.request(req)
+    assert TestServer.get_handshake_error(server)
+  end
+
+  test "ca_certificates trusts a server signed by a private CA" do
+    fixture = TestServer.tls_fixture()
+    response = TestServer.build_response(200, [{"content-type", "text/plain"}], "trusted")
+    {url, _server} = TestServer.start_tls(response: response, ssl: fixture.server_ssl, host: "localhost")
+
+    req =
+      [url: url, retry: false]
+      |> Req.new()
+      |> CloakedReq.attach(ca_certificates: fixture.server_ca, ca_certificates_only: true)
+
+    assert {:ok, %Req.Response{status: 200, body: "trusted"}} = Req.request(req)
+  end
+
+  test "server signed by a private CA is rejected without ca_certificates" do
+    fixture = TestServer.tls_fixture()
+    response = TestServer.build_response(200, [], "unreachable")
+    {url, _server} = TestServer.start_tls(response: response, ssl: fixture.server_ssl, host: "localhost")
+
+    req = [url: url, retry: false] |> Req.new() |> CloakedReq.attach()
+
+    assert {:error, %AdapterError{}} = Req.request(req)
+  end
+
+  test "pinned_public_keys accepts a server presenting the pinned key" do
+    fixture = TestServer.tls_fixture()
+    response = TestServer.build_response(200, [{"content-type", "text/plain"}], "pinned")
+    {url, _server} = TestServer.start_tls(response: response, ssl: fixture.server_ssl)
+    host = URI.parse(url).host
+
+    req =
+      [url: url, retry: false]
+      |> Req.new()
+      |> CloakedReq.attach(insecure_skip_verify: true, pinned_public_keys: %{host => spki_pin(fixture.server_cert)})
+
+    assert {:ok, %Req.Response{status: 200, body: "pinned"}} = Req.request(req)
+  end
+
+  test "pinned_public_keys rejects a server presenting another key" do
+    fixture = TestServer.tls_fixture()
+    response = TestServer.build_response(200, [], "unreachable")
+    {url, server} = TestServer.start_tls(response: response, ssl: fixture.server_ssl)
+    host = URI.parse(url).host
+    pin = Base.encode64(:crypto.hash(:sha256, "other key"))
+
+    req =
+      [url: url, retry: false]
+      |> Req.new()
+      |> CloakedReq.attach(insecure_skip_verify: true, pinned_public_keys: %{host => pin})
+
+    assert {:error, %AdapterError{} = error} = Req.request(req)
+    assert error.error.type == :certificate_pin_error
+    assert error.error.details["presented"] == [spki_pin(fixture.server_cert)]
+    assert TestServer.get_request(server) == ""
+  end
+
+  @tag :tmp_dir
+  test "tls_keylog writes session secrets for the handshake", %{tmp_dir: tmp_dir} do
+    fixture = TestServer.tls_fixture()
+    response = TestServer.build_response(200, [], "logged")
+    {url, _server} = TestServer.start_tls(response: response, ssl: fixture.server_ssl)
+    path = Path.join(tmp_dir, "sslkeys.log")
+
+    req =
+      [url: url, retry: false]
+      |> Req.new()
+      |> CloakedReq.attach(insecure_skip_verify: true, tls_keylog: path)
+
+    assert {:ok, %Req.Response{status: 200, body: "logged"}} = Req.request(req)
+    assert wait_for_file(path) =~ ~r/^(CLIENT_RANDOM|CLIENT_HANDSHAKE_TRAFFIC_SECRET) /m
+  end
+
+  # Key log lines are written by a background thread.
+  defp wait_for_file(path, attempts \\ 50) do
+    case File.read(path) do
+      {:ok, contents} when contents != "" ->
+        contents
+
+      _ when attempts > 0 ->
+        Process.sleep(20)
+        wait_for_file(path, attempts - 1)
+
+      _ ->
+        flunk("#{path} was not written")
+    end
+  end
+
+  defp spki_pin(der) do
+    {:Certificate, tbs, _algorithm, _signature} = :public_key.pkix_decode_cert(der, :plain)
+    {:TBSCertificate, _, _, _, _, _, _, spki, _, _, _} = tbs
+    :SubjectPublicKeyInfo |> :public_key.der_encode(spki) |> then(&:crypto.hash(:sha256, &1)) |> Base.encode64()
+  end
 end
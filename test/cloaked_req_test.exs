@@ -6,6 +6,7 @@ defmodule CloakedReqTest do
   use ExUnit.Case, async: true
 
   alias CloakedReq.Error
+  alias CloakedReq.TestServer
 
   doctest CloakedReq, import: false
   doctest Error, import: false
@@ -28,4 +29,60 @@ defmodule CloakedReqTest do
       [url: "https://example.com"] |> Req.new() |> CloakedReq.attach(unknown: :value)
     end
   end
+
+  test "configure_client_cache/1 validates its options" do
+    for {options, message} <- [
+          {[max_clients: 0], "max_clients must be a positive integer"},
+          {[idle_timeout: -1], "idle_timeout must be a positive integer or :infinity"},
+          {[max_size: 10], "unknown client cache options"}
+        ] do
+      assert {:error, %Error{type: :invalid_request, message: ^message}} = CloakedReq.configure_client_cache(options)
+    end
+  end
+
+  test "evict_client/1 accepts a request or adapter options" do
+    request = [url: "https://example.com"] |> Req.new() |> CloakedReq.attach(impersonate: :chrome_136)
+
+    assert CloakedReq.evict_client(request) == :ok
+    assert CloakedReq.evict_client(impersonate: :chrome_136, local_address: "127.0.0.1") == :ok
+    assert {:error, %Error{type: :invalid_request}} = CloakedReq.evict_client(max_body_size: 0)
+  end
+
+  test "client_stats/0 lists cached clients with redacted proxies and open connections" do
+    response = TestServer.build_response(200, [{"content-type", "text/plain"}], "proxied")
+    {proxy_url, proxy} = TestServer.start(response: response, delay_ms: 200)
+    proxy_options = [url: proxy_url, username: "user", password: "secret"]
+
+    req =
+      [url: "http://example.com/path", retry: false]
+      |> Req.new()
+      |> CloakedReq.attach(proxy: proxy_options, pool_max_idle_per_host: 11)
+
+    task = Task.async(fn -> Req.request(req) end)
+    assert_receive {:test_server_request, ^proxy, _request}, 1_000
+
+    redacted = String.replace(proxy_url, "http://", "http://user:***@")
+    assert [stats] = Enum.filter(CloakedReq.client_stats(), &(&1.proxy == redacted))
+    assert %{impersonate: nil, insecure_skip_verify: false, local_address: nil, interface: nil} = stats
+    assert stats.hosts == %{"http://example.com" => %{connections: 1, in_flight: 1}}
+    assert %DateTime{} = stats.created_at
+    assert DateTime.compare(stats.last_used_at, stats.created_at) in [:gt, :eq]
+
+    assert {:ok, %Req.Response{status: 200}} = Task.await(task)
+  end
+
+  test "preconnect/2 validates origins and options" do
+    assert {:error, %Error{type: :invalid_request, message: "origins must be a list of URL strings"}} =
+             CloakedReq.preconnect([:example])
+
+    assert {:error, %Error{type: :invalid_request, message: "client must be a %CloakedReq.Client{}"}} =
+             CloakedReq.preconnect(["https://example.com"], client: :default)
+
+    assert {:error, %Error{type: :invalid_request}} = CloakedReq.preconnect(["https://example.com"], max_body_size: 0)
+    assert CloakedReq.preconnect([]) == {:ok, %{}}
+  end
+
+  test "clear_clients/0 drops every cached client" do
+    assert CloakedReq.clear_clients() == :ok
+  end
 end
@@ -29,6 +29,114 @@ defmodule CloakedReq.TestServer do
     {"http://#{host}:#{port}/", pid}
   end
 
+  @spec start_hanging(keyword()) :: {String.t(), pid()}
+  def start_hanging(opts \\ []) when is_list(opts) do
+    host = Keyword.get(opts, :host, "127.0.0.1")
+    caller = self()
+
+    {:ok, listen} = :gen_tcp.listen(0, [:binary, active: false, reuseaddr: true])
+    {:ok, port} = :inet.port(listen)
+
+    pid =
+      spawn_link(fn ->
+        {:ok, socket} = :gen_tcp.accept(listen, 5_000)
+        request_data = read_request(socket)
+        send(caller, {:test_server_request, self(), request_data})
+
+        # Never respond; report once the client gives up on the connection.
+        {:error, :closed} = :gen_tcp.recv(socket, 0, 10_000)
+        send(caller, {:test_server_closed, self()})
+        :gen_tcp.close(listen)
+      end)
+
+    {"http://#{host}:#{port}/", pid}
+  end
+
+  @spec start_tls(keyword()) :: {String.t(), pid()}
+  def start_tls(opts) when is_list(opts) do
+    response = Keyword.fetch!(opts, :response)
+    ssl_opts = Keyword.fetch!(opts, :ssl)
+    host = Keyword.get(opts, :host, "127.0.0.1")
+    caller = self()
+    {:ok, _apps} = Application.ensure_all_started(:ssl)
+
+    {:ok, listen} = :ssl.listen(0, [:binary, active: false, reuseaddr: true] ++ ssl_opts)
+    {:ok, {_address, port}} = :ssl.sockname(listen)
+
+    pid =
+      spawn_link(fn ->
+        {:ok, socket} = :ssl.transport_accept(listen, 5_000)
+
+        case :ssl.handshake(socket, 5_000) do
+          {:ok, socket} ->
+            send(caller, {:test_server_peer_cert, self(), :ssl.peercert(socket)})
+            request_data = read_tls_headers(socket, <<>>)
+            send(caller, {:test_server_request, self(), request_data})
+            _ = :ssl.send(socket, response)
+            :ssl.close(socket)
+
+          {:error, reason} ->
+            send(caller, {:test_server_handshake_error, self(), reason})
+        end
+
+        :ssl.close(listen)
+      end)
+
+    {"https://#{host}:#{port}/", pid}
+  end
+
+  # Generates server and client chains with `:public_key.pkix_test_data/1`: `:ssl`
+  # options for a server with a `localhost` certificate, the PEM root that signs
+  # it, and a client certificate and key as PEM. Add `mtls_ssl_opts/0` to the
+  # server options to require client certificates.
+  @spec tls_fixture() :: %{
+          server_ssl: keyword(),
+          server_cert: binary(),
+          server_ca: binary(),
+          client_cert: binary(),
+          client_key: binary()
+        }
+  def tls_fixture do
+    san = {:Extension, {2, 5, 29, 17}, false, [dNSName: ~c"localhost"]}
+
+    %{server_config: server_config, client_config: client_config} =
+      :public_key.pkix_test_data(%{
+        server_chain: %{root: [], intermediates: [], peer: [extensions: [san]]},
+        client_chain: %{root: [], intermediates: [], peer: []}
+      })
+
+    {key_type, key_der} = Keyword.fetch!(client_config, :key)
+
+    %{
+      server_ssl: server_config,
+      server_cert: Keyword.fetch!(server_config, :cert),
+      server_ca: pem_certificates(Keyword.fetch!(client_config, :cacerts)),
+      client_cert: pem_certificates([Keyword.fetch!(client_config, :cert)]),
+      client_key: :public_key.pem_encode([{key_type, key_der, :not_encrypted}])
+    }
+  end
+
+  @spec mtls_ssl_opts() :: keyword()
+  def mtls_ssl_opts, do: [verify: :verify_peer, fail_if_no_peer_cert: true]
+
+  @spec get_peer_cert(pid(), timeout()) :: {:ok, binary()} | {:error, term()}
+  def get_peer_cert(pid, timeout \\ 5_000) do
+    receive do
+      {:test_server_peer_cert, ^pid, result} -> result
+    after
+      timeout -> raise "TestServer: timed out waiting for TLS handshake"
+    end
+  end
+
+  @spec get_handshake_error(pid(), timeout()) :: term()
+  def get_handshake_error(pid, timeout \\ 5_000) do
+    receive do
+      {:test_server_handshake_error, ^pid, reason} -> reason
+    after
+      timeout -> raise "TestServer: timed out waiting for TLS handshake failure"
+    end
+  end
+
   @spec get_request(pid(), timeout()) :: binary()
   def get_request(pid, timeout \\ 5_000) do
     receive do
@@ -38,6 +146,15 @@ defmodule CloakedReq.TestServer do
     end
   end
 
+  @spec await_closed(pid(), timeout()) :: :ok
+  def await_closed(pid, timeout \\ 5_000) do
+    receive do
+      {:test_server_closed, ^pid} -> :ok
+    after
+      timeout -> raise "TestServer: timed out waiting for client to close the connection"
+    end
+  end
+
   @spec get_peer_address(pid(), timeout()) :: :inet.ip_address()
   def get_peer_address(pid, timeout \\ 5_000) do
     receive do
@@ -96,6 +213,25 @@ defmodule CloakedReq.TestServer do
     end
   end
 
+  defp pem_certificates(ders), do: :public_key.pem_encode(for der <- ders, do: {:Certificate, der, :not_encrypted})
+
+  # Returns what arrived so far when the client closes before sending a request.
+  defp read_tls_headers(socket, acc) do
+    case :ssl.recv(socket, 0, 5_000) do
+      {:ok, chunk} ->
+        data = <<acc::binary, chunk::binary>>
+
+        if :binary.match(data, "\r\n\r\n") == :nomatch do
+          read_tls_headers(socket, data)
+        else
+          data
+        end
+
+      {:error, _reason} ->
+        acc
+    end
+  end
+
   defp parse_content_length(headers_part) do
     headers_part
     |> :binary.split("\r\n", [:global])